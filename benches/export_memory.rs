@@ -0,0 +1,107 @@
+//! Peak heap used to answer every user, collected into one body or streamed in chunks.
+//!
+//! `cargo bench --bench export_memory [-- <users>...]`
+//!
+//! Users come from a generator rather than a database, so the numbers isolate
+//! what the service holds: a `Vec<User>` plus its JSON, or the chunks in flight.
+
+use futures::{StreamExt, executor::block_on, stream};
+use ms1::domain::database::User;
+use ms1::handlers::bulk_handler::{BulkFormat, export_stream};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+fn user(uid: usize) -> User {
+    User {
+        uid: uid as i32,
+        name: format!("User number {}", uid),
+        updated_at: Some(time::OffsetDateTime::UNIX_EPOCH),
+        ..Default::default()
+    }
+}
+
+/// Runs `f`, returning the bytes it sent and the peak heap above what was live before.
+fn measure(f: impl FnOnce() -> usize) -> (usize, usize, f64) {
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let started = Instant::now();
+    let sent = f();
+    let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+    (sent, PEAK.load(Ordering::Relaxed) - baseline, elapsed)
+}
+
+/// What `GET /users` does: every user in a `Vec`, then one JSON body.
+fn collected(count: usize) -> usize {
+    let users: Vec<User> = (1..=count).map(user).collect();
+    serde_json::to_vec(&users).unwrap().len()
+}
+
+/// What `GET /users/export` does: chunks encoded as users arrive, each dropped once sent.
+fn streamed(count: usize, format: BulkFormat) -> usize {
+    let users = stream::iter((1..=count).map(|uid| Ok(user(uid))));
+    block_on(
+        export_stream(format, users)
+            .fold(0, |sent, chunk| async move { sent + chunk.unwrap().len() }),
+    )
+}
+
+fn main() {
+    let counts: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let counts = if counts.is_empty() {
+        vec![1_000, 10_000, 100_000, 1_000_000]
+    } else {
+        counts
+    };
+
+    println!(
+        "{:>10} {:>16} {:>14} {:>14} {:>10}",
+        "users", "strategy", "body bytes", "peak heap", "ms"
+    );
+    for count in counts {
+        let runs = [
+            ("collected json", measure(|| collected(count))),
+            (
+                "streamed json",
+                measure(|| streamed(count, BulkFormat::Json)),
+            ),
+            (
+                "streamed ndjson",
+                measure(|| streamed(count, BulkFormat::Ndjson)),
+            ),
+        ];
+        for (strategy, (sent, peak, elapsed)) in runs {
+            println!(
+                "{:>10} {:>16} {:>14} {:>14} {:>10.1}",
+                count, strategy, sent, peak, elapsed
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+ALTER TABLE `TESTMS`.`t_Users`
+    ADD COLUMN `UPDATED_AT` timestamp(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3) ON UPDATE CURRENT_TIMESTAMP(3);
+
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_USERS()
+BEGIN
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT
+    FROM t_Users U;
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_User(
+    IN prmUid int(11)
+)
+BEGIN
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT
+    FROM t_Users U
+    WHERE U.UID = prmUid;
+END //
+
+# prmExpectedUpdatedAt is the version the client last saw, NULL updates unconditionally.
+# Returns the updated row, or nothing when the user is missing or changed since.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Update_User(
+    IN prmUid int(11),
+    IN prmName varchar(15),
+    IN prmExpectedUpdatedAt timestamp(3)
+)
+BEGIN
+    START TRANSACTION;
+    UPDATE t_Users
+    SET NAME = prmName,
+        UPDATED_AT = CURRENT_TIMESTAMP(3)
+    WHERE UID = prmUid
+      AND (prmExpectedUpdatedAt IS NULL OR UPDATED_AT = prmExpectedUpdatedAt);
+
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+    COMMIT;
+END //
+
+# Returns the number of deleted rows, 0 when the user is missing or changed since.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Delete_User(
+    IN prmUid int(11),
+    IN prmExpectedUpdatedAt timestamp(3)
+)
+BEGIN
+    START TRANSACTION;
+    DELETE FROM t_Users
+    WHERE UID = prmUid
+      AND (prmExpectedUpdatedAt IS NULL OR UPDATED_AT = prmExpectedUpdatedAt);
+    SELECT ROW_COUNT() AS DELETED;
+    COMMIT;
+END //
+
+DELIMITER ;
@@ -0,0 +1,46 @@
+# The writes no longer open and commit their own transaction, which would implicitly commit
+# a transaction opened by the caller. Outside of one, each statement autocommits.
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Insert_User(
+    IN prmName varchar(15)
+)
+BEGIN
+    INSERT INTO t_Users (NAME)
+    VALUES (prmName);
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Update_User(
+    IN prmUid int(11),
+    IN prmName varchar(15),
+    IN prmExpectedUpdatedAt timestamp(3)
+)
+BEGIN
+    UPDATE t_Users
+    SET NAME = prmName,
+        UPDATED_AT = CURRENT_TIMESTAMP(3)
+    WHERE UID = prmUid
+      AND (prmExpectedUpdatedAt IS NULL OR UPDATED_AT = prmExpectedUpdatedAt);
+
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Delete_User(
+    IN prmUid int(11),
+    IN prmExpectedUpdatedAt timestamp(3)
+)
+BEGIN
+    DELETE FROM t_Users
+    WHERE UID = prmUid
+      AND (prmExpectedUpdatedAt IS NULL OR UPDATED_AT = prmExpectedUpdatedAt);
+    SELECT ROW_COUNT() AS DELETED;
+END //
+
+DELIMITER ;
@@ -0,0 +1,19 @@
+# Returns the inserted row, so callers learn the new UID.
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Insert_User(
+    IN prmName varchar(15)
+)
+BEGIN
+    INSERT INTO t_Users (NAME)
+    VALUES (prmName);
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT
+    FROM t_Users U
+    WHERE U.UID = LAST_INSERT_ID();
+END //
+
+DELIMITER ;
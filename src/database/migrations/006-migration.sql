@@ -0,0 +1,35 @@
+# Batched imports: every row of one INSERT carries the key of its batch, which is how the batch
+# is read back. Its ids are not guaranteed consecutive (innodb_autoinc_lock_mode=2,
+# auto_increment_increment), only increasing within the statement.
+ALTER TABLE `TESTMS`.`t_Users`
+    ADD COLUMN `IMPORT_BATCH` char(36) NULL DEFAULT NULL,
+    ADD KEY `IX_Users_IMPORT_BATCH` (`IMPORT_BATCH`);
+
+DELIMITER //
+
+# Inserts the names of the prmNames JSON array in order, returns the rows in the same order.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Insert_Users(
+    IN prmNames json
+)
+BEGIN
+    DECLARE vBatch char(36) DEFAULT UUID();
+
+    INSERT INTO t_Users (NAME, IMPORT_BATCH)
+    SELECT J.NAME, vBatch
+    FROM JSON_TABLE(prmNames, '$[*]' COLUMNS (
+        POS FOR ORDINALITY,
+        NAME varchar(15) PATH '$'
+    )) J
+    ORDER BY J.POS;
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.IMPORT_BATCH = vBatch
+    ORDER BY U.UID;
+END //
+
+DELIMITER ;
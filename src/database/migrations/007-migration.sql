@@ -0,0 +1,15 @@
+# Stamp of the user list, so that GET /users can answer 304 or set its validators
+# before streaming the users. Any write changes one of its columns.
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_Users_Stamp()
+BEGIN
+    SELECT
+        COUNT(*) AS USERS,
+        MAX(U.UID) AS MAX_UID,
+        CAST(COALESCE(SUM(U.VERSION), 0) AS SIGNED) AS VERSIONS,
+        MAX(U.UPDATED_AT) AS UPDATED_AT
+    FROM t_Users U;
+END //
+
+DELIMITER ;
@@ -0,0 +1,180 @@
+# Audit columns and soft deletes: DELETE only stamps DELETED_AT, and deleted users are left
+# out of every procedure but sp_Return_USERS with prmIncludeDeleted and sp_Restore_User.
+ALTER TABLE `TESTMS`.`t_Users`
+    ADD COLUMN `CREATED_AT` timestamp(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    ADD COLUMN `DELETED_AT` timestamp(3) NULL DEFAULT NULL,
+    ADD COLUMN `CREATED_BY` varchar(255) NULL DEFAULT NULL;
+
+# Existing users were created no later than their last update. Setting UPDATED_AT to itself
+# keeps its ON UPDATE from bumping it.
+UPDATE `TESTMS`.`t_Users`
+SET CREATED_AT = UPDATED_AT,
+    UPDATED_AT = UPDATED_AT;
+
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_USERS(
+    IN prmIncludeDeleted boolean
+)
+BEGIN
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE prmIncludeDeleted OR U.DELETED_AT IS NULL;
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_User(
+    IN prmUid int(11)
+)
+BEGIN
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.UID = prmUid
+      AND U.DELETED_AT IS NULL;
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Insert_User(
+    IN prmName varchar(15),
+    IN prmCreatedBy varchar(255)
+)
+BEGIN
+    INSERT INTO t_Users (NAME, CREATED_BY)
+    VALUES (prmName, prmCreatedBy);
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.UID = LAST_INSERT_ID();
+END //
+
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Update_User(
+    IN prmUid int(11),
+    IN prmName varchar(15),
+    IN prmExpectedVersion int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET NAME = prmName,
+        UPDATED_AT = CURRENT_TIMESTAMP(3),
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NULL
+      AND (prmExpectedVersion IS NULL OR VERSION = prmExpectedVersion);
+
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT,
+            U.CREATED_AT,
+            U.DELETED_AT,
+            U.CREATED_BY,
+            U.VERSION
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+END //
+
+# Returns the number of deleted rows, 0 when the user is missing, already deleted or changed since.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Delete_User(
+    IN prmUid int(11),
+    IN prmExpectedVersion int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET DELETED_AT = CURRENT_TIMESTAMP(3),
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NULL
+      AND (prmExpectedVersion IS NULL OR VERSION = prmExpectedVersion);
+    SELECT ROW_COUNT() AS DELETED;
+END //
+
+# Returns the restored row, or nothing when the user is missing or not deleted.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Restore_User(
+    IN prmUid int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET DELETED_AT = NULL,
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NOT NULL;
+
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT,
+            U.CREATED_AT,
+            U.DELETED_AT,
+            U.CREATED_BY,
+            U.VERSION
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+END //
+
+# Inserts the names of the prmNames JSON array in order, returns the rows in the same order.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Insert_Users(
+    IN prmNames json,
+    IN prmCreatedBy varchar(255)
+)
+BEGIN
+    DECLARE vBatch char(36) DEFAULT UUID();
+
+    INSERT INTO t_Users (NAME, CREATED_BY, IMPORT_BATCH)
+    SELECT J.NAME, prmCreatedBy, vBatch
+    FROM JSON_TABLE(prmNames, '$[*]' COLUMNS (
+        POS FOR ORDINALITY,
+        NAME varchar(15) PATH '$'
+    )) J
+    ORDER BY J.POS;
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.IMPORT_BATCH = vBatch
+    ORDER BY U.UID;
+END //
+
+# Stamp of the user list, deleted users left out unless prmIncludeDeleted.
+CREATE OR REPLACE PROCEDURE TESTMS.sp_Return_Users_Stamp(
+    IN prmIncludeDeleted boolean
+)
+BEGIN
+    SELECT
+        COUNT(*) AS USERS,
+        MAX(U.UID) AS MAX_UID,
+        CAST(COALESCE(SUM(U.VERSION), 0) AS SIGNED) AS VERSIONS,
+        MAX(U.UPDATED_AT) AS UPDATED_AT
+    FROM t_Users U
+    WHERE prmIncludeDeleted OR U.DELETED_AT IS NULL;
+END //
+
+DELIMITER ;
@@ -1 +1,7 @@
 pub mod connection;
+pub mod pool;
+pub mod redis_connection;
+pub mod repository;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,167 @@
+use crate::database::repository::{StoredProcedures, UserRepository};
+use crate::utils::config_utils::env_duration;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::{KeyValue, global};
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// How long queries waited for a connection, kept locally and exported as
+/// `db.pool.acquire_duration` (ms) and `db.pool.acquire_errors`.
+pub struct AcquireMetrics {
+    name: String,
+    acquires: AtomicU64,
+    errors: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+    duration: Histogram<f64>,
+    error_counter: Counter<u64>,
+}
+
+impl AcquireMetrics {
+    pub fn new(name: impl Into<String>) -> Self {
+        let meter = global::meter("excelsior");
+        AcquireMetrics {
+            name: name.into(),
+            acquires: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+            duration: meter
+                .f64_histogram("db.pool.acquire_duration")
+                .with_unit("ms")
+                .build(),
+            error_counter: meter.u64_counter("db.pool.acquire_errors").build(),
+        }
+    }
+
+    fn record(&self, started: Instant, ok: bool) {
+        let micros = started.elapsed().as_micros() as u64;
+        let attributes = [KeyValue::new("pool", self.name.clone())];
+        self.duration.record(micros as f64 / 1000.0, &attributes);
+        if ok {
+            self.acquires.fetch_add(1, Ordering::Relaxed);
+            self.total_micros.fetch_add(micros, Ordering::Relaxed);
+            self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        } else {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            self.error_counter.add(1, &attributes);
+        }
+    }
+}
+
+/// Size and acquire latency of a pool.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct PoolStats {
+    pub name: String,
+    /// Open connections, idle or in use.
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// Successful acquires since startup.
+    pub acquires: u64,
+    /// Acquires that failed, mostly timeouts of an exhausted pool.
+    pub acquire_errors: u64,
+    pub acquire_avg_ms: f64,
+    pub acquire_max_ms: f64,
+    /// False for a replica skipped after failing to connect.
+    pub healthy: bool,
+}
+
+/// How long `stream_users` waits for a slow consumer before giving its connection back.
+pub const STREAM_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `DB_STREAM_SEND_TIMEOUT`, `STREAM_SEND_TIMEOUT` without it.
+pub fn stream_send_timeout_from_env() -> Duration {
+    env_duration("DB_STREAM_SEND_TIMEOUT").unwrap_or(STREAM_SEND_TIMEOUT)
+}
+
+/// A MySQL pool whose connection acquires are timed, queried through `repository`.
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool<MySql>,
+    metrics: Arc<AcquireMetrics>,
+    repository: Arc<dyn UserRepository>,
+    stream_send_timeout: Duration,
+}
+
+impl Database {
+    pub fn new(name: impl Into<String>, pool: Pool<MySql>) -> Self {
+        Database {
+            pool,
+            metrics: Arc::new(AcquireMetrics::new(name)),
+            repository: Arc::new(StoredProcedures),
+            stream_send_timeout: STREAM_SEND_TIMEOUT,
+        }
+    }
+
+    pub fn with_repository(mut self, repository: Arc<dyn UserRepository>) -> Self {
+        self.repository = repository;
+        self
+    }
+
+    pub fn with_stream_send_timeout(mut self, timeout: Duration) -> Self {
+        self.stream_send_timeout = timeout;
+        self
+    }
+
+    pub fn stream_send_timeout(&self) -> Duration {
+        self.stream_send_timeout
+    }
+
+    pub fn repository(&self) -> &Arc<dyn UserRepository> {
+        &self.repository
+    }
+
+    pub fn pool(&self) -> &Pool<MySql> {
+        &self.pool
+    }
+
+    pub async fn acquire(&self) -> Result<PoolConnection<MySql>, sqlx::Error> {
+        let started = Instant::now();
+        let connection = self.pool.acquire().await;
+        self.metrics.record(started, connection.is_ok());
+        connection
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let options = self.pool.options();
+        let size = self.pool.size();
+        let idle = self.pool.num_idle();
+        let acquires = self.metrics.acquires.load(Ordering::Relaxed);
+        let total_micros = self.metrics.total_micros.load(Ordering::Relaxed);
+        PoolStats {
+            name: self.metrics.name.clone(),
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            min_connections: options.get_min_connections(),
+            max_connections: options.get_max_connections(),
+            acquires,
+            acquire_errors: self.metrics.errors.load(Ordering::Relaxed),
+            acquire_avg_ms: if acquires == 0 {
+                0.0
+            } else {
+                total_micros as f64 / acquires as f64 / 1000.0
+            },
+            acquire_max_ms: self.metrics.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            healthy: true,
+        }
+    }
+}
+
+impl From<Pool<MySql>> for Database {
+    fn from(pool: Pool<MySql>) -> Self {
+        Database::new("primary", pool)
+    }
+}
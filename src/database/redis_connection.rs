@@ -0,0 +1,83 @@
+use crate::utils::config_utils::env_duration;
+use anyhow::Result;
+use redis::Client;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Redis client connecting lazily on first use, so the service can start before Redis is reachable.
+/// Clones share the same multiplexed connection.
+#[derive(Clone)]
+pub struct RedisConnection {
+    client: Client,
+    manager: Arc<OnceCell<ConnectionManager>>,
+    /// Bound on connecting and on every command, Redis sits on the request path.
+    timeout: Duration,
+}
+
+impl RedisConnection {
+    pub fn open(url: &str) -> Result<Self> {
+        Ok(RedisConnection {
+            client: Client::open(url)?,
+            manager: Arc::new(OnceCell::new()),
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builds the connection from `REDIS_URL`, or from `REDIS_HOST`, `REDIS_PORT` and `REDIS_PASSWORD`.
+    /// Returns `None` when neither `REDIS_URL` nor `REDIS_HOST` is set.
+    /// `REDIS_TIMEOUT` bounds connecting and each command, 1s by default.
+    pub fn from_env() -> Option<Self> {
+        let url = match std::env::var("REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                let host = std::env::var("REDIS_HOST").ok()?;
+                let port = std::env::var("REDIS_PORT").unwrap_or_else(|_| "6379".to_string());
+                match std::env::var("REDIS_PASSWORD") {
+                    Ok(password) => {
+                        format!("redis://:{}@{}:{}/", percent_encode(&password), host, port)
+                    }
+                    Err(_) => format!("redis://{}:{}/", host, port),
+                }
+            }
+        };
+        let connection =
+            RedisConnection::open(&url).expect("REDIS_URL must be a valid redis:// url.");
+        Some(match env_duration("REDIS_TIMEOUT") {
+            Some(timeout) => connection.with_timeout(timeout),
+            None => connection,
+        })
+    }
+
+    pub async fn connection(&self) -> Result<ConnectionManager> {
+        let manager = self
+            .manager
+            .get_or_try_init(|| {
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(self.timeout)
+                    .set_response_timeout(self.timeout)
+                    .set_number_of_retries(1);
+                ConnectionManager::new_with_config(self.client.clone(), config)
+            })
+            .await?;
+        Ok(manager.clone())
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
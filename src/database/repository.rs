@@ -0,0 +1,435 @@
+use crate::domain::database::{User, UsersStamp};
+use anyhow::{Result, anyhow};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use sqlx::{MySql, MySqlConnection, QueryBuilder, Row, query, query_as, query_scalar};
+use std::sync::Arc;
+
+/// The SQL behind each user operation, run on a connection or inside a transaction.
+/// Deletes are soft: deleted users keep their row, hidden from everything but
+/// `get_users(.., true)` and `restore_user`.
+#[async_trait::async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get_users(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<Vec<User>>;
+    /// Same users as `get_users`, decoded as they are read.
+    fn stream_users<'c>(
+        &'c self,
+        connection: &'c mut MySqlConnection,
+        include_deleted: bool,
+    ) -> BoxStream<'c, Result<User>>;
+    /// Stamp of the users `get_users` lists, computed by the database.
+    async fn users_stamp(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<UsersStamp>;
+    /// Returns the inserted user.
+    async fn create_user(
+        &self,
+        connection: &mut MySqlConnection,
+        name: String,
+        created_by: Option<String>,
+    ) -> Result<User>;
+    /// Inserts every name in a single statement, returns the users in the order of `names`.
+    async fn create_users(
+        &self,
+        connection: &mut MySqlConnection,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>>;
+    async fn get_user(&self, connection: &mut MySqlConnection, uid: i32) -> Result<Option<User>>;
+    async fn update_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>>;
+    async fn delete_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        expected_version: Option<i32>,
+    ) -> Result<bool>;
+    /// Undeletes the user, `None` when it does not exist or is not deleted.
+    async fn restore_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+    ) -> Result<Option<User>>;
+}
+
+/// Calls the `sp_*_User` procedures of the migrations.
+pub struct StoredProcedures;
+
+fn user_from_row(row: sqlx::mysql::MySqlRow) -> User {
+    User {
+        uid: row.get(0),
+        name: row.get(1),
+        updated_at: row.get(2),
+        created_at: row.get(3),
+        deleted_at: row.get(4),
+        created_by: row.get(5),
+        version: row.get(6),
+    }
+}
+
+/// The users of an import batch, as long as all `count` of them were read back.
+fn read_back(users: Vec<User>, count: usize) -> Result<Vec<User>> {
+    if users.len() != count {
+        return Err(anyhow!(
+            "inserted {} users but read back {}",
+            count,
+            users.len()
+        ));
+    }
+    Ok(users)
+}
+
+#[async_trait::async_trait]
+impl UserRepository for StoredProcedures {
+    async fn get_users(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<Vec<User>> {
+        let users = query("CALL sp_Return_USERS(?);")
+            .bind(include_deleted)
+            .map(user_from_row)
+            .fetch_all(connection)
+            .await?;
+        Ok(users)
+    }
+
+    fn stream_users<'c>(
+        &'c self,
+        connection: &'c mut MySqlConnection,
+        include_deleted: bool,
+    ) -> BoxStream<'c, Result<User>> {
+        query("CALL sp_Return_USERS(?);")
+            .bind(include_deleted)
+            .map(user_from_row)
+            .fetch(connection)
+            .map_err(Into::into)
+            .boxed()
+    }
+
+    async fn users_stamp(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<UsersStamp> {
+        let stamp = query("CALL sp_Return_Users_Stamp(?);")
+            .bind(include_deleted)
+            .map(|row: sqlx::mysql::MySqlRow| UsersStamp {
+                count: row.get(0),
+                max_uid: row.get(1),
+                versions: row.get(2),
+                updated_at: row.get(3),
+            })
+            .fetch_one(connection)
+            .await?;
+        Ok(stamp)
+    }
+
+    async fn create_user(
+        &self,
+        connection: &mut MySqlConnection,
+        name: String,
+        created_by: Option<String>,
+    ) -> Result<User> {
+        let user = query("CALL sp_Insert_User(?, ?);")
+            .bind(name)
+            .bind(created_by)
+            .map(user_from_row)
+            .fetch_one(connection)
+            .await?;
+        Ok(user)
+    }
+
+    async fn create_users(
+        &self,
+        connection: &mut MySqlConnection,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        let count = names.len();
+        let users = query("CALL sp_Insert_Users(?, ?);")
+            .bind(serde_json::to_string(&names)?)
+            .bind(created_by)
+            .map(user_from_row)
+            .fetch_all(connection)
+            .await?;
+        read_back(users, count)
+    }
+
+    async fn get_user(&self, connection: &mut MySqlConnection, uid: i32) -> Result<Option<User>> {
+        let user = query("CALL sp_Return_User(?);")
+            .bind(uid)
+            .map(user_from_row)
+            .fetch_optional(connection)
+            .await?;
+        Ok(user)
+    }
+
+    async fn update_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        let user = query("CALL sp_Update_User(?, ?, ?);")
+            .bind(uid)
+            .bind(name)
+            .bind(expected_version)
+            .map(user_from_row)
+            .fetch_optional(connection)
+            .await?;
+        Ok(user)
+    }
+
+    async fn delete_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        expected_version: Option<i32>,
+    ) -> Result<bool> {
+        let deleted: i64 = query("CALL sp_Delete_User(?, ?);")
+            .bind(uid)
+            .bind(expected_version)
+            .map(|row: sqlx::mysql::MySqlRow| row.get(0))
+            .fetch_one(connection)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    async fn restore_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+    ) -> Result<Option<User>> {
+        let user = query("CALL sp_Restore_User(?);")
+            .bind(uid)
+            .map(user_from_row)
+            .fetch_optional(connection)
+            .await?;
+        Ok(user)
+    }
+}
+
+/// Plain statements on `t_Users`, for schemas without the procedures.
+/// Checked against the schema at compile time, from the `.sqlx` data offline
+/// (`cargo sqlx prepare` after changing a statement or a migration). Columns are aliased
+/// to the `User` fields, `?` marking the ones read as `Option` although `NOT NULL`.
+pub struct PlainQueries;
+
+#[async_trait::async_trait]
+impl UserRepository for PlainQueries {
+    async fn get_users(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<Vec<User>> {
+        let users = query_as!(
+            User,
+            "SELECT UID AS uid, NAME AS name, UPDATED_AT AS `updated_at?`, \
+             CREATED_AT AS `created_at?`, DELETED_AT AS deleted_at, CREATED_BY AS created_by, \
+             VERSION AS version FROM t_Users WHERE ? OR DELETED_AT IS NULL",
+            include_deleted
+        )
+        .fetch_all(connection)
+        .await?;
+        Ok(users)
+    }
+
+    fn stream_users<'c>(
+        &'c self,
+        connection: &'c mut MySqlConnection,
+        include_deleted: bool,
+    ) -> BoxStream<'c, Result<User>> {
+        query_as!(
+            User,
+            "SELECT UID AS uid, NAME AS name, UPDATED_AT AS `updated_at?`, \
+             CREATED_AT AS `created_at?`, DELETED_AT AS deleted_at, CREATED_BY AS created_by, \
+             VERSION AS version FROM t_Users WHERE ? OR DELETED_AT IS NULL",
+            include_deleted
+        )
+        .fetch(connection)
+        .map_err(Into::into)
+        .boxed()
+    }
+
+    async fn users_stamp(
+        &self,
+        connection: &mut MySqlConnection,
+        include_deleted: bool,
+    ) -> Result<UsersStamp> {
+        let stamp = query_as!(
+            UsersStamp,
+            "SELECT COUNT(*) AS `count!`, MAX(UID) AS max_uid, \
+             CAST(COALESCE(SUM(VERSION), 0) AS SIGNED) AS `versions!`, \
+             MAX(UPDATED_AT) AS updated_at FROM t_Users WHERE ? OR DELETED_AT IS NULL",
+            include_deleted
+        )
+        .fetch_one(connection)
+        .await?;
+        Ok(stamp)
+    }
+
+    async fn create_user(
+        &self,
+        connection: &mut MySqlConnection,
+        name: String,
+        created_by: Option<String>,
+    ) -> Result<User> {
+        let uid = query!(
+            "INSERT INTO t_Users (NAME, CREATED_BY) VALUES (?, ?)",
+            name,
+            created_by
+        )
+        .execute(&mut *connection)
+        .await?
+        .last_insert_id();
+        let uid = i32::try_from(uid)?;
+        self.get_user(connection, uid)
+            .await?
+            .ok_or_else(|| anyhow!("inserted user {} not found", uid))
+    }
+
+    /// A single multi-row `INSERT` tagged with a fresh `IMPORT_BATCH` key, read back by it.
+    async fn create_users(
+        &self,
+        connection: &mut MySqlConnection,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+        let count = names.len();
+        let batch = query_scalar!("SELECT UUID() AS `batch!`")
+            .fetch_one(&mut *connection)
+            .await?;
+        // As many rows as names, which the macros cannot check.
+        QueryBuilder::<MySql>::new("INSERT INTO t_Users (NAME, CREATED_BY, IMPORT_BATCH) ")
+            .push_values(names, |mut row, name| {
+                row.push_bind(name)
+                    .push_bind(created_by.clone())
+                    .push_bind(batch.clone());
+            })
+            .build()
+            .execute(&mut *connection)
+            .await?;
+        let users = query_as!(
+            User,
+            "SELECT UID AS uid, NAME AS name, UPDATED_AT AS `updated_at?`, \
+             CREATED_AT AS `created_at?`, DELETED_AT AS deleted_at, CREATED_BY AS created_by, \
+             VERSION AS version FROM t_Users WHERE IMPORT_BATCH = ? ORDER BY UID",
+            batch
+        )
+        .fetch_all(connection)
+        .await?;
+        read_back(users, count)
+    }
+
+    async fn get_user(&self, connection: &mut MySqlConnection, uid: i32) -> Result<Option<User>> {
+        let user = query_as!(
+            User,
+            "SELECT UID AS uid, NAME AS name, UPDATED_AT AS `updated_at?`, \
+             CREATED_AT AS `created_at?`, DELETED_AT AS deleted_at, CREATED_BY AS created_by, \
+             VERSION AS version FROM t_Users WHERE UID = ? AND DELETED_AT IS NULL",
+            uid
+        )
+        .fetch_optional(connection)
+        .await?;
+        Ok(user)
+    }
+
+    async fn update_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        let updated = query!(
+            "UPDATE t_Users SET NAME = ?, UPDATED_AT = CURRENT_TIMESTAMP(3), VERSION = VERSION + 1 \
+             WHERE UID = ? AND DELETED_AT IS NULL AND (? IS NULL OR VERSION = ?)",
+            name,
+            uid,
+            expected_version,
+            expected_version
+        )
+        .execute(&mut *connection)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.get_user(connection, uid).await
+    }
+
+    async fn delete_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+        expected_version: Option<i32>,
+    ) -> Result<bool> {
+        let deleted = query!(
+            "UPDATE t_Users SET DELETED_AT = CURRENT_TIMESTAMP(3), VERSION = VERSION + 1 \
+             WHERE UID = ? AND DELETED_AT IS NULL AND (? IS NULL OR VERSION = ?)",
+            uid,
+            expected_version,
+            expected_version
+        )
+        .execute(connection)
+        .await?
+        .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    async fn restore_user(
+        &self,
+        connection: &mut MySqlConnection,
+        uid: i32,
+    ) -> Result<Option<User>> {
+        let restored = query!(
+            "UPDATE t_Users SET DELETED_AT = NULL, VERSION = VERSION + 1 \
+             WHERE UID = ? AND DELETED_AT IS NOT NULL",
+            uid
+        )
+        .execute(&mut *connection)
+        .await?
+        .rows_affected();
+        if restored == 0 {
+            return Ok(None);
+        }
+        self.get_user(connection, uid).await
+    }
+}
+
+/// `procedures` (the default) or `queries`, as in `DB_REPOSITORY`.
+pub fn repository(kind: &str) -> Result<Arc<dyn UserRepository>> {
+    match kind.trim() {
+        "procedures" => Ok(Arc::new(StoredProcedures)),
+        "queries" => Ok(Arc::new(PlainQueries)),
+        other => Err(anyhow!(
+            "user repository must be procedures or queries, got {}",
+            other
+        )),
+    }
+}
+
+/// Reads `DB_REPOSITORY`, stored procedures without it.
+pub fn repository_from_env() -> Arc<dyn UserRepository> {
+    let kind = std::env::var("DB_REPOSITORY").unwrap_or_else(|_| "procedures".to_string());
+    repository(&kind).unwrap_or_else(|err| panic!("DB_REPOSITORY is invalid: {:#}", err))
+}
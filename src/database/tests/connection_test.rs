@@ -0,0 +1,81 @@
+use crate::database::connection::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+fn retry(attempts: u32) -> RetrySettings {
+    RetrySettings {
+        attempts,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(300),
+    }
+}
+
+#[test]
+fn test_backoff_doubles_up_to_the_max() {
+    let retry = retry(10);
+    let backoffs: Vec<_> = (1..=5).map(|attempt| retry.backoff(attempt)).collect();
+    assert_eq!(
+        backoffs,
+        [100, 200, 300, 300, 300].map(Duration::from_millis)
+    );
+    assert_eq!(retry.backoff(u32::MAX), Duration::from_millis(300));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_retry_until_success() {
+    let calls = Arc::new(AtomicU32::new(0));
+    let started = Instant::now();
+    let result: Result<u32, String> = retry_with_backoff("test", &retry(5), || {
+        let calls = calls.clone();
+        async move {
+            match calls.fetch_add(1, Ordering::SeqCst) + 1 {
+                call if call < 3 => Err(format!("down #{}", call)),
+                call => Ok(call),
+            }
+        }
+    })
+    .await;
+    assert_eq!(result, Ok(3));
+    assert_eq!(started.elapsed(), Duration::from_millis(300));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_retry_gives_up_with_the_last_error() {
+    let calls = Arc::new(AtomicU32::new(0));
+    let result: Result<(), String> = retry_with_backoff("test", &retry(3), || {
+        let calls = calls.clone();
+        async move {
+            Err(format!(
+                "down #{}",
+                calls.fetch_add(1, Ordering::SeqCst) + 1
+            ))
+        }
+    })
+    .await;
+    assert_eq!(result, Err("down #3".to_string()));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_redact_url() {
+    assert_eq!(
+        redact_url("mysql://root:s3cr:et@db:3306/app"),
+        "mysql://root:***@db:3306/app"
+    );
+    assert_eq!(redact_url("mysql://db:3306/app"), "mysql://db:3306/app");
+}
+
+#[test]
+fn test_pool_settings_defaults() {
+    let settings = PoolSettings::from_env("EXCELSIOR_TEST_UNSET_POOL");
+    assert_eq!(settings, PoolSettings::default());
+    assert_eq!(settings.max_connections, 5);
+    assert_eq!(settings.acquire_timeout, Duration::from_secs(5));
+
+    let options = settings.options();
+    assert_eq!(options.get_max_connections(), 5);
+    assert_eq!(options.get_min_connections(), 2);
+    assert_eq!(options.get_idle_timeout(), Some(Duration::from_secs(300)));
+}
@@ -0,0 +1,3 @@
+mod connection_test;
+mod pool_test;
+mod repository_test;
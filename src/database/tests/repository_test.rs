@@ -0,0 +1,9 @@
+use crate::database::repository::repository;
+
+#[test]
+fn test_repository_kinds() {
+    assert!(repository("procedures").is_ok());
+    assert!(repository(" queries ").is_ok());
+    let err = repository("orm").err().unwrap();
+    assert!(err.to_string().contains("procedures or queries"));
+}
@@ -1,13 +1,98 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
-#[derive(Deserialize, Debug)]
+/// Rejects names made only of whitespace.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be blank".into()));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
 pub struct NewUser {
+    /// Bounded by the `NAME varchar(15)` column.
+    #[validate(
+        length(min = 1, max = 15, message = "must be 1 to 15 characters"),
+        custom(function = "not_blank")
+    )]
+    #[schema(min_length = 1, max_length = 15)]
     pub name: String,
 }
 
-#[derive(Serialize, Deserialize, FromRow, Clone)]
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct UserId {
+    #[param(minimum = 1)]
+    #[validate(range(min = 1, message = "must be a positive id"))]
+    pub uid: i32,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Default, ToSchema)]
 pub struct User {
     pub uid: i32,
     pub name: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub updated_at: Option<OffsetDateTime>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub created_at: Option<OffsetDateTime>,
+    /// Set while the user is soft-deleted, until it is restored.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub deleted_at: Option<OffsetDateTime>,
+    /// Client certificate name of the creator, or `admin` for the admin token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    /// Bumped by every write, what `If-Match` updates and deletes are checked against.
+    #[serde(default)]
+    pub version: i32,
+}
+
+/// Query of `GET /users`.
+#[derive(Deserialize, Debug, Default, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsers {
+    /// Lists soft-deleted users too, admin token required.
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+/// Summary of a user list that changes with every write to it: a create raises the count and
+/// the highest id, an update or a delete the sum of versions. Validates a list without reading it.
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug, Default, PartialEq)]
+pub struct UsersStamp {
+    pub count: i64,
+    pub max_uid: Option<i32>,
+    pub versions: i64,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub updated_at: Option<OffsetDateTime>,
+}
+
+impl UsersStamp {
+    /// Stamp of users already read.
+    pub fn of_users(users: &[User]) -> Self {
+        UsersStamp {
+            count: users.len() as i64,
+            max_uid: users.iter().map(|user| user.uid).max(),
+            versions: users.iter().map(|user| i64::from(user.version)).sum(),
+            updated_at: users.iter().filter_map(|user| user.updated_at).max(),
+        }
+    }
 }
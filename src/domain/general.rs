@@ -1,21 +1,33 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
-#[derive(Serialize, Deserialize, FromRow, Debug, Default)]
+#[derive(Serialize, Deserialize, FromRow, Debug, Default, Validate, ToSchema)]
 pub struct Message {
     pub code: i32,
+    #[schema(min_length = 1, max_length = 1000)]
+    #[validate(length(min = 1, max = 1000, message = "must be 1 to 1000 characters"))]
     pub message_text: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct Params {
     pub param_1: u32,
+    #[param(min_length = 1, max_length = 100)]
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
     pub param_2: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FilterParams {
+    #[param(max_length = 50)]
+    #[validate(length(max = 50, message = "must be at most 50 characters"))]
     pub name: Option<String>,
+    #[param(maximum = 150)]
+    #[validate(range(max = 150, message = "must be at most 150"))]
     pub age: Option<u32>,
     pub active: Option<bool>,
 }
@@ -1,4 +1,4 @@
-use crate::domain::database::{NewUser, User};
+use crate::domain::database::{NewUser, User, UsersStamp};
 use serde_json::{from_value, json, to_value};
 
 #[test]
@@ -23,12 +23,15 @@ fn test_user_serialization() {
     let user = User {
         uid: 1,
         name: String::from("John Doe"),
+        updated_at: None,
+        ..Default::default()
     };
 
     let serialized = to_value(&user).unwrap();
     let expected = json!({
         "uid": 1,
-        "name": "John Doe"
+        "name": "John Doe",
+        "version": 0
     });
 
     assert_eq!(serialized, expected);
@@ -51,6 +54,8 @@ fn test_user_clone() {
     let original_user = User {
         uid: 1,
         name: String::from("John Doe"),
+        updated_at: None,
+        ..Default::default()
     };
 
     let cloned_user = original_user.clone();
@@ -79,3 +84,45 @@ fn test_user_invalid_types() {
     let result = from_value::<User>(json_data);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_new_user_validation() {
+    use validator::Validate;
+
+    let valid = NewUser {
+        name: String::from("John Doe"),
+    };
+    assert!(valid.validate().is_ok());
+
+    for name in ["", "   ", "a name that is far too long"] {
+        let invalid = NewUser {
+            name: name.to_string(),
+        };
+        assert!(invalid.validate().is_err(), "{:?} should be rejected", name);
+    }
+}
+
+#[test]
+fn test_users_stamp_changes_with_every_write() {
+    let user = |uid: i32, version: i32| User {
+        uid,
+        name: "John Doe".to_string(),
+        version,
+        ..Default::default()
+    };
+    let stamp = UsersStamp::of_users(&[user(1, 1), user(2, 1)]);
+    assert_eq!(stamp.count, 2);
+    assert_eq!(stamp.max_uid, Some(2));
+    assert_eq!(stamp.versions, 2);
+
+    for users in [
+        vec![user(1, 1), user(2, 1), user(3, 1)],
+        vec![user(1, 2), user(2, 1)],
+        vec![user(1, 1)],
+        // The last user deleted and another created.
+        vec![user(1, 1), user(3, 1)],
+    ] {
+        assert_ne!(UsersStamp::of_users(&users), stamp);
+    }
+    assert_eq!(UsersStamp::of_users(&[]).max_uid, None);
+}
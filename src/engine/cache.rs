@@ -0,0 +1,533 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::domain::database::{User, UsersStamp};
+use crate::engine::db_engine::{DatabaseExecutor, DbPool, UserStream};
+use crate::engine::replicas::reading_primary;
+use crate::engine::transaction::Transaction;
+use crate::utils::config_utils::{env_duration, env_parse};
+use anyhow::Result;
+use opentelemetry::KeyValue;
+use opentelemetry::global;
+use opentelemetry::metrics::Counter;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Key of the cached `sp_Return_USERS()` result.
+pub const USERS_KEY: &str = "users:all";
+/// Key of the cached stamp of the same users, what conditional `GET /users` are answered from.
+pub const USERS_STAMP_KEY: &str = "users:stamp";
+/// Entries every user write makes stale.
+const USER_KEYS: [&str; 2] = [USERS_KEY, USERS_STAMP_KEY];
+
+tokio::task_local! {
+    static BYPASS: bool;
+}
+
+/// Runs `f` with cache reads disabled, results are still fetched from the database and stored.
+pub async fn bypassing<F: Future>(f: F) -> F::Output {
+    BYPASS.scope(true, f).await
+}
+
+fn bypassed() -> bool {
+    BYPASS.try_with(|bypass| *bypass).unwrap_or(false)
+}
+
+/// Byte store holding serialized query results.
+#[async_trait::async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<()>;
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// Counter of the invalidations, shared by whoever shares the entries.
+    async fn generation(&self) -> Result<u64>;
+    async fn bump_generation(&self) -> Result<()>;
+}
+
+/// Process-local cache holding at most `max_entries` entries.
+/// When full, expired entries are dropped first, then the one closest to expiry.
+#[derive(Debug)]
+pub struct InMemoryCache {
+    entries: Mutex<HashMap<String, (Vec<u8>, Instant)>>,
+    max_entries: usize,
+    generation: AtomicU64,
+}
+
+impl InMemoryCache {
+    pub fn new(max_entries: usize) -> Self {
+        InMemoryCache {
+            entries: Mutex::new(HashMap::new()),
+            max_entries: max_entries.max(1),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("cache lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        match entries.get(key) {
+            Some((_, expires)) if *expires <= Instant::now() => {
+                entries.remove(key);
+                Ok(None)
+            }
+            entry => Ok(entry.map(|(value, _)| value.clone())),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("cache lock poisoned");
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, (_, expires)| *expires > now);
+            if entries.len() >= self.max_entries
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, (_, expires))| *expires)
+                    .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_string(), (value, now + ttl));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.entries
+            .lock()
+            .expect("cache lock poisoned")
+            .remove(key);
+        Ok(())
+    }
+
+    async fn generation(&self) -> Result<u64> {
+        Ok(self.generation.load(Ordering::SeqCst))
+    }
+
+    async fn bump_generation(&self) -> Result<()> {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Cache shared by every replica, entries expire through Redis TTLs.
+#[derive(Clone)]
+pub struct RedisCache {
+    redis: RedisConnection,
+    prefix: String,
+}
+
+impl RedisCache {
+    pub fn new(redis: RedisConnection) -> Self {
+        RedisCache {
+            redis,
+            prefix: "cache:".to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut connection = self.redis.connection().await?;
+        let value: Option<Vec<u8>> = redis::cmd("GET")
+            .arg(format!("{}{}", self.prefix, key))
+            .query_async(&mut connection)
+            .await?;
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let mut connection = self.redis.connection().await?;
+        let _: () = redis::cmd("SET")
+            .arg(format!("{}{}", self.prefix, key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl.as_millis().max(1) as u64)
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let mut connection = self.redis.connection().await?;
+        let _: i64 = redis::cmd("DEL")
+            .arg(format!("{}{}", self.prefix, key))
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Without expiry, a missing counter (e.g. after a flush) reads as 0.
+    async fn generation(&self) -> Result<u64> {
+        let mut connection = self.redis.connection().await?;
+        let value: Option<u64> = redis::cmd("GET")
+            .arg(format!("{}generation", self.prefix))
+            .query_async(&mut connection)
+            .await?;
+        Ok(value.unwrap_or(0))
+    }
+
+    async fn bump_generation(&self) -> Result<()> {
+        let mut connection = self.redis.connection().await?;
+        let _: u64 = redis::cmd("INCR")
+            .arg(format!("{}generation", self.prefix))
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Hit and miss counters, kept locally and exported as `db.cache.hits` / `db.cache.misses`.
+pub struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    hit_counter: Counter<u64>,
+    miss_counter: Counter<u64>,
+}
+
+impl Default for CacheMetrics {
+    fn default() -> Self {
+        let meter = global::meter("excelsior");
+        CacheMetrics {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            hit_counter: meter.u64_counter("db.cache.hits").build(),
+            miss_counter: meter.u64_counter("db.cache.misses").build(),
+        }
+    }
+}
+
+impl CacheMetrics {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, key: &str, hit: bool) {
+        let attributes = [KeyValue::new("key", key.to_string())];
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.hit_counter.add(1, &attributes);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            self.miss_counter.add(1, &attributes);
+        }
+    }
+}
+
+/// Read-through cache in front of another executor.
+/// Reads are served from the backend while fresh, writes go straight through and invalidate
+/// the cached reads they affect. Misses are read from the primary when the executor has
+/// replicas. Backend failures are logged and fall back to the database.
+pub struct CachedExecutor {
+    inner: Box<DbPool>,
+    backend: Arc<dyn CacheBackend>,
+    ttl: Duration,
+    metrics: CacheMetrics,
+}
+
+impl CachedExecutor {
+    pub fn new(inner: DbPool, backend: Box<dyn CacheBackend>, ttl: Duration) -> Self {
+        CachedExecutor {
+            inner: Box::new(inner),
+            backend: backend.into(),
+            ttl,
+            metrics: CacheMetrics::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &DbPool {
+        &self.inner
+    }
+
+    pub fn metrics(&self) -> &CacheMetrics {
+        &self.metrics
+    }
+
+    async fn writing<T>(&self, write: impl Future<Output = Result<T>>) -> Result<T> {
+        writing(self.backend.as_ref(), write).await
+    }
+
+    /// `key` from the backend while fresh, else from `fetch`, stored for the next reads.
+    /// `fetch` runs on the primary: a replica still missing a write invalidated just before
+    /// would put the stale value back for a whole TTL. For the same reason, a value fetched
+    /// while a write invalidated the entries is not kept.
+    async fn read_through<T, F>(&self, key: &str, fetch: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T>>,
+    {
+        if !bypassed() {
+            match self.backend.get(key).await {
+                Ok(Some(bytes)) => match serde_json::from_slice(&bytes) {
+                    Ok(value) => {
+                        self.metrics.record(key, true);
+                        return Ok(value);
+                    }
+                    Err(err) => warn!("discarding unreadable cached {}: {}", key, err),
+                },
+                Ok(None) => {}
+                Err(err) => warn!("cache read for {} failed: {:#}", key, err),
+            }
+        } else {
+            debug!("cache bypassed for {}", key);
+        }
+        self.metrics.record(key, false);
+
+        let generation = self.backend.generation().await;
+        let value = reading_primary(fetch).await?;
+        match generation {
+            Ok(generation) => self.store(key, &value, generation).await?,
+            Err(err) => warn!("cache generation for {} unavailable: {:#}", key, err),
+        }
+        Ok(value)
+    }
+
+    /// Stores `value`, read while the backend was at `generation`, unless an invalidation
+    /// came after. Checked again once stored, in case it came while storing.
+    async fn store<T: Serialize>(&self, key: &str, value: &T, generation: u64) -> Result<()> {
+        if !self.still_at(generation).await {
+            debug!("{} invalidated while fetched, not cached", key);
+            return Ok(());
+        }
+        if let Err(err) = self
+            .backend
+            .set(key, serde_json::to_vec(value)?, self.ttl)
+            .await
+        {
+            warn!("cache write for {} failed: {:#}", key, err);
+        }
+        if !self.still_at(generation).await
+            && let Err(err) = self.backend.delete(key).await
+        {
+            warn!("failed to drop cached {}: {:#}", key, err);
+        }
+        Ok(())
+    }
+
+    async fn still_at(&self, generation: u64) -> bool {
+        self.backend
+            .generation()
+            .await
+            .is_ok_and(|current| current == generation)
+    }
+}
+
+/// Runs `write`, then invalidates the user entries even when it failed: the write may have
+/// been applied before the failure.
+async fn writing<T>(
+    backend: &dyn CacheBackend,
+    write: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = write.await;
+    invalidate(backend).await;
+    result
+}
+
+/// Bumps the generation before deleting, so that reads fetched before the write and not
+/// yet stored see they are stale.
+async fn invalidate(backend: &dyn CacheBackend) {
+    if let Err(err) = backend.bump_generation().await {
+        warn!("failed to bump the cache generation: {:#}", err);
+    }
+    for key in USER_KEYS {
+        if let Err(err) = backend.delete(key).await {
+            warn!("failed to invalidate cached {}: {:#}", key, err);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseExecutor for CachedExecutor {
+    async fn execute_get_users(&self) -> Result<Vec<User>> {
+        self.read_through(USERS_KEY, self.inner.execute_get_users())
+            .await
+    }
+
+    /// Admin listings are rare, not worth an entry to invalidate.
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        self.inner.execute_get_all_users().await
+    }
+
+    /// Always reads the database: users too many to collect are too many to cache.
+    async fn stream_users(&self, include_deleted: bool) -> Result<UserStream> {
+        self.inner.stream_users(include_deleted).await
+    }
+
+    /// Cached apart from the users, so that polling the list never collects it.
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        if include_deleted {
+            return self.inner.execute_get_users_stamp(true).await;
+        }
+        self.read_through(USERS_STAMP_KEY, self.inner.execute_get_users_stamp(false))
+            .await
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        self.writing(self.inner.execute_create_user(name, created_by))
+            .await
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        self.writing(self.inner.execute_create_users(names, created_by))
+            .await
+    }
+
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        self.inner.execute_get_user(uid).await
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        self.writing(self.inner.execute_update_user(uid, name, expected_version))
+            .await
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        self.writing(self.inner.execute_delete_user(uid, expected_version))
+            .await
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        self.writing(self.inner.execute_restore_user(uid)).await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        Ok(Box::new(CachedTransaction {
+            inner: self.inner.begin().await?,
+            backend: self.backend.clone(),
+            wrote: AtomicBool::new(false),
+        }))
+    }
+}
+
+/// Transaction of a `CachedExecutor`. Its reads skip the cache to see its own writes,
+/// which invalidate the cached reads once committed.
+struct CachedTransaction {
+    inner: Box<dyn Transaction>,
+    backend: Arc<dyn CacheBackend>,
+    wrote: AtomicBool,
+}
+
+impl CachedTransaction {
+    fn wrote(&self) {
+        self.wrote.store(true, Ordering::Relaxed);
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseExecutor for CachedTransaction {
+    async fn execute_get_users(&self) -> Result<Vec<User>> {
+        self.inner.execute_get_users().await
+    }
+
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        self.inner.execute_get_all_users().await
+    }
+
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        self.inner.execute_get_users_stamp(include_deleted).await
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        self.wrote();
+        self.inner.execute_create_user(name, created_by).await
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        self.wrote();
+        self.inner.execute_create_users(names, created_by).await
+    }
+
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        self.inner.execute_get_user(uid).await
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        self.wrote();
+        self.inner
+            .execute_update_user(uid, name, expected_version)
+            .await
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        self.wrote();
+        self.inner.execute_delete_user(uid, expected_version).await
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        self.wrote();
+        self.inner.execute_restore_user(uid).await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        self.inner.begin().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Transaction for CachedTransaction {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        if self.wrote.load(Ordering::Relaxed) {
+            writing(self.backend.as_ref(), self.inner.commit()).await
+        } else {
+            self.inner.commit().await
+        }
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        self.inner.rollback().await
+    }
+}
+
+/// Wraps `pool` in a read-through cache according to `DB_CACHE` (`off`, `memory` or `redis`).
+/// `DB_CACHE_TTL` sets the freshness window, `DB_CACHE_MAX_ENTRIES` bounds the in-memory cache.
+pub fn cached_from_env(pool: DbPool) -> DbPool {
+    let ttl = env_duration("DB_CACHE_TTL").unwrap_or(Duration::from_secs(30));
+    let backend: Box<dyn CacheBackend> = match std::env::var("DB_CACHE").as_deref() {
+        Ok("off") | Err(_) => return pool,
+        Ok("memory") => Box::new(InMemoryCache::new(env_parse("DB_CACHE_MAX_ENTRIES", 1000))),
+        Ok("redis") => Box::new(RedisCache::new(
+            RedisConnection::from_env()
+                .expect("REDIS_URL or REDIS_HOST must be set when DB_CACHE=redis."),
+        )),
+        Ok(other) => panic!("DB_CACHE must be off, memory or redis, got {}.", other),
+    };
+    DbPool::Cached(CachedExecutor::new(pool, backend, ttl))
+}
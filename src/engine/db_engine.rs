@@ -1,43 +1,326 @@
-use crate::domain::database::User;
+use crate::database::pool::{Database, PoolStats};
+use crate::database::repository::UserRepository;
+use crate::domain::database::{User, UsersStamp};
+use crate::engine::cache::CachedExecutor;
+use crate::engine::replicas::ReplicatedExecutor;
+use crate::engine::transaction::{Transaction, in_transaction};
 use crate::state::AppState;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use axum::extract::State;
+use futures::stream::BoxStream;
+use futures::{StreamExt, stream};
 #[cfg(test)]
 use mockall::automock;
-use sqlx::{query, MySql, Pool, Row};
+use sqlx::MySql;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Rows streamed by `DatabaseExecutor::stream_users`.
+pub type UserStream = BoxStream<'static, Result<User>>;
+
+/// Users `Database::stream_users` reads ahead of its consumer before waiting for it.
+pub const STREAM_BUFFER: usize = 64;
 
 // Wrapper type that can be either a real pool or a mock (in tests)
 pub enum DbPool {
-    Real(Pool<MySql>),
+    Real(Database),
+    Cached(CachedExecutor),
+    Replicated(ReplicatedExecutor),
     #[cfg(test)]
     Mock(MockDatabaseExecutor),
 }
 
+impl DbPool {
+    /// Closes the underlying pool, waiting for checked-out connections to be returned.
+    pub async fn close(&self) {
+        match self {
+            DbPool::Real(pool) => pool.close().await,
+            DbPool::Cached(cached) => Box::pin(cached.inner().close()).await,
+            DbPool::Replicated(replicated) => Box::pin(replicated.close()).await,
+            #[cfg(test)]
+            DbPool::Mock(_) => {}
+        }
+    }
+
+    /// Size and acquire latency of the underlying pools, none for mocks.
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        match self {
+            DbPool::Real(database) => vec![database.stats()],
+            DbPool::Cached(cached) => cached.inner().pool_stats(),
+            DbPool::Replicated(replicated) => replicated.pool_stats(),
+            #[cfg(test)]
+            DbPool::Mock(_) => Vec::new(),
+        }
+    }
+}
+
 #[cfg_attr(test, automock)]
 #[async_trait::async_trait]
 pub trait DatabaseExecutor: Send + Sync {
+    /// Users that are not soft-deleted.
     async fn execute_get_users(&self) -> Result<Vec<User>>;
-    async fn execute_create_user(&self, name: String) -> Result<String>;
+    /// Every user, soft-deleted ones included.
+    async fn execute_get_all_users(&self) -> Result<Vec<User>>;
+    /// Same users as `execute_get_users`, or `execute_get_all_users` with `include_deleted`,
+    /// without collecting them first. By default they are still collected first, `Database`
+    /// reads them as the stream is polled.
+    async fn stream_users(&self, include_deleted: bool) -> Result<UserStream> {
+        let users = if include_deleted {
+            self.execute_get_all_users().await?
+        } else {
+            self.execute_get_users().await?
+        };
+        Ok(stream::iter(users.into_iter().map(Ok)).boxed())
+    }
+    /// Stamp of the users `stream_users` lists. By default computed from the collected users,
+    /// `Database` has the database compute it.
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        let users = if include_deleted {
+            self.execute_get_all_users().await?
+        } else {
+            self.execute_get_users().await?
+        };
+        Ok(UsersStamp::of_users(&users))
+    }
+    /// Returns the inserted user, recorded as created by `created_by`.
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User>;
+    /// Inserts all the names or none of them, returning the users in the same order.
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>>;
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>>;
+    /// Renames the user, only if it is still at version `expected_version` when given.
+    /// Returns `None` when the user does not exist or changed in the meantime.
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>>;
+    /// Soft-deletes the user, with the same precondition as `execute_update_user`.
+    /// Returns whether a user was deleted.
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool>;
+    /// Undoes `execute_delete_user`, `None` when the user does not exist or is not deleted.
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>>;
+    /// Starts a transaction, whose operations apply together on `Transaction::commit`.
+    async fn begin(&self) -> Result<Box<dyn Transaction>>;
 }
 
 #[async_trait::async_trait]
-impl DatabaseExecutor for Pool<MySql> {
+impl DatabaseExecutor for Database {
     async fn execute_get_users(&self) -> Result<Vec<User>> {
-        let users = query("CALL sp_Return_USERS();")
-            .map(|row: sqlx::mysql::MySqlRow| User {
-                uid: row.get(0),
-                name: row.get(1),
+        self.repository()
+            .get_users(&mut *self.acquire().await?, false)
+            .await
+    }
+
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        self.repository()
+            .get_users(&mut *self.acquire().await?, true)
+            .await
+    }
+
+    /// Reads on a connection of its own, kept until the stream ends or is dropped.
+    /// At most `STREAM_BUFFER` users wait for the consumer, so a slow client slows the reads.
+    /// A consumer taking no user for `stream_send_timeout` gets the connection released
+    /// and an error in place of the remaining users. The reading task ends at once, the
+    /// error is kept in its result and follows the users already buffered.
+    async fn stream_users(&self, include_deleted: bool) -> Result<UserStream> {
+        let mut connection = self.acquire().await?;
+        let repository = self.repository().clone();
+        let send_timeout = self.stream_send_timeout();
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let task = tokio::spawn(async move {
+            let mut users = repository.stream_users(&mut connection, include_deleted);
+            while let Some(user) = users.next().await {
+                let failed = user.is_err();
+                match tokio::time::timeout(send_timeout, sender.send(user)).await {
+                    Ok(Ok(())) if !failed => {}
+                    Ok(_) => break,
+                    Err(_) => {
+                        warn!("user stream consumer stalled, connection released");
+                        return Err(anyhow!(
+                            "no user taken for {:?}, stream aborted",
+                            send_timeout
+                        ));
+                    }
+                }
+            }
+            Ok(())
+        });
+        Ok(
+            stream::unfold((receiver, Some(task)), |(mut receiver, task)| async move {
+                if let Some(user) = receiver.recv().await {
+                    return Some((user, (receiver, task)));
+                }
+                let error = match task?.await {
+                    Ok(Ok(())) => return None,
+                    Ok(Err(err)) => err,
+                    Err(err) => anyhow!("user stream task failed: {}", err),
+                };
+                Some((Err(error), (receiver, None)))
             })
-            .fetch_all(self)
+            .boxed(),
+        )
+    }
+
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        self.repository()
+            .users_stamp(&mut *self.acquire().await?, include_deleted)
+            .await
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        self.repository()
+            .create_user(&mut *self.acquire().await?, name, created_by)
+            .await
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        let mut transaction = sqlx::Transaction::begin(self.acquire().await?, None).await?;
+        let users = self
+            .repository()
+            .create_users(&mut transaction, names, created_by)
             .await?;
+        transaction.commit().await?;
         Ok(users)
     }
 
-    async fn execute_create_user(&self, name: String) -> Result<String> {
-        let _ = query(&format!("CALL sp_Insert_User(\"{}\")", name))
-            .execute(self)
-            .await?;
-        Ok("OK".to_string())
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        self.repository()
+            .get_user(&mut *self.acquire().await?, uid)
+            .await
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        self.repository()
+            .update_user(&mut *self.acquire().await?, uid, name, expected_version)
+            .await
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        self.repository()
+            .delete_user(&mut *self.acquire().await?, uid, expected_version)
+            .await
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        self.repository()
+            .restore_user(&mut *self.acquire().await?, uid)
+            .await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        let connection = self.acquire().await?;
+        let transaction = sqlx::Transaction::begin(connection, None).await?;
+        Ok(Box::new(MySqlTransaction {
+            transaction: tokio::sync::Mutex::new(transaction),
+            repository: self.repository().clone(),
+        }))
+    }
+}
+
+/// A transaction holding its pooled connection until committed or rolled back.
+/// Dropped without either, it is rolled back when the connection returns to the pool.
+pub struct MySqlTransaction {
+    transaction: tokio::sync::Mutex<sqlx::Transaction<'static, MySql>>,
+    repository: Arc<dyn UserRepository>,
+}
+
+#[async_trait::async_trait]
+impl DatabaseExecutor for MySqlTransaction {
+    async fn execute_get_users(&self) -> Result<Vec<User>> {
+        self.repository
+            .get_users(&mut **self.transaction.lock().await, false)
+            .await
+    }
+
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        self.repository
+            .get_users(&mut **self.transaction.lock().await, true)
+            .await
+    }
+
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        self.repository
+            .users_stamp(&mut **self.transaction.lock().await, include_deleted)
+            .await
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        self.repository
+            .create_user(&mut **self.transaction.lock().await, name, created_by)
+            .await
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        self.repository
+            .create_users(&mut **self.transaction.lock().await, names, created_by)
+            .await
+    }
+
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        self.repository
+            .get_user(&mut **self.transaction.lock().await, uid)
+            .await
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        self.repository
+            .update_user(
+                &mut **self.transaction.lock().await,
+                uid,
+                name,
+                expected_version,
+            )
+            .await
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        self.repository
+            .delete_user(&mut **self.transaction.lock().await, uid, expected_version)
+            .await
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        self.repository
+            .restore_user(&mut **self.transaction.lock().await, uid)
+            .await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        Err(anyhow!("transactions cannot be nested"))
+    }
+}
+
+#[async_trait::async_trait]
+impl Transaction for MySqlTransaction {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        Ok(self.transaction.into_inner().commit().await?)
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        Ok(self.transaction.into_inner().rollback().await?)
     }
 }
 
@@ -46,16 +329,135 @@ impl DatabaseExecutor for DbPool {
     async fn execute_get_users(&self) -> Result<Vec<User>> {
         match self {
             DbPool::Real(pool) => pool.execute_get_users().await,
+            DbPool::Cached(cached) => cached.execute_get_users().await,
+            DbPool::Replicated(replicated) => replicated.execute_get_users().await,
             #[cfg(test)]
             DbPool::Mock(mock) => mock.execute_get_users().await,
         }
     }
 
-    async fn execute_create_user(&self, name: String) -> Result<String> {
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        match self {
+            DbPool::Real(pool) => pool.execute_get_all_users().await,
+            DbPool::Cached(cached) => cached.execute_get_all_users().await,
+            DbPool::Replicated(replicated) => replicated.execute_get_all_users().await,
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_get_all_users().await,
+        }
+    }
+
+    async fn stream_users(&self, include_deleted: bool) -> Result<UserStream> {
+        match self {
+            DbPool::Real(pool) => pool.stream_users(include_deleted).await,
+            DbPool::Cached(cached) => cached.stream_users(include_deleted).await,
+            DbPool::Replicated(replicated) => replicated.stream_users(include_deleted).await,
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.stream_users(include_deleted).await,
+        }
+    }
+
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        match self {
+            DbPool::Real(pool) => pool.execute_get_users_stamp(include_deleted).await,
+            DbPool::Cached(cached) => cached.execute_get_users_stamp(include_deleted).await,
+            DbPool::Replicated(replicated) => {
+                replicated.execute_get_users_stamp(include_deleted).await
+            }
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_get_users_stamp(include_deleted).await,
+        }
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        match self {
+            DbPool::Real(pool) => pool.execute_create_user(name, created_by).await,
+            DbPool::Cached(cached) => cached.execute_create_user(name, created_by).await,
+            DbPool::Replicated(replicated) => {
+                replicated.execute_create_user(name, created_by).await
+            }
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_create_user(name, created_by).await,
+        }
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        match self {
+            DbPool::Real(pool) => pool.execute_create_users(names, created_by).await,
+            DbPool::Cached(cached) => cached.execute_create_users(names, created_by).await,
+            DbPool::Replicated(replicated) => {
+                replicated.execute_create_users(names, created_by).await
+            }
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_create_users(names, created_by).await,
+        }
+    }
+
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        match self {
+            DbPool::Real(pool) => pool.execute_get_user(uid).await,
+            DbPool::Cached(cached) => cached.execute_get_user(uid).await,
+            DbPool::Replicated(replicated) => replicated.execute_get_user(uid).await,
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_get_user(uid).await,
+        }
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
         match self {
-            DbPool::Real(pool) => pool.execute_create_user(name).await,
+            DbPool::Real(pool) => pool.execute_update_user(uid, name, expected_version).await,
+            DbPool::Cached(cached) => {
+                cached
+                    .execute_update_user(uid, name, expected_version)
+                    .await
+            }
+            DbPool::Replicated(replicated) => {
+                replicated
+                    .execute_update_user(uid, name, expected_version)
+                    .await
+            }
             #[cfg(test)]
-            DbPool::Mock(mock) => mock.execute_create_user(name).await,
+            DbPool::Mock(mock) => mock.execute_update_user(uid, name, expected_version).await,
+        }
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        match self {
+            DbPool::Real(pool) => pool.execute_delete_user(uid, expected_version).await,
+            DbPool::Cached(cached) => cached.execute_delete_user(uid, expected_version).await,
+            DbPool::Replicated(replicated) => {
+                replicated.execute_delete_user(uid, expected_version).await
+            }
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_delete_user(uid, expected_version).await,
+        }
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        match self {
+            DbPool::Real(pool) => pool.execute_restore_user(uid).await,
+            DbPool::Cached(cached) => cached.execute_restore_user(uid).await,
+            DbPool::Replicated(replicated) => replicated.execute_restore_user(uid).await,
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.execute_restore_user(uid).await,
+        }
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        match self {
+            DbPool::Real(pool) => pool.begin().await,
+            DbPool::Cached(cached) => cached.begin().await,
+            DbPool::Replicated(replicated) => replicated.begin().await,
+            #[cfg(test)]
+            DbPool::Mock(mock) => mock.begin().await,
         }
     }
 }
@@ -64,6 +466,71 @@ pub async fn get_users_db_call(State(state): State<AppState>) -> Result<Vec<User
     state.db_pool.execute_get_users().await
 }
 
-pub async fn create_user_db_call(State(state): State<AppState>, name: String) -> Result<String> {
-    state.db_pool.execute_create_user(name).await
+pub async fn get_all_users_db_call(State(state): State<AppState>) -> Result<Vec<User>> {
+    state.db_pool.execute_get_all_users().await
+}
+
+pub async fn stream_users_db_call(
+    State(state): State<AppState>,
+    include_deleted: bool,
+) -> Result<UserStream> {
+    state.db_pool.stream_users(include_deleted).await
+}
+
+pub async fn get_users_stamp_db_call(
+    State(state): State<AppState>,
+    include_deleted: bool,
+) -> Result<UsersStamp> {
+    state.db_pool.execute_get_users_stamp(include_deleted).await
+}
+
+pub async fn create_user_db_call(
+    State(state): State<AppState>,
+    name: String,
+    created_by: Option<String>,
+) -> Result<User> {
+    state.db_pool.execute_create_user(name, created_by).await
+}
+
+/// Inserts `names` in a transaction of their own: all of them, or none when one fails.
+pub async fn create_users_db_call(
+    State(state): State<AppState>,
+    names: Vec<String>,
+    created_by: Option<String>,
+) -> Result<Vec<User>> {
+    in_transaction(state.db_pool.as_ref(), move |transaction| {
+        Box::pin(async move { transaction.execute_create_users(names, created_by).await })
+    })
+    .await
+}
+
+pub async fn get_user_db_call(State(state): State<AppState>, uid: i32) -> Result<Option<User>> {
+    state.db_pool.execute_get_user(uid).await
+}
+
+pub async fn update_user_db_call(
+    State(state): State<AppState>,
+    uid: i32,
+    name: String,
+    expected_version: Option<i32>,
+) -> Result<Option<User>> {
+    state
+        .db_pool
+        .execute_update_user(uid, name, expected_version)
+        .await
+}
+
+pub async fn delete_user_db_call(
+    State(state): State<AppState>,
+    uid: i32,
+    expected_version: Option<i32>,
+) -> Result<bool> {
+    state
+        .db_pool
+        .execute_delete_user(uid, expected_version)
+        .await
+}
+
+pub async fn restore_user_db_call(State(state): State<AppState>, uid: i32) -> Result<Option<User>> {
+    state.db_pool.execute_restore_user(uid).await
 }
@@ -1,4 +1,7 @@
+pub mod cache;
 pub mod db_engine;
+pub mod replicas;
+pub mod transaction;
 
 #[cfg(test)]
 mod tests;
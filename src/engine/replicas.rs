@@ -0,0 +1,312 @@
+use crate::database::connection::PoolSettings;
+use crate::database::pool::{Database, PoolStats, stream_send_timeout_from_env};
+use crate::database::repository::repository_from_env;
+use crate::domain::database::{User, UsersStamp};
+use crate::engine::db_engine::{DatabaseExecutor, DbPool, UserStream};
+use crate::engine::transaction::Transaction;
+use crate::utils::config_utils::{env_duration, env_parse};
+use anyhow::Result;
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+tokio::task_local! {
+    static WROTE: Cell<bool>;
+    static FROM_PRIMARY: bool;
+}
+
+/// Runs `f` remembering whether it wrote, so its later reads can go to the primary.
+pub async fn tracking_writes<F: Future>(f: F) -> F::Output {
+    WROTE.scope(Cell::new(false), f).await
+}
+
+fn record_write() {
+    let _ = WROTE.try_with(|wrote| wrote.set(true));
+}
+
+fn wrote() -> bool {
+    WROTE.try_with(|wrote| wrote.get()).unwrap_or(false)
+}
+
+/// Runs `f` with every read sent to the primary, for results kept beyond the request
+/// (a replica lagging behind a write would have them outlive it).
+pub async fn reading_primary<F: Future>(f: F) -> F::Output {
+    FROM_PRIMARY.scope(true, f).await
+}
+
+fn from_primary() -> bool {
+    FROM_PRIMARY.try_with(|primary| *primary).unwrap_or(false)
+}
+
+/// Errors saying the replica itself is unreachable, as opposed to the query failing.
+fn is_connection_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<sqlx::Error>(),
+        Some(
+            sqlx::Error::Io(_)
+                | sqlx::Error::Tls(_)
+                | sqlx::Error::PoolTimedOut
+                | sqlx::Error::PoolClosed
+                | sqlx::Error::WorkerCrashed
+        )
+    )
+}
+
+pub struct Replica {
+    pub name: String,
+    pool: DbPool,
+    /// Skipped until then after a connection failure, tried again afterwards.
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    pub fn new(name: impl Into<String>, pool: DbPool) -> Self {
+        Replica {
+            name: name.into(),
+            pool,
+            down_until: Mutex::new(None),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.down_until
+            .lock()
+            .expect("replica lock poisoned")
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn mark_down(&self, cooldown: Duration) {
+        *self.down_until.lock().expect("replica lock poisoned") = Some(Instant::now() + cooldown);
+    }
+
+    fn mark_up(&self) {
+        let mut down_until = self.down_until.lock().expect("replica lock poisoned");
+        if down_until.take().is_some() {
+            info!("replica {} is reachable again", self.name);
+        }
+    }
+}
+
+/// Sends writes to the primary and spreads reads over the healthy replicas in turn.
+/// A replica failing to connect is skipped for `cooldown` and the read moves on to the next
+/// one, then to the primary. With `read_your_writes`, a request that wrote reads from the
+/// primary for the rest of its lifetime, see `tracking_writes`.
+pub struct ReplicatedExecutor {
+    primary: Box<DbPool>,
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+    cooldown: Duration,
+    read_your_writes: bool,
+}
+
+impl ReplicatedExecutor {
+    pub fn new(primary: DbPool, replicas: Vec<Replica>) -> Self {
+        ReplicatedExecutor {
+            primary: Box::new(primary),
+            replicas,
+            next: AtomicUsize::new(0),
+            cooldown: Duration::from_secs(10),
+            read_your_writes: true,
+        }
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn with_read_your_writes(mut self, read_your_writes: bool) -> Self {
+        self.read_your_writes = read_your_writes;
+        self
+    }
+
+    pub fn primary(&self) -> &DbPool {
+        &self.primary
+    }
+
+    pub fn replicas(&self) -> &[Replica] {
+        &self.replicas
+    }
+
+    pub async fn close(&self) {
+        self.primary.close().await;
+        for replica in &self.replicas {
+            replica.pool.close().await;
+        }
+    }
+
+    /// Pools of the primary then of the replicas, with the health of each replica.
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        let mut stats = self.primary.pool_stats();
+        for replica in &self.replicas {
+            stats.extend(
+                replica
+                    .pool
+                    .pool_stats()
+                    .into_iter()
+                    .map(|stats| PoolStats {
+                        healthy: replica.is_healthy(),
+                        ..stats
+                    }),
+            );
+        }
+        stats
+    }
+
+    async fn read<'a, T, F, Fut>(&'a self, query: F) -> Result<T>
+    where
+        F: Fn(&'a DbPool) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let pinned = from_primary() || (self.read_your_writes && wrote());
+        if !pinned && !self.replicas.is_empty() {
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            for offset in 0..self.replicas.len() {
+                let replica = &self.replicas[(start + offset) % self.replicas.len()];
+                if !replica.is_healthy() {
+                    continue;
+                }
+                match query(&replica.pool).await {
+                    Ok(value) => {
+                        replica.mark_up();
+                        return Ok(value);
+                    }
+                    Err(err) if is_connection_error(&err) => {
+                        warn!(
+                            "replica {} unreachable, skipping it for {:?}: {:#}",
+                            replica.name, self.cooldown, err
+                        );
+                        replica.mark_down(self.cooldown);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        query(&self.primary).await
+    }
+
+    async fn write<T>(&self, result: impl Future<Output = Result<T>>) -> Result<T> {
+        // Even on error, the write may have been applied before the failure.
+        record_write();
+        result.await
+    }
+}
+
+#[async_trait::async_trait]
+impl DatabaseExecutor for ReplicatedExecutor {
+    async fn execute_get_users(&self) -> Result<Vec<User>> {
+        self.read(|pool| pool.execute_get_users()).await
+    }
+
+    async fn execute_get_all_users(&self) -> Result<Vec<User>> {
+        self.read(|pool| pool.execute_get_all_users()).await
+    }
+
+    /// Fails over like other reads only until the stream starts.
+    async fn stream_users(&self, include_deleted: bool) -> Result<UserStream> {
+        self.read(|pool| pool.stream_users(include_deleted)).await
+    }
+
+    async fn execute_get_users_stamp(&self, include_deleted: bool) -> Result<UsersStamp> {
+        self.read(|pool| pool.execute_get_users_stamp(include_deleted))
+            .await
+    }
+
+    async fn execute_create_user(&self, name: String, created_by: Option<String>) -> Result<User> {
+        self.write(self.primary.execute_create_user(name, created_by))
+            .await
+    }
+
+    async fn execute_create_users(
+        &self,
+        names: Vec<String>,
+        created_by: Option<String>,
+    ) -> Result<Vec<User>> {
+        self.write(self.primary.execute_create_users(names, created_by))
+            .await
+    }
+
+    async fn execute_get_user(&self, uid: i32) -> Result<Option<User>> {
+        self.read(|pool| pool.execute_get_user(uid)).await
+    }
+
+    async fn execute_update_user(
+        &self,
+        uid: i32,
+        name: String,
+        expected_version: Option<i32>,
+    ) -> Result<Option<User>> {
+        self.write(
+            self.primary
+                .execute_update_user(uid, name, expected_version),
+        )
+        .await
+    }
+
+    async fn execute_delete_user(&self, uid: i32, expected_version: Option<i32>) -> Result<bool> {
+        self.write(self.primary.execute_delete_user(uid, expected_version))
+            .await
+    }
+
+    async fn execute_restore_user(&self, uid: i32) -> Result<Option<User>> {
+        self.write(self.primary.execute_restore_user(uid)).await
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>> {
+        self.write(self.primary.begin()).await
+    }
+}
+
+/// Adds the replicas of `DB_REPLICA_HOSTS` (`host:port,host:port`, same credentials and
+/// schema as the primary) in front of `primary`. Their pools follow `DB_REPLICA_POOL_*`
+/// and connect lazily, so a replica down at startup is only skipped.
+/// `DB_REPLICA_COOLDOWN` sets how long an unreachable replica is skipped,
+/// `DB_READ_YOUR_WRITES=false` lets requests read from replicas after writing.
+pub fn replicated_from_env(primary: DbPool) -> DbPool {
+    let Ok(hosts) = std::env::var("DB_REPLICA_HOSTS") else {
+        return primary;
+    };
+    let settings = PoolSettings::from_env("DB_REPLICA_POOL");
+    let repository = repository_from_env();
+    let stream_send_timeout = stream_send_timeout_from_env();
+    let user = std::env::var("DATABASE_USER").expect("DATABASE_USER must be set.");
+    let password = std::env::var("DATABASE_PSWD").expect("DATABASE_PSWD must be set.");
+    let name = std::env::var("DATABASE_NAME").expect("DATABASE_NAME must be set.");
+
+    let replicas: Vec<Replica> = hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .enumerate()
+        .map(|(index, host)| {
+            let url = format!("mysql://{}:{}@{}/{}", user, password, host, name);
+            let pool = settings.options().connect_lazy(&url).unwrap_or_else(|err| {
+                panic!("DB_REPLICA_HOSTS has an invalid host {}: {}", host, err)
+            });
+            let name = format!("replica-{}", index + 1);
+            info!("Reading from {} at {}", name, host);
+            Replica::new(
+                name.clone(),
+                DbPool::Real(
+                    Database::new(name, pool)
+                        .with_repository(repository.clone())
+                        .with_stream_send_timeout(stream_send_timeout),
+                ),
+            )
+        })
+        .collect();
+    if replicas.is_empty() {
+        return primary;
+    }
+
+    let mut replicated = ReplicatedExecutor::new(primary, replicas)
+        .with_read_your_writes(env_parse("DB_READ_YOUR_WRITES", true));
+    if let Some(cooldown) = env_duration("DB_REPLICA_COOLDOWN") {
+        replicated = replicated.with_cooldown(cooldown);
+    }
+    DbPool::Replicated(replicated)
+}
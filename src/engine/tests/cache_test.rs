@@ -0,0 +1,373 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::domain::database::{User, UsersStamp};
+use crate::engine::cache::*;
+use crate::engine::db_engine::*;
+use crate::handlers::db_handler::get_users;
+use crate::middleware::cache_bypass::cache_bypass;
+use crate::state::AppState;
+use crate::test_support::redis_stand_in::RedisStandIn;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::middleware::from_fn;
+use axum::{Router, routing::get};
+use futures::{StreamExt, TryStreamExt, stream};
+use mockall::predicate::eq;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn users() -> Vec<User> {
+    vec![User {
+        uid: 1,
+        name: "Test User".to_string(),
+        updated_at: None,
+        ..Default::default()
+    }]
+}
+
+fn cached(mock: MockDatabaseExecutor, backend: Box<dyn CacheBackend>) -> CachedExecutor {
+    CachedExecutor::new(DbPool::Mock(mock), backend, Duration::from_secs(30))
+}
+
+#[tokio::test]
+async fn test_second_read_is_served_from_cache() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    assert_eq!(executor.execute_get_users().await.unwrap()[0].uid, 1);
+    assert_eq!(executor.execute_get_users().await.unwrap()[0].uid, 1);
+
+    assert_eq!(executor.metrics().misses(), 1);
+    assert_eq!(executor.metrics().hits(), 1);
+}
+
+#[tokio::test]
+async fn test_streams_skip_the_cache() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db.expect_execute_get_users().never();
+    mock_db
+        .expect_stream_users()
+        .times(2)
+        .returning(|_| Ok(stream::iter(users().into_iter().map(Ok)).boxed()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    for _ in 0..2 {
+        let streamed: Vec<User> = executor
+            .stream_users(false)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(streamed[0].uid, 1);
+    }
+
+    assert_eq!(executor.metrics().misses(), 0);
+    assert_eq!(executor.metrics().hits(), 0);
+}
+
+#[tokio::test]
+async fn test_create_user_invalidates_users() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    mock_db
+        .expect_execute_create_user()
+        .times(1)
+        .returning(|_, _| Ok(users()[0].clone()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    executor.execute_get_users().await.unwrap();
+    executor
+        .execute_create_user("Someone".to_string(), None)
+        .await
+        .unwrap();
+    executor.execute_get_users().await.unwrap();
+
+    assert_eq!(executor.metrics().misses(), 2);
+    assert_eq!(executor.metrics().hits(), 0);
+}
+
+#[tokio::test]
+async fn test_stamp_is_cached_and_invalidated_with_the_users() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users_stamp()
+        .with(eq(false))
+        .times(2)
+        .returning(|_| Ok(UsersStamp::of_users(&users())));
+    // Admin listings are never cached.
+    mock_db
+        .expect_execute_get_users_stamp()
+        .with(eq(true))
+        .times(2)
+        .returning(|_| Ok(UsersStamp::default()));
+    mock_db
+        .expect_execute_delete_user()
+        .times(1)
+        .returning(|_, _| Ok(true));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    for _ in 0..2 {
+        assert_eq!(
+            executor.execute_get_users_stamp(false).await.unwrap().count,
+            1
+        );
+        executor.execute_get_users_stamp(true).await.unwrap();
+    }
+    executor.execute_delete_user(1, None).await.unwrap();
+    executor.execute_get_users_stamp(false).await.unwrap();
+
+    assert_eq!(executor.metrics().misses(), 2);
+    assert_eq!(executor.metrics().hits(), 1);
+}
+
+#[tokio::test]
+async fn test_restore_user_invalidates_users() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    mock_db
+        .expect_execute_restore_user()
+        .times(1)
+        .returning(|_| Ok(Some(users()[0].clone())));
+    // Deleted users are listed to admins only, never from the cache.
+    mock_db
+        .expect_execute_get_all_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    executor.execute_get_users().await.unwrap();
+    executor.execute_restore_user(1).await.unwrap();
+    executor.execute_get_users().await.unwrap();
+    executor.execute_get_all_users().await.unwrap();
+    executor.execute_get_all_users().await.unwrap();
+
+    assert_eq!(executor.metrics().misses(), 2);
+    assert_eq!(executor.metrics().hits(), 0);
+}
+
+type Hook = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Shares an in-memory cache, running `before_set` once before the first value is stored.
+struct RacingBackend {
+    inner: Arc<InMemoryCache>,
+    before_set: Mutex<Option<Hook>>,
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for RacingBackend {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.inner.get(key).await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) -> anyhow::Result<()> {
+        let hook = self.before_set.lock().unwrap().take();
+        if let Some(hook) = hook {
+            hook.await;
+        }
+        self.inner.set(key, value, ttl).await
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn generation(&self) -> anyhow::Result<u64> {
+        self.inner.generation().await
+    }
+
+    async fn bump_generation(&self) -> anyhow::Result<()> {
+        self.inner.bump_generation().await
+    }
+}
+
+#[tokio::test]
+async fn test_read_fetched_before_a_write_is_not_cached() {
+    let cache = Arc::new(InMemoryCache::new(10));
+
+    let mut writer_db = MockDatabaseExecutor::new();
+    writer_db
+        .expect_execute_create_user()
+        .times(1)
+        .returning(|_, _| Ok(users()[0].clone()));
+    let writer = cached(
+        writer_db,
+        Box::new(RacingBackend {
+            inner: cache.clone(),
+            before_set: Mutex::new(None),
+        }),
+    );
+    // The write commits and invalidates after the read fetched, before it stores.
+    let write: Hook = Box::pin(async move {
+        writer
+            .execute_create_user("Someone".to_string(), None)
+            .await
+            .unwrap();
+    });
+
+    let mut reader_db = MockDatabaseExecutor::new();
+    reader_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    let reader = cached(
+        reader_db,
+        Box::new(RacingBackend {
+            inner: cache.clone(),
+            before_set: Mutex::new(Some(write)),
+        }),
+    );
+
+    reader.execute_get_users().await.unwrap();
+    assert!(cache.is_empty());
+    reader.execute_get_users().await.unwrap();
+    assert_eq!(reader.metrics().misses(), 2);
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_entries_expire_after_ttl() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    executor.execute_get_users().await.unwrap();
+    tokio::time::advance(Duration::from_secs(29)).await;
+    executor.execute_get_users().await.unwrap();
+    tokio::time::advance(Duration::from_secs(2)).await;
+    executor.execute_get_users().await.unwrap();
+
+    assert_eq!(executor.metrics().hits(), 1);
+    assert_eq!(executor.metrics().misses(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_in_memory_cache_is_size_bounded() {
+    let cache = InMemoryCache::new(2);
+    cache
+        .set("a", b"1".to_vec(), Duration::from_secs(10))
+        .await
+        .unwrap();
+    cache
+        .set("b", b"2".to_vec(), Duration::from_secs(20))
+        .await
+        .unwrap();
+    cache
+        .set("c", b"3".to_vec(), Duration::from_secs(30))
+        .await
+        .unwrap();
+
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get("a").await.unwrap(), None);
+    assert_eq!(cache.get("c").await.unwrap(), Some(b"3".to_vec()));
+}
+
+#[tokio::test]
+async fn test_bypass_reads_from_database_and_refreshes() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    let executor = cached(mock_db, Box::new(InMemoryCache::new(10)));
+
+    executor.execute_get_users().await.unwrap();
+    bypassing(executor.execute_get_users()).await.unwrap();
+    executor.execute_get_users().await.unwrap();
+
+    assert_eq!(executor.metrics().misses(), 2);
+    assert_eq!(executor.metrics().hits(), 1);
+}
+
+#[tokio::test]
+async fn test_bypass_header_skips_cache() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    // The list is streamed every time, only its stamp is cached.
+    mock_db
+        .expect_execute_get_users_stamp()
+        .with(eq(false))
+        .times(2)
+        .returning(|_| Ok(UsersStamp::of_users(&users())));
+    mock_db
+        .expect_stream_users()
+        .times(3)
+        .returning(|_| Ok(stream::iter(users().into_iter().map(Ok)).boxed()));
+    let state = AppState::new(DbPool::Cached(cached(
+        mock_db,
+        Box::new(InMemoryCache::new(10)),
+    )));
+    let app = Router::new()
+        .route("/users", get(get_users))
+        .layer(from_fn(cache_bypass))
+        .with_state(state);
+
+    for bypass in [None, Some("true"), None] {
+        let mut request = Request::builder().uri("/users");
+        if let Some(value) = bypass {
+            request = request.header("x-cache-bypass", value);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_redis_backend_shares_and_invalidates() {
+    let redis = RedisStandIn::start().await;
+    let backend = || {
+        Box::new(RedisCache::new(
+            RedisConnection::open(&redis.url()).unwrap(),
+        ))
+    };
+
+    let mut first_db = MockDatabaseExecutor::new();
+    first_db
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users()));
+    first_db
+        .expect_execute_create_user()
+        .times(1)
+        .returning(|_, _| Ok(users()[0].clone()));
+    let first = cached(first_db, backend());
+
+    let mut second_db = MockDatabaseExecutor::new();
+    second_db
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users()));
+    let second = cached(second_db, backend());
+
+    first.execute_get_users().await.unwrap();
+    assert_eq!(redis.key_count(), 1);
+    second.execute_get_users().await.unwrap();
+    assert_eq!(second.metrics().hits(), 1);
+
+    first
+        .execute_create_user("Someone".to_string(), None)
+        .await
+        .unwrap();
+    // Only the generation counter is left.
+    assert_eq!(redis.key_count(), 1);
+    second.execute_get_users().await.unwrap();
+    assert_eq!(second.metrics().misses(), 1);
+}
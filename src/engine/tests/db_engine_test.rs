@@ -1,8 +1,9 @@
 use crate::domain::database::User;
 use crate::engine::db_engine::*;
+use crate::engine::transaction::MockDatabaseTransaction;
 use crate::state::AppState;
 use axum::extract::State;
-use std::sync::Arc;
+use futures::{StreamExt, TryStreamExt, stream};
 
 #[tokio::test]
 async fn test_get_users_db_call() {
@@ -13,13 +14,13 @@ async fn test_get_users_db_call() {
         Ok(vec![User {
             uid: 1,
             name: "Test User".to_string(),
+            updated_at: None,
+            ..Default::default()
         }])
     });
 
     // Create AppState with our mock wrapped in DbPool
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_db)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_db));
 
     // Test the actual function
     let result = get_users_db_call(State(state)).await.unwrap();
@@ -37,20 +38,84 @@ async fn test_create_user_db_call() {
     // Setup mock expectations
     mock_db
         .expect_execute_create_user()
-        .with(mockall::predicate::eq("Test User".to_string()))
+        .with(
+            mockall::predicate::eq("Test User".to_string()),
+            mockall::predicate::eq(Some("tester".to_string())),
+        )
         .times(1)
-        .returning(|_| Ok("OK".to_string()));
+        .returning(|name, created_by| {
+            Ok(User {
+                uid: 42,
+                name,
+                created_by,
+                ..Default::default()
+            })
+        });
 
     // Create AppState with our mock wrapped in DbPool
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_db)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_db));
 
     // Test the actual function
-    let result = create_user_db_call(State(state), "Test User".to_string())
+    let result = create_user_db_call(
+        State(state),
+        "Test User".to_string(),
+        Some("tester".to_string()),
+    )
+    .await
+    .unwrap();
+
+    // Assertions
+    assert_eq!(result.uid, 42);
+    assert_eq!(result.name, "Test User");
+}
+
+fn user(uid: i32) -> User {
+    User {
+        uid,
+        name: format!("User {}", uid),
+        updated_at: None,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_stream_users_db_call() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db.expect_execute_get_users().never();
+    mock_db
+        .expect_stream_users()
+        .times(1)
+        .returning(|_| Ok(stream::iter((1..=3).map(|uid| Ok(user(uid)))).boxed()));
+    let state = AppState::new(DbPool::Mock(mock_db));
+
+    let users: Vec<User> = stream_users_db_call(State(state), false)
+        .await
+        .unwrap()
+        .try_collect()
         .await
         .unwrap();
 
-    // Assertions
-    assert_eq!(result, "OK");
+    assert_eq!(
+        users.iter().map(|user| user.uid).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+}
+
+#[tokio::test]
+async fn test_stream_users_defaults_to_collected_users() {
+    let mut transaction = MockDatabaseTransaction::new();
+    transaction
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(vec![user(1), user(2)]));
+
+    let users: Vec<User> = transaction
+        .stream_users(false)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert_eq!(users.len(), 2);
 }
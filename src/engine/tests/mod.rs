@@ -1 +1,4 @@
+mod cache_test;
 mod db_engine_test;
+mod replicas_test;
+mod transaction_test;
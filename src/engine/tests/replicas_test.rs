@@ -0,0 +1,292 @@
+use crate::domain::database::{User, UsersStamp};
+use crate::engine::cache::{CachedExecutor, InMemoryCache};
+use crate::engine::db_engine::*;
+use crate::engine::replicas::*;
+use crate::handlers::db_handler::{create_user, get_users};
+use crate::middleware::read_your_writes::read_your_writes;
+use crate::state::AppState;
+use anyhow::anyhow;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::middleware::from_fn;
+use axum::routing::post;
+use axum::{Router, routing::get};
+use futures::{StreamExt, TryStreamExt, stream};
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn users(uid: i32) -> Vec<User> {
+    vec![User {
+        uid,
+        name: "Test User".to_string(),
+        updated_at: None,
+        ..Default::default()
+    }]
+}
+
+/// A mock answering reads with a single user carrying `uid`, to tell the pools apart.
+fn reads_as(uid: i32, times: usize) -> DbPool {
+    let mut mock = MockDatabaseExecutor::new();
+    mock.expect_execute_get_users()
+        .times(times)
+        .returning(move || Ok(users(uid)));
+    DbPool::Mock(mock)
+}
+
+fn unreachable(times: usize) -> DbPool {
+    let mut mock = MockDatabaseExecutor::new();
+    mock.expect_execute_get_users()
+        .times(times)
+        .returning(|| Err(sqlx::Error::PoolTimedOut.into()));
+    DbPool::Mock(mock)
+}
+
+async fn read_uid(executor: &ReplicatedExecutor) -> i32 {
+    executor.execute_get_users().await.unwrap()[0].uid
+}
+
+#[tokio::test]
+async fn test_reads_rotate_over_replicas() {
+    let executor = ReplicatedExecutor::new(
+        reads_as(0, 0),
+        vec![
+            Replica::new("replica-1", reads_as(1, 2)),
+            Replica::new("replica-2", reads_as(2, 2)),
+        ],
+    );
+
+    let mut uids = Vec::new();
+    for _ in 0..4 {
+        uids.push(read_uid(&executor).await);
+    }
+    assert_eq!(uids, vec![1, 2, 1, 2]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_unreachable_replica_is_skipped_until_cooldown() {
+    let executor = ReplicatedExecutor::new(
+        reads_as(0, 0),
+        vec![
+            Replica::new("replica-1", unreachable(2)),
+            Replica::new("replica-2", reads_as(2, 3)),
+        ],
+    )
+    .with_cooldown(Duration::from_secs(10));
+
+    // Fails over to the next replica, then leaves the failed one alone.
+    assert_eq!(read_uid(&executor).await, 2);
+    assert!(!executor.replicas()[0].is_healthy());
+    assert_eq!(read_uid(&executor).await, 2);
+
+    // Tried again once the cooldown elapsed.
+    tokio::time::advance(Duration::from_secs(11)).await;
+    assert!(executor.replicas()[0].is_healthy());
+    assert_eq!(read_uid(&executor).await, 2);
+    assert!(!executor.replicas()[0].is_healthy());
+}
+
+#[tokio::test]
+async fn test_reads_fall_back_to_primary() {
+    let executor = ReplicatedExecutor::new(
+        reads_as(0, 1),
+        vec![Replica::new("replica-1", unreachable(1))],
+    );
+
+    assert_eq!(read_uid(&executor).await, 0);
+}
+
+#[tokio::test]
+async fn test_query_errors_are_not_failed_over() {
+    let mut replica = MockDatabaseExecutor::new();
+    replica
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Err(anyhow!("syntax error")));
+    let executor = ReplicatedExecutor::new(
+        reads_as(0, 0),
+        vec![Replica::new("replica-1", DbPool::Mock(replica))],
+    );
+
+    assert!(executor.execute_get_users().await.is_err());
+    assert!(executor.replicas()[0].is_healthy());
+}
+
+#[tokio::test]
+async fn test_streams_fail_over_before_they_start() {
+    let mut down = MockDatabaseExecutor::new();
+    down.expect_stream_users()
+        .times(1)
+        .returning(|_| Err(sqlx::Error::PoolTimedOut.into()));
+    let mut up = MockDatabaseExecutor::new();
+    up.expect_stream_users()
+        .times(1)
+        .returning(|_| Ok(stream::iter(users(2).into_iter().map(Ok)).boxed()));
+    let executor = ReplicatedExecutor::new(
+        reads_as(0, 0),
+        vec![
+            Replica::new("replica-1", DbPool::Mock(down)),
+            Replica::new("replica-2", DbPool::Mock(up)),
+        ],
+    );
+
+    let streamed: Vec<User> = executor
+        .stream_users(false)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert_eq!(streamed[0].uid, 2);
+    assert!(!executor.replicas()[0].is_healthy());
+}
+
+#[tokio::test]
+async fn test_reads_after_a_write_go_to_primary() {
+    let mut primary = MockDatabaseExecutor::new();
+    primary
+        .expect_execute_create_user()
+        .times(2)
+        .returning(|_, _| Ok(users(0)[0].clone()));
+    primary
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users(0)));
+    let executor = ReplicatedExecutor::new(
+        DbPool::Mock(primary),
+        vec![Replica::new("replica-1", reads_as(1, 2))],
+    );
+
+    tracking_writes(async {
+        assert_eq!(read_uid(&executor).await, 1);
+        executor
+            .execute_create_user("Someone".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(read_uid(&executor).await, 0);
+    })
+    .await;
+    // Another request, or a write outside any request, pins nothing.
+    executor
+        .execute_create_user("Someone".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(read_uid(&executor).await, 1);
+}
+
+#[tokio::test]
+async fn test_restores_go_to_primary_and_pin_reads() {
+    let mut primary = MockDatabaseExecutor::new();
+    primary
+        .expect_execute_restore_user()
+        .times(1)
+        .returning(|_| Ok(Some(users(0)[0].clone())));
+    primary
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users(0)));
+    let executor = ReplicatedExecutor::new(
+        DbPool::Mock(primary),
+        vec![Replica::new("replica-1", reads_as(1, 0))],
+    );
+
+    tracking_writes(async {
+        executor.execute_restore_user(1).await.unwrap();
+        assert_eq!(read_uid(&executor).await, 0);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_read_your_writes_can_be_disabled() {
+    let mut primary = MockDatabaseExecutor::new();
+    primary
+        .expect_execute_create_user()
+        .times(1)
+        .returning(|_, _| Ok(users(0)[0].clone()));
+    let executor = ReplicatedExecutor::new(
+        DbPool::Mock(primary),
+        vec![Replica::new("replica-1", reads_as(1, 1))],
+    )
+    .with_read_your_writes(false);
+
+    tracking_writes(async {
+        executor
+            .execute_create_user("Someone".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(read_uid(&executor).await, 1);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_middleware_scopes_pinning_to_the_request() {
+    let mut primary = MockDatabaseExecutor::new();
+    primary
+        .expect_execute_create_user()
+        .times(1)
+        .returning(|_, _| Ok(users(0)[0].clone()));
+    // Listed from the primary whatever the pinning, see `get_users`.
+    primary
+        .expect_stream_users()
+        .times(1)
+        .returning(|_| Ok(stream::iter(users(0).into_iter().map(Ok)).boxed()));
+    let mut replica = MockDatabaseExecutor::new();
+    replica
+        .expect_execute_get_users_stamp()
+        .times(1)
+        .returning(|_| Ok(UsersStamp::of_users(&users(1))));
+    let state = AppState::new(DbPool::Replicated(ReplicatedExecutor::new(
+        DbPool::Mock(primary),
+        vec![Replica::new("replica-1", DbPool::Mock(replica))],
+    )));
+    let app = Router::new()
+        .route("/users", get(get_users))
+        .route("/users", post(create_user))
+        .layer(from_fn(read_your_writes))
+        .with_state(state);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/users")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"Someone"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    // The next request reads from the replica again.
+    let response = app
+        .oneshot(Request::get("/users").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_cache_is_filled_from_the_primary() {
+    let mut replica = MockDatabaseExecutor::new();
+    replica.expect_execute_get_users().never();
+    replica
+        .expect_execute_get_all_users()
+        .times(1)
+        .returning(|| Ok(users(1)));
+    let replicated = ReplicatedExecutor::new(
+        reads_as(0, 1),
+        vec![Replica::new("replica-1", DbPool::Mock(replica))],
+    );
+    let cached = DbPool::Cached(CachedExecutor::new(
+        DbPool::Replicated(replicated),
+        Box::new(InMemoryCache::new(10)),
+        Duration::from_secs(60),
+    ));
+
+    // Missed, filled from the primary, then served from the cache.
+    assert_eq!(cached.execute_get_users().await.unwrap()[0].uid, 0);
+    assert_eq!(cached.execute_get_users().await.unwrap()[0].uid, 0);
+    // Uncached reads still go to the replica.
+    assert_eq!(cached.execute_get_all_users().await.unwrap()[0].uid, 1);
+}
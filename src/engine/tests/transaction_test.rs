@@ -0,0 +1,167 @@
+use crate::domain::database::User;
+use crate::engine::cache::*;
+use crate::engine::db_engine::*;
+use crate::engine::replicas::*;
+use crate::engine::transaction::*;
+use anyhow::anyhow;
+use std::time::Duration;
+
+fn users() -> Vec<User> {
+    vec![User {
+        uid: 1,
+        name: "Test User".to_string(),
+        updated_at: None,
+        ..Default::default()
+    }]
+}
+
+/// A transaction expecting `creates` inserts, then either a commit or a rollback.
+fn transaction(creates: usize, committed: bool) -> MockDatabaseTransaction {
+    let mut transaction = MockDatabaseTransaction::new();
+    transaction
+        .expect_execute_create_user()
+        .times(creates)
+        .returning(|_, _| Ok(users()[0].clone()));
+    transaction
+        .expect_commit()
+        .times(usize::from(committed))
+        .returning(|| Ok(()));
+    transaction
+        .expect_rollback()
+        .times(usize::from(!committed))
+        .returning(|| Ok(()));
+    transaction
+}
+
+fn beginning(transaction: MockDatabaseTransaction) -> MockDatabaseExecutor {
+    let mut mock_db = MockDatabaseExecutor::new();
+    let mut transaction = Some(transaction);
+    mock_db.expect_begin().times(1).returning(move || {
+        Ok(Box::new(transaction.take().expect("a single transaction")) as Box<dyn Transaction>)
+    });
+    mock_db
+}
+
+#[tokio::test]
+async fn test_successful_work_is_committed() {
+    let pool = DbPool::Mock(beginning(transaction(2, true)));
+
+    let created = in_transaction(&pool, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("First".to_string(), None).await?;
+            tx.execute_create_user("Second".to_string(), None).await?;
+            Ok(2)
+        })
+    })
+    .await
+    .unwrap();
+    assert_eq!(created, 2);
+}
+
+#[tokio::test]
+async fn test_failed_work_is_rolled_back() {
+    let mut failing = transaction(1, false);
+    failing
+        .expect_execute_delete_user()
+        .times(1)
+        .returning(|_, _| Err(anyhow!("deadlock")));
+    let pool = DbPool::Mock(beginning(failing));
+
+    let result: anyhow::Result<()> = in_transaction(&pool, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("First".to_string(), None).await?;
+            tx.execute_delete_user(1, None).await?;
+            Ok(())
+        })
+    })
+    .await;
+    assert_eq!(result.unwrap_err().to_string(), "deadlock");
+}
+
+#[tokio::test]
+async fn test_failed_begin_runs_nothing() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_begin()
+        .times(1)
+        .returning(|| Err(anyhow!("pool timed out")));
+    let pool = DbPool::Mock(mock_db);
+
+    let result: anyhow::Result<()> = in_transaction(&pool, |_| {
+        Box::pin(async { panic!("work ran without a transaction") })
+    })
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_cached_reads_are_invalidated_on_commit_only() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db
+        .expect_execute_get_users()
+        .times(2)
+        .returning(|| Ok(users()));
+    let mut transactions = vec![transaction(1, true), transaction(1, false)];
+    mock_db
+        .expect_begin()
+        .times(2)
+        .returning(move || Ok(Box::new(transactions.pop().unwrap()) as Box<dyn Transaction>));
+    let executor = CachedExecutor::new(
+        DbPool::Mock(mock_db),
+        Box::new(InMemoryCache::new(10)),
+        Duration::from_secs(30),
+    );
+    executor.execute_get_users().await.unwrap();
+    // Rolled back, the cached users are still right.
+    let rolled_back: anyhow::Result<()> = in_transaction(&executor, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("Rolled back".to_string(), None)
+                .await?;
+            Err(anyhow!("abort"))
+        })
+    })
+    .await;
+    assert!(rolled_back.is_err());
+    executor.execute_get_users().await.unwrap();
+    assert_eq!(executor.metrics().hits(), 1);
+
+    in_transaction(&executor, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("Committed".to_string(), None)
+                .await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    executor.execute_get_users().await.unwrap();
+    assert_eq!(executor.metrics().misses(), 2);
+}
+
+#[tokio::test]
+async fn test_transaction_pins_reads_to_primary() {
+    let mut replica = MockDatabaseExecutor::new();
+    replica.expect_execute_get_users().times(0);
+    let mut primary = beginning(transaction(1, true));
+    primary
+        .expect_execute_get_users()
+        .times(1)
+        .returning(|| Ok(users()));
+    let executor = ReplicatedExecutor::new(
+        DbPool::Mock(primary),
+        vec![Replica::new("replica-1", DbPool::Mock(replica))],
+    );
+
+    tracking_writes(async {
+        in_transaction(&executor, |tx| {
+            Box::pin(async move {
+                tx.execute_create_user("Someone".to_string(), None).await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+        executor.execute_get_users().await.unwrap();
+    })
+    .await;
+}
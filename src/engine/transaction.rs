@@ -0,0 +1,85 @@
+use crate::engine::db_engine::DatabaseExecutor;
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::warn;
+
+/// Repository operations sharing one database transaction, started by `DatabaseExecutor::begin`.
+/// Nothing is applied before `commit`, and dropping it without committing rolls it back.
+#[async_trait::async_trait]
+pub trait Transaction: DatabaseExecutor {
+    async fn commit(self: Box<Self>) -> Result<()>;
+    async fn rollback(self: Box<Self>) -> Result<()>;
+}
+
+pub type TransactionFuture<'t, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 't>>;
+
+/// Runs `work` in a transaction, committed when it succeeds and rolled back when it fails:
+///
+/// ```ignore
+/// in_transaction(state.db_pool.as_ref(), move |tx| Box::pin(async move {
+///     tx.execute_create_user(first, None).await?;
+///     tx.execute_create_user(second, None).await
+/// }))
+/// .await
+/// ```
+pub async fn in_transaction<T, F>(executor: &dyn DatabaseExecutor, work: F) -> Result<T>
+where
+    F: for<'t> FnOnce(&'t dyn Transaction) -> TransactionFuture<'t, T>,
+{
+    let transaction = executor.begin().await?;
+    let result = work(transaction.as_ref()).await;
+    match result {
+        Ok(value) => {
+            transaction.commit().await?;
+            Ok(value)
+        }
+        Err(err) => {
+            if let Err(rollback_err) = transaction.rollback().await {
+                warn!("failed to roll back transaction: {:#}", rollback_err);
+            }
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mockall::mock! {
+    pub DatabaseTransaction {}
+
+    #[async_trait::async_trait]
+    impl DatabaseExecutor for DatabaseTransaction {
+        async fn execute_get_users(&self) -> Result<Vec<crate::domain::database::User>>;
+        async fn execute_get_all_users(&self) -> Result<Vec<crate::domain::database::User>>;
+        async fn execute_create_user(
+            &self,
+            name: String,
+            created_by: Option<String>,
+        ) -> Result<crate::domain::database::User>;
+        async fn execute_create_users(
+            &self,
+            names: Vec<String>,
+            created_by: Option<String>,
+        ) -> Result<Vec<crate::domain::database::User>>;
+        async fn execute_get_user(&self, uid: i32) -> Result<Option<crate::domain::database::User>>;
+        async fn execute_update_user(
+            &self,
+            uid: i32,
+            name: String,
+            expected_version: Option<i32>,
+        ) -> Result<Option<crate::domain::database::User>>;
+        async fn execute_delete_user(
+            &self,
+            uid: i32,
+            expected_version: Option<i32>,
+        ) -> Result<bool>;
+        async fn execute_restore_user(&self, uid: i32) -> Result<Option<crate::domain::database::User>>;
+        async fn begin(&self) -> Result<Box<dyn Transaction>>;
+    }
+
+    #[async_trait::async_trait]
+    impl Transaction for DatabaseTransaction {
+        async fn commit(self: Box<Self>) -> Result<()>;
+        async fn rollback(self: Box<Self>) -> Result<()>;
+    }
+}
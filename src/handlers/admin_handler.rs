@@ -0,0 +1,153 @@
+use crate::database::pool::PoolStats;
+use crate::engine::db_engine::DbPool;
+use crate::middleware::load_shed::ScopeStats;
+use crate::modules::EnabledModules;
+use crate::server::connection::ClientIdentity;
+use crate::state::AppState;
+use axum::Json;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::convert::Infallible;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// `ADMIN_TOKEN`, read whether or not the admin module is mounted.
+pub fn admin_token_from_env() -> Option<String> {
+    std::env::var("ADMIN_TOKEN").ok()
+}
+
+/// Whether `headers` carry `Authorization: Bearer <ADMIN_TOKEN>`, compared in constant time.
+/// Never without a token in the state.
+pub fn is_admin(headers: &HeaderMap, state: &AppState) -> bool {
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match (&state.admin_token, presented) {
+        (Some(token), Some(presented)) => presented.as_bytes().ct_eq(token.as_bytes()).into(),
+        _ => false,
+    }
+}
+
+/// Proof that the request carries `Authorization: Bearer <ADMIN_TOKEN>`.
+/// Fails closed when the state has no token.
+pub struct AdminAuth;
+
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        if is_admin(&parts.headers, state) {
+            return Ok(AdminAuth);
+        }
+        warn!("admin route called without a valid token");
+        Err((StatusCode::UNAUTHORIZED, "Invalid or missing admin token").into_response())
+    }
+}
+
+/// Who a write is recorded as made by: the common name of a verified client certificate
+/// (its subject without one), `admin` for the admin token, nobody otherwise.
+pub struct Actor(pub Option<String>);
+
+impl FromRequestParts<AppState> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Infallible> {
+        let identity = <ClientIdentity as OptionalFromRequestParts<AppState>>::from_request_parts(
+            parts, state,
+        )
+        .await?;
+        if let Some(identity) = identity {
+            return Ok(Actor(Some(
+                identity.common_name.unwrap_or(identity.subject),
+            )));
+        }
+        Ok(Actor(
+            is_admin(&parts.headers, state).then(|| "admin".to_string()),
+        ))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/modules",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Names of the enabled modules", body = [String]),
+        (status = 401, description = "Invalid or missing admin token"),
+    )
+)]
+pub async fn get_modules(_: AdminAuth, State(state): State<AppState>) -> Response {
+    let modules = state
+        .extensions
+        .get::<EnabledModules>()
+        .map(|modules| modules.0.clone())
+        .unwrap_or_default();
+    (StatusCode::OK, Json(modules)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/cache",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Query cache counters since startup", body = CacheStats),
+        (status = 401, description = "Invalid or missing admin token"),
+    )
+)]
+pub async fn get_cache_stats(_: AdminAuth, State(state): State<AppState>) -> Response {
+    let stats = match state.db_pool.as_ref() {
+        DbPool::Cached(cached) => CacheStats {
+            enabled: true,
+            hits: cached.metrics().hits(),
+            misses: cached.metrics().misses(),
+        },
+        _ => CacheStats {
+            enabled: false,
+            hits: 0,
+            misses: 0,
+        },
+    };
+    (StatusCode::OK, Json(stats)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/load",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "In-flight, queued and shed requests of every concurrency limit", body = [ScopeStats]),
+        (status = 401, description = "Invalid or missing admin token"),
+    )
+)]
+pub async fn get_load(_: AdminAuth, State(state): State<AppState>) -> Response {
+    (StatusCode::OK, Json(state.load_shedder.stats())).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/db/pool",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Size, idle connections and acquire latency of the database pools", body = [PoolStats]),
+        (status = 401, description = "Invalid or missing admin token"),
+    )
+)]
+pub async fn get_db_pool(_: AdminAuth, State(state): State<AppState>) -> Response {
+    (StatusCode::OK, Json(state.db_pool.pool_stats())).into_response()
+}
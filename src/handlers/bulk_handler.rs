@@ -0,0 +1,463 @@
+use crate::domain::database::{NewUser, User};
+use crate::engine::db_engine::{create_users_db_call, stream_users_db_call};
+use crate::handlers::admin_handler::Actor;
+use crate::handlers::negotiation::{CsvRecord, essence, preferred_format};
+use crate::handlers::validation::{FieldError, Problem};
+use crate::state::AppState;
+use anyhow::Result;
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, http::HeaderValue};
+use futures::{Stream, StreamExt, stream};
+use serde::Serialize;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// Users inserted per `execute_create_users` call, each batch commits or fails on its own.
+pub const IMPORT_BATCH_SIZE: usize = 500;
+/// Users serialized per chunk of an export body.
+pub const EXPORT_CHUNK_SIZE: usize = 100;
+
+/// Body formats of the import and export endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BulkFormat {
+    /// One array of users.
+    Json,
+    /// One user per line.
+    Ndjson,
+    /// A header row naming the columns, then one user per row.
+    Csv,
+}
+
+impl BulkFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => "application/json",
+            BulkFormat::Ndjson => "application/x-ndjson",
+            BulkFormat::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BulkFormat::Json => "json",
+            BulkFormat::Ndjson => "ndjson",
+            BulkFormat::Csv => "csv",
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match essence(media_type).as_str() {
+            "application/json" => Some(BulkFormat::Json),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" => {
+                Some(BulkFormat::Ndjson)
+            }
+            "text/csv" => Some(BulkFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, JSON without a `Content-Type`.
+    pub fn of_content_type(headers: &HeaderMap) -> Option<Self> {
+        match headers.get(header::CONTENT_TYPE) {
+            None => Some(BulkFormat::Json),
+            Some(value) => Self::from_media_type(value.to_str().ok()?),
+        }
+    }
+
+    /// Preferred format of `Accept` by quality, JSON without one or for wildcards.
+    pub fn of_accept(headers: &HeaderMap) -> Option<Self> {
+        preferred_format(
+            headers,
+            &[BulkFormat::Json, BulkFormat::Ndjson, BulkFormat::Csv],
+            |format| format.content_type(),
+            Self::from_media_type,
+        )
+    }
+}
+
+/// A parsed import row, or why it was rejected.
+pub type ImportRow = std::result::Result<NewUser, Vec<FieldError>>;
+
+fn parse_error(message: impl ToString) -> Vec<FieldError> {
+    vec![FieldError {
+        field: String::new(),
+        code: "parse".to_string(),
+        message: message.to_string(),
+    }]
+}
+
+fn validated(user: NewUser) -> ImportRow {
+    match user.validate() {
+        Ok(()) => Ok(user),
+        Err(errors) => Err(Problem::from(errors).errors),
+    }
+}
+
+/// Rows of an import body, numbered from 1 in the order they appear.
+/// Only a body that cannot be split into rows is rejected as a whole.
+pub fn parse_import(
+    format: BulkFormat,
+    body: &[u8],
+) -> std::result::Result<Vec<ImportRow>, Problem> {
+    match format {
+        BulkFormat::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_slice(body).map_err(|err| {
+                Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("body must be a JSON array of users: {}", err),
+                )
+            })?;
+            Ok(values
+                .into_iter()
+                .map(|value| {
+                    serde_json::from_value(value)
+                        .map_err(parse_error)
+                        .and_then(validated)
+                })
+                .collect())
+        }
+        BulkFormat::Ndjson => {
+            let body = std::str::from_utf8(body).map_err(|err| {
+                Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("body must be UTF-8: {}", err),
+                )
+            })?;
+            Ok(body
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    serde_json::from_str(line)
+                        .map_err(parse_error)
+                        .and_then(validated)
+                })
+                .collect())
+        }
+        BulkFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(body);
+            let name_column = reader
+                .headers()
+                .map_err(|err| {
+                    Problem::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("unreadable CSV header: {}", err),
+                    )
+                })?
+                .iter()
+                .position(|column| column.eq_ignore_ascii_case("name"))
+                .ok_or_else(|| {
+                    Problem::new(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "CSV header must have a name column",
+                    )
+                })?;
+            Ok(reader
+                .records()
+                .map(|record| {
+                    let record = record.map_err(parse_error)?;
+                    let name = record
+                        .get(name_column)
+                        .ok_or_else(|| parse_error("missing name column"))?;
+                    validated(NewUser {
+                        name: name.to_string(),
+                    })
+                })
+                .collect())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RowStatus {
+    Created,
+    /// Rejected before reaching the database, see `errors`.
+    Invalid,
+    /// Its batch failed to insert, see `error`.
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct RowResult {
+    /// Position in the body, from 1, CSV header excluded.
+    pub row: usize,
+    pub status: RowStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of every row of an import.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct ImportReport {
+    pub created: usize,
+    pub invalid: usize,
+    pub failed: usize,
+    pub results: Vec<RowResult>,
+}
+
+/// Inserts the valid rows in batches of `IMPORT_BATCH_SIZE`. A failed batch fails its own rows
+/// only, the batches before it stay committed and the next ones are still tried.
+pub async fn import_rows(
+    state: &AppState,
+    rows: Vec<ImportRow>,
+    created_by: Option<String>,
+) -> ImportReport {
+    let mut results = Vec::with_capacity(rows.len());
+    let mut valid = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        match row {
+            Ok(user) => valid.push((index + 1, user.name)),
+            Err(errors) => results.push(RowResult {
+                row: index + 1,
+                status: RowStatus::Invalid,
+                uid: None,
+                errors,
+                error: None,
+            }),
+        }
+    }
+
+    for batch in valid.chunks(IMPORT_BATCH_SIZE) {
+        let names = batch.iter().map(|(_, name)| name.clone()).collect();
+        match create_users_db_call(State(state.clone()), names, created_by.clone()).await {
+            Ok(users) => {
+                results.extend(batch.iter().zip(users).map(|((row, _), user)| RowResult {
+                    row: *row,
+                    status: RowStatus::Created,
+                    uid: Some(user.uid),
+                    errors: Vec::new(),
+                    error: None,
+                }))
+            }
+            Err(err) => {
+                error!("import batch of {} users failed: {:#}", batch.len(), err);
+                results.extend(batch.iter().map(|(row, _)| RowResult {
+                    row: *row,
+                    status: RowStatus::Failed,
+                    uid: None,
+                    errors: Vec::new(),
+                    error: Some(format!("failed to insert batch: {}", err)),
+                }));
+            }
+        }
+    }
+
+    results.sort_by_key(|result| result.row);
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.status == status)
+            .count()
+    };
+    ImportReport {
+        created: count(RowStatus::Created),
+        invalid: count(RowStatus::Invalid),
+        failed: count(RowStatus::Failed),
+        results,
+    }
+}
+
+/// Creates users from a JSON array, NDJSON or CSV body (with a `name` column), chosen by
+/// `Content-Type`. Answers `201` when every row was created, `207` with the failed rows otherwise.
+#[utoipa::path(
+    post,
+    path = "/users/bulk",
+    tag = "users",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the first response to retries")),
+    request_body(content(
+        ([NewUser] = "application/json"),
+        (String = "application/x-ndjson"),
+        (String = "text/csv"),
+    )),
+    responses(
+        (status = 201, description = "Every row created", body = ImportReport),
+        (status = 207, description = "Some rows invalid or failed", body = ImportReport),
+        (status = 400, description = "Body cannot be split into rows", body = Problem, content_type = "application/problem+json"),
+        (status = 415, description = "Unsupported Content-Type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "No rows, or CSV without a name column", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn import_users(
+    State(state): State<AppState>,
+    Actor(actor): Actor,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(format) = BulkFormat::of_content_type(&headers) else {
+        return Problem::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/json, application/x-ndjson or text/csv",
+        )
+        .into_response();
+    };
+    let rows = match parse_import(format, &body) {
+        Ok(rows) if rows.is_empty() => {
+            return Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "no users to import")
+                .into_response();
+        }
+        Ok(rows) => rows,
+        Err(problem) => return problem.into_response(),
+    };
+    info!("importing {} users as {}", rows.len(), format.extension());
+
+    let report = import_rows(&state, rows, actor).await;
+    if report.invalid + report.failed == 0 {
+        (StatusCode::CREATED, Json(report)).into_response()
+    } else {
+        warn!(
+            "import created {} users, {} invalid and {} failed",
+            report.created, report.invalid, report.failed
+        );
+        (StatusCode::MULTI_STATUS, Json(report)).into_response()
+    }
+}
+
+/// Serializes users one at a time, so an export never holds its whole body.
+pub struct UserEncoder {
+    format: BulkFormat,
+    encoded: usize,
+}
+
+impl UserEncoder {
+    pub fn new(format: BulkFormat) -> Self {
+        UserEncoder { format, encoded: 0 }
+    }
+
+    /// Bytes before the first user.
+    pub fn start(&self) -> Vec<u8> {
+        match self.format {
+            BulkFormat::Json => b"[".to_vec(),
+            BulkFormat::Ndjson => Vec::new(),
+            BulkFormat::Csv => {
+                let mut bytes = User::HEADER.join(",").into_bytes();
+                bytes.push(b'\n');
+                bytes
+            }
+        }
+    }
+
+    pub fn user(&mut self, user: &User) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self.format {
+            BulkFormat::Json => {
+                if self.encoded > 0 {
+                    bytes.push(b',');
+                }
+                serde_json::to_writer(&mut bytes, user)?;
+            }
+            BulkFormat::Ndjson => {
+                serde_json::to_writer(&mut bytes, user)?;
+                bytes.push(b'\n');
+            }
+            BulkFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut bytes);
+                writer.write_record(user.record()?)?;
+                writer.flush()?;
+            }
+        }
+        self.encoded += 1;
+        Ok(bytes)
+    }
+
+    /// Bytes after the last user.
+    pub fn finish(&self) -> Vec<u8> {
+        match self.format {
+            BulkFormat::Json => b"]".to_vec(),
+            BulkFormat::Ndjson | BulkFormat::Csv => Vec::new(),
+        }
+    }
+}
+
+/// Chunks of an export body, up to `EXPORT_CHUNK_SIZE` users each, encoded as they arrive.
+/// A failing user ends the body early, as the status was already sent.
+pub fn export_stream(
+    format: BulkFormat,
+    users: impl Stream<Item = Result<User>> + Send + 'static,
+) -> impl Stream<Item = Result<Vec<u8>>> + Send {
+    let mut encoder = UserEncoder::new(format);
+    let start = encoder.start();
+    let finish = encoder.finish();
+    let users = users.ready_chunks(EXPORT_CHUNK_SIZE).map(move |chunk| {
+        let mut bytes = Vec::new();
+        for user in chunk {
+            let user = user.inspect_err(|err| error!("export interrupted: {:#}", err))?;
+            bytes.extend(encoder.user(&user)?);
+        }
+        Ok(bytes)
+    });
+    stream::once(async { Ok(start) })
+        .chain(users)
+        .chain(stream::once(async { Ok(finish) }))
+}
+
+pub fn attachment(format: BulkFormat, name: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}.{}\"",
+        name,
+        format.extension()
+    ))
+    .expect("file names are ASCII")
+}
+
+/// Downloads every user as JSON, NDJSON or CSV, chosen by `Accept`, streamed from the database
+/// in a chunked body so memory stays flat whatever the table size.
+#[utoipa::path(
+    get,
+    path = "/users/export",
+    tag = "users",
+    responses(
+        (status = 200, description = "All users", content(
+            ([User] = "application/json"),
+            (String = "application/x-ndjson"),
+            (String = "text/csv"),
+        )),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Database failure", body = String),
+    )
+)]
+pub async fn export_users(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(format) = BulkFormat::of_accept(&headers) else {
+        return Problem::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "Accept must allow application/json, application/x-ndjson or text/csv",
+        )
+        .into_response();
+    };
+    match stream_users_db_call(State(state), false).await {
+        Ok(users) => {
+            info!("exporting users as {}", format.extension());
+            (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(format.content_type()),
+                    ),
+                    (header::CONTENT_DISPOSITION, attachment(format, "users")),
+                ],
+                Body::from_stream(export_stream(format, users)),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            error!("Error occurred: {}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to export users: {}", err),
+            )
+                .into_response()
+        }
+    }
+}
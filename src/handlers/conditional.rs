@@ -0,0 +1,198 @@
+use crate::domain::database::{User, UsersStamp};
+use crate::handlers::negotiation::MediaFormat;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Validators of a representation, as sent in `ETag` and `Last-Modified`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// Strong entity tag, quotes included, marked with the format unless JSON.
+    pub etag: String,
+    /// Whole seconds, the precision of HTTP dates.
+    pub last_modified: Option<SystemTime>,
+    /// Format negotiated from `Accept`, whose responses then vary on it.
+    pub format: Option<MediaFormat>,
+}
+
+/// Strong ETag over the JSON representation of `value`.
+pub fn strong_etag<T: Serialize>(value: &T) -> String {
+    let body = serde_json::to_vec(value).expect("representation must serialize");
+    format!("\"{:x}\"", Sha256::digest(&body))
+}
+
+/// Marks the tags of the formats other than JSON, so that each representation gets its own.
+fn etag_suffix(format: MediaFormat) -> Option<&'static str> {
+    match format {
+        MediaFormat::Json => None,
+        MediaFormat::MessagePack => Some("msgpack"),
+        MediaFormat::Cbor => Some("cbor"),
+        MediaFormat::Csv => Some("csv"),
+    }
+}
+
+/// The tag of the JSON representation a tag of any format was derived from.
+fn json_etag(etag: &str) -> String {
+    MediaFormat::ALL
+        .into_iter()
+        .filter_map(etag_suffix)
+        .find_map(|suffix| etag.strip_suffix(&format!("-{}\"", suffix)))
+        .map_or_else(|| etag.to_string(), |base| format!("{}\"", base))
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn http_date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+/// Whether an `If-Match` / `If-None-Match` list matches `etag`.
+/// Weak comparison ignores the `W/` prefix, strong comparison never matches weak tags.
+/// With `any_format`, the tag of any representation of the same state matches.
+fn etag_list_matches(list: &str, etag: &str, weak: bool, any_format: bool) -> bool {
+    let same = |candidate: &str| match any_format {
+        true => json_etag(candidate) == json_etag(etag),
+        false => candidate == etag,
+    };
+    list.split(',').map(str::trim).any(|candidate| {
+        if candidate == "*" {
+            return true;
+        }
+        match candidate.strip_prefix("W/") {
+            Some(candidate) => weak && same(candidate),
+            None => same(candidate),
+        }
+    })
+}
+
+impl Validators {
+    /// Validators of a user list, from its stamp rather than from the users themselves.
+    pub fn of_users(stamp: &UsersStamp) -> Self {
+        Validators {
+            etag: strong_etag(stamp),
+            last_modified: stamp
+                .updated_at
+                .map(|updated_at| truncate_to_secs(updated_at.into())),
+            format: None,
+        }
+    }
+
+    pub fn of_user(user: &User) -> Self {
+        Validators {
+            etag: strong_etag(user),
+            last_modified: user
+                .updated_at
+                .map(|updated_at| truncate_to_secs(updated_at.into())),
+            format: None,
+        }
+    }
+
+    /// Validators of the representation in `format`, whose tag differs from the other formats'.
+    pub fn in_format(self, format: MediaFormat) -> Self {
+        let etag = match etag_suffix(format) {
+            Some(suffix) => format!(
+                "{}-{}\"",
+                json_etag(&self.etag).trim_end_matches('"'),
+                suffix
+            ),
+            None => json_etag(&self.etag),
+        };
+        Validators {
+            etag,
+            format: Some(format),
+            ..self
+        }
+    }
+
+    /// Also lets clients keep a private copy, revalidated on each use, in place of the
+    /// API-wide `no-store`.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-cache"),
+        );
+        headers.insert(
+            header::ETAG,
+            HeaderValue::from_str(&self.etag).expect("etag is a valid header value"),
+        );
+        if let Some(last_modified) = self.last_modified {
+            headers.insert(
+                header::LAST_MODIFIED,
+                HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))
+                    .expect("http date is a valid header value"),
+            );
+        }
+    }
+
+    /// `If-None-Match`, or `If-Modified-Since` when the former is absent, says the client copy is current.
+    pub fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(list) = headers.get(header::IF_NONE_MATCH) {
+            return list
+                .to_str()
+                .is_ok_and(|list| etag_list_matches(list, &self.etag, true, false));
+        }
+        match (
+            self.last_modified,
+            http_date(headers, header::IF_MODIFIED_SINCE),
+        ) {
+            (Some(last_modified), Some(since)) => last_modified <= since,
+            _ => false,
+        }
+    }
+
+    /// `If-Match`, or `If-Unmodified-Since` when the former is absent, holds for this state.
+    /// An `ETag` read in any format matches, as the write replaces every representation.
+    pub fn precondition_holds(&self, headers: &HeaderMap) -> bool {
+        if let Some(list) = headers.get(header::IF_MATCH) {
+            return list
+                .to_str()
+                .is_ok_and(|list| etag_list_matches(list, &self.etag, false, true));
+        }
+        match (
+            self.last_modified,
+            http_date(headers, header::IF_UNMODIFIED_SINCE),
+        ) {
+            (Some(last_modified), Some(since)) => last_modified <= since,
+            (None, Some(_)) => false,
+            _ => true,
+        }
+    }
+
+    /// Keeps the `Vary: Accept` the full response would have had.
+    pub fn not_modified_response(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(response.headers_mut());
+        if self.format.is_some() {
+            response
+                .headers_mut()
+                .append(header::VARY, HeaderValue::from_static("accept"));
+        }
+        response
+    }
+
+    pub fn precondition_failed_response(&self) -> Response {
+        let mut response = precondition_failed();
+        self.apply(response.headers_mut());
+        response
+    }
+}
+
+/// True when the request carries `If-Match` or `If-Unmodified-Since`.
+pub fn has_preconditions(headers: &HeaderMap) -> bool {
+    headers.contains_key(header::IF_MATCH) || headers.contains_key(header::IF_UNMODIFIED_SINCE)
+}
+
+pub fn precondition_failed() -> Response {
+    (StatusCode::PRECONDITION_FAILED, "Precondition Failed").into_response()
+}
@@ -1,43 +1,377 @@
-use crate::domain::database::NewUser;
-use crate::engine::db_engine::{create_user_db_call, get_users_db_call};
+use crate::domain::database::{ListUsers, NewUser, User, UserId};
+use crate::engine::db_engine::{
+    UserStream, create_user_db_call, delete_user_db_call, get_user_db_call,
+    get_users_stamp_db_call, restore_user_db_call, stream_users_db_call, update_user_db_call,
+};
+use crate::engine::replicas::reading_primary;
+use crate::handlers::admin_handler::{Actor, AdminAuth, is_admin};
+use crate::handlers::bulk_handler::{BulkFormat, export_stream};
+use crate::handlers::conditional::{Validators, has_preconditions, precondition_failed};
+use crate::handlers::negotiation::{AcceptedFormat, MediaFormat, Negotiated, ValidatedBody};
+use crate::handlers::validation::{Problem, ValidatedPath, ValidatedQuery};
 use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::OriginalUri;
+use axum::http::{HeaderMap, HeaderValue, header};
 use axum::response::{IntoResponse, Response};
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{extract::State, http::StatusCode};
+use futures::TryStreamExt;
 use tracing::{error, info, warn};
 
-pub async fn get_users(State(state): State<AppState>) -> Response {
-    info!("get_users called");
-    let vec_users = get_users_db_call(State(state)).await;
-    match vec_users {
+/// Lists the users in the format `Accept` prefers, streamed from the database, with an
+/// `ETag` and `Last-Modified` for conditional polling, taken from the stamp of the list.
+/// Soft-deleted users are only listed to admins asking for them.
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    params(ListUsers),
+    security((), ("admin_token" = [])),
+    responses(
+        (status = 200, description = "All users", content(
+            ([User] = "application/json"),
+            ([User] = "application/msgpack"),
+            ([User] = "application/cbor"),
+            (String = "text/csv"),
+        )),
+        (status = 304, description = "The client copy is current"),
+        (status = 401, description = "include_deleted without the admin token"),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Database failure", body = String),
+    )
+)]
+pub async fn get_users(
+    State(state): State<AppState>,
+    AcceptedFormat(format): AcceptedFormat,
+    ValidatedQuery(ListUsers { include_deleted }): ValidatedQuery<ListUsers>,
+    headers: HeaderMap,
+) -> Response {
+    info!("get_users called, include_deleted: {}", include_deleted);
+    if include_deleted && !is_admin(&headers, &state) {
+        warn!("deleted users requested without a valid admin token");
+        return (StatusCode::UNAUTHORIZED, "Invalid or missing admin token").into_response();
+    }
+    let validators = match get_users_stamp_db_call(State(state.clone()), include_deleted).await {
+        Ok(stamp) => Validators::of_users(&stamp).in_format(format),
+        Err(err) => return get_users_failed(err),
+    };
+    if validators.not_modified(&headers) {
+        return validators.not_modified_response();
+    }
+    // From the primary, never behind the replica the stamp may have been read from,
+    // so the users are never older than their validators.
+    match reading_primary(stream_users_db_call(State(state), include_deleted)).await {
         Ok(users) => {
             warn!("Users returned");
-            (StatusCode::OK, Json(users)).into_response()
+            let mut response = users_response(format, users).await;
+            if response.status() == StatusCode::OK {
+                validators.apply(response.headers_mut());
+            }
+            response
+        }
+        Err(err) => get_users_failed(err),
+    }
+}
+
+/// JSON and CSV lists are encoded as the users arrive. MessagePack and CBOR arrays start
+/// with their length, so those users are collected first.
+async fn users_response(format: MediaFormat, users: UserStream) -> Response {
+    let bulk_format = match format {
+        MediaFormat::Json => BulkFormat::Json,
+        MediaFormat::Csv => BulkFormat::Csv,
+        MediaFormat::MessagePack | MediaFormat::Cbor => {
+            return match users.try_collect::<Vec<User>>().await {
+                Ok(users) => (StatusCode::OK, Negotiated(format, users)).into_response(),
+                Err(err) => get_users_failed(err),
+            };
+        }
+    };
+    let mut response = (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        )],
+        Body::from_stream(export_stream(bulk_format, users)),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+fn get_users_failed(err: anyhow::Error) -> Response {
+    error!("Error occurred: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("failed to get users: {}", err).into_response(),
+    )
+        .into_response()
+}
+
+/// Creates a user, answering it with its `Location` under the path it was posted to.
+/// `created_by` records the caller's client certificate, or `admin` for the admin token.
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the first response to retries")),
+    request_body(content(
+        (NewUser = "application/json"),
+        (NewUser = "application/msgpack"),
+        (NewUser = "application/cbor"),
+        (NewUser = "text/csv"),
+    )),
+    responses(
+        (status = 201, description = "User created", content(
+            (User = "application/json"),
+            (User = "application/msgpack"),
+            (User = "application/cbor"),
+            (String = "text/csv"),
+        ),
+            headers(("Location" = String, description = "URL of the created user"))),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Same Idempotency-Key still in progress"),
+        (status = 415, description = "Unsupported Content-Type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid payload, or Idempotency-Key reused", body = Problem, content_type = "application/problem+json"),
+        (status = 500, description = "Database failure", body = String),
+    )
+)]
+pub async fn create_user(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Actor(actor): Actor,
+    AcceptedFormat(format): AcceptedFormat,
+    ValidatedBody(payload): ValidatedBody<NewUser>,
+) -> Response {
+    info!("create_user called with params: {:?}", payload);
+    match create_user_db_call(State(state), payload.name.clone(), actor).await {
+        Ok(user) => {
+            warn!("New user created with uid {}", user.uid);
+            let location = format!("{}/{}", uri.path().trim_end_matches('/'), user.uid);
+            let validators = Validators::of_user(&user).in_format(format);
+            let mut response = (
+                StatusCode::CREATED,
+                [(header::LOCATION, location)],
+                Negotiated(format, user),
+            )
+                .into_response();
+            validators.apply(response.headers_mut());
+            response
         }
         Err(err) => {
             error!("Error occurred: {}", err);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("failed to get users: {}", err).into_response(),
+                format!("failed to insert user: {}", err).into_response(),
             )
                 .into_response()
         }
     }
 }
 
-pub async fn create_user(State(state): State<AppState>, Json(payload): Json<NewUser>) -> Response {
-    info!("create_user called with params: {:?}", payload);
-    match create_user_db_call(State(state), payload.name.clone()).await {
-        Ok(_) => {
-            warn!("New user created");
-            (StatusCode::CREATED, "CREATED").into_response()
+#[utoipa::path(
+    get,
+    path = "/users/{uid}",
+    tag = "users",
+    params(UserId),
+    responses(
+        (status = 200, description = "The user", content(
+            (User = "application/json"),
+            (User = "application/msgpack"),
+            (User = "application/cbor"),
+            (String = "text/csv"),
+        )),
+        (status = 304, description = "The client copy is current"),
+        (status = 404, description = "No such user"),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_user(
+    State(state): State<AppState>,
+    ValidatedPath(UserId { uid }): ValidatedPath<UserId>,
+    AcceptedFormat(format): AcceptedFormat,
+    headers: HeaderMap,
+) -> Response {
+    info!("get_user called with uid: {}", uid);
+    match get_user_db_call(State(state), uid).await {
+        Ok(Some(user)) => {
+            let validators = Validators::of_user(&user).in_format(format);
+            if validators.not_modified(&headers) {
+                return validators.not_modified_response();
+            }
+            let mut response = (StatusCode::OK, Negotiated(format, user)).into_response();
+            validators.apply(response.headers_mut());
+            response
         }
+        Ok(None) => (StatusCode::NOT_FOUND, "Not Found").into_response(),
         Err(err) => {
             error!("Error occurred: {}", err);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("failed to insert user: {}", err).into_response(),
+                format!("failed to get user: {}", err),
             )
                 .into_response()
         }
     }
 }
+
+#[utoipa::path(
+    put,
+    path = "/users/{uid}",
+    tag = "users",
+    params(UserId, ("If-Match" = Option<String>, Header, description = "ETag the update is based on")),
+    request_body(content(
+        (NewUser = "application/json"),
+        (NewUser = "application/msgpack"),
+        (NewUser = "application/cbor"),
+        (NewUser = "text/csv"),
+    )),
+    responses(
+        (status = 200, description = "The updated user", content(
+            (User = "application/json"),
+            (User = "application/msgpack"),
+            (User = "application/cbor"),
+            (String = "text/csv"),
+        )),
+        (status = 404, description = "No such user"),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The user changed since the given ETag"),
+        (status = 415, description = "Unsupported Content-Type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid payload", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_user(
+    State(state): State<AppState>,
+    ValidatedPath(UserId { uid }): ValidatedPath<UserId>,
+    AcceptedFormat(format): AcceptedFormat,
+    headers: HeaderMap,
+    ValidatedBody(payload): ValidatedBody<NewUser>,
+) -> Response {
+    info!(
+        "update_user called with uid: {} and params: {:?}",
+        uid, payload
+    );
+    let expected_version = if has_preconditions(&headers) {
+        match get_user_db_call(State(state.clone()), uid).await {
+            Ok(Some(current)) => {
+                let validators = Validators::of_user(&current).in_format(format);
+                if !validators.precondition_holds(&headers) {
+                    return validators.precondition_failed_response();
+                }
+                Some(current.version)
+            }
+            Ok(None) => return precondition_failed(),
+            Err(err) => return db_error("failed to update user", err),
+        }
+    } else {
+        None
+    };
+
+    match update_user_db_call(State(state), uid, payload.name, expected_version).await {
+        Ok(Some(user)) => {
+            warn!("User updated");
+            let validators = Validators::of_user(&user).in_format(format);
+            let mut response = (StatusCode::OK, Negotiated(format, user)).into_response();
+            validators.apply(response.headers_mut());
+            response
+        }
+        // Changed between the precondition check and the update.
+        Ok(None) if expected_version.is_some() => precondition_failed(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+        Err(err) => db_error("failed to update user", err),
+    }
+}
+
+/// Soft-deletes the user: it disappears from the API until restored.
+#[utoipa::path(
+    delete,
+    path = "/users/{uid}",
+    tag = "users",
+    params(UserId, ("If-Match" = Option<String>, Header, description = "ETag the deletion is based on")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 404, description = "No such user"),
+        (status = 412, description = "The user changed since the given ETag"),
+    )
+)]
+pub async fn delete_user(
+    State(state): State<AppState>,
+    ValidatedPath(UserId { uid }): ValidatedPath<UserId>,
+    headers: HeaderMap,
+) -> Response {
+    info!("delete_user called with uid: {}", uid);
+    let expected_version = if has_preconditions(&headers) {
+        match get_user_db_call(State(state.clone()), uid).await {
+            Ok(Some(current)) => {
+                let validators = Validators::of_user(&current);
+                if !validators.precondition_holds(&headers) {
+                    return validators.precondition_failed_response();
+                }
+                Some(current.version)
+            }
+            Ok(None) => return precondition_failed(),
+            Err(err) => return db_error("failed to delete user", err),
+        }
+    } else {
+        None
+    };
+
+    match delete_user_db_call(State(state), uid, expected_version).await {
+        Ok(true) => {
+            warn!("User deleted");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) if expected_version.is_some() => precondition_failed(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+        Err(err) => db_error("failed to delete user", err),
+    }
+}
+
+/// Undoes a `DELETE`, answering the user as it was before. Admins only.
+#[utoipa::path(
+    post,
+    path = "/users/{uid}/restore",
+    tag = "users",
+    params(UserId),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The restored user", content(
+            (User = "application/json"),
+            (User = "application/msgpack"),
+            (User = "application/cbor"),
+            (String = "text/csv"),
+        )),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "No such deleted user"),
+        (status = 406, description = "No acceptable format", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn restore_user(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    ValidatedPath(UserId { uid }): ValidatedPath<UserId>,
+    AcceptedFormat(format): AcceptedFormat,
+) -> Response {
+    info!("restore_user called with uid: {}", uid);
+    match restore_user_db_call(State(state), uid).await {
+        Ok(Some(user)) => {
+            warn!("User restored");
+            let validators = Validators::of_user(&user).in_format(format);
+            let mut response = (StatusCode::OK, Negotiated(format, user)).into_response();
+            validators.apply(response.headers_mut());
+            response
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+        Err(err) => db_error("failed to restore user", err),
+    }
+}
+
+fn db_error(context: &str, err: anyhow::Error) -> Response {
+    error!("Error occurred: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("{}: {}", context, err),
+    )
+        .into_response()
+}
@@ -0,0 +1,24 @@
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use tracing::warn;
+
+/// Readiness probe, failing as soon as a shutdown starts so traffic is routed elsewhere.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "health",
+    responses(
+        (status = 200, body = String),
+        (status = 503, description = "Shutdown in progress", body = String),
+    )
+)]
+pub async fn get_ready(State(state): State<AppState>) -> Response {
+    if state.lifecycle.is_ready() {
+        (StatusCode::OK, "READY").into_response()
+    } else {
+        warn!("readiness probe failing, shutdown in progress");
+        (StatusCode::SERVICE_UNAVAILABLE, "SHUTTING DOWN").into_response()
+    }
+}
@@ -1,5 +1,11 @@
+pub mod admin_handler;
+pub mod bulk_handler;
+pub mod conditional;
 pub mod db_handler;
+pub mod health_handler;
+pub mod negotiation;
 pub mod simple_handler;
+pub mod validation;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,359 @@
+use crate::domain::database::User;
+use crate::handlers::validation::{Problem, ValidatedJson};
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::error;
+use validator::Validate;
+
+/// Media ranges of `Accept` in the client's order, with their quality, `q=0` included.
+/// `None` when there is no `Accept`, which accepts anything.
+fn media_ranges(headers: &HeaderMap) -> Option<Vec<(String, f32)>> {
+    let accept = headers.get(header::ACCEPT)?;
+    Some(
+        accept
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .map(|range| {
+                let mut params = range.split(';');
+                let media_type = essence(params.next().unwrap_or_default());
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse().ok())
+                    .unwrap_or(1.0);
+                (media_type, quality)
+            })
+            .filter(|(media_type, _)| !media_type.is_empty())
+            .collect(),
+    )
+}
+
+/// The format of `offered` that `Accept` prefers, the first offered without `Accept`.
+/// A format takes the quality of the most specific range naming it, so `q=0` on its type
+/// excludes it whatever the wildcards allow. Ties go to the range listed first, then to
+/// the format offered first.
+pub fn preferred_format<F: Copy + PartialEq>(
+    headers: &HeaderMap,
+    offered: &[F],
+    content_type: impl Fn(F) -> &'static str,
+    from_media_type: impl Fn(&str) -> Option<F>,
+) -> Option<F> {
+    let Some(ranges) = media_ranges(headers) else {
+        return offered.first().copied();
+    };
+    let specificity = |range: &str, format: F| {
+        if from_media_type(range) == Some(format) {
+            Some(2)
+        } else if range == "*/*" {
+            Some(0)
+        } else {
+            let prefix = range
+                .strip_suffix('*')
+                .filter(|prefix| prefix.ends_with('/'))?;
+            content_type(format).starts_with(prefix).then_some(1)
+        }
+    };
+    offered
+        .iter()
+        .enumerate()
+        .filter_map(|(order, &format)| {
+            // The most specific range, the first one of those.
+            let (_, index, quality) = ranges
+                .iter()
+                .enumerate()
+                .filter_map(|(index, (range, quality))| {
+                    Some((specificity(range, format)?, index, *quality))
+                })
+                .min_by_key(|(specificity, index, _)| (-specificity, *index))?;
+            (quality > 0.0).then_some((quality, index, order, format))
+        })
+        .min_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)))
+        .map(|(_, _, _, format)| format)
+}
+
+/// `type/subtype` of a media type, lowercased and without parameters.
+pub fn essence(media_type: &str) -> String {
+    media_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Formats domain types are read and written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaFormat {
+    Json,
+    MessagePack,
+    Cbor,
+    /// A header row, then one row per value.
+    Csv,
+}
+
+impl MediaFormat {
+    pub const ALL: [MediaFormat; 4] = [
+        MediaFormat::Json,
+        MediaFormat::MessagePack,
+        MediaFormat::Cbor,
+        MediaFormat::Csv,
+    ];
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            MediaFormat::Json => "application/json",
+            MediaFormat::MessagePack => "application/msgpack",
+            MediaFormat::Cbor => "application/cbor",
+            MediaFormat::Csv => "text/csv",
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match essence(media_type).as_str() {
+            "application/json" => Some(MediaFormat::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(MediaFormat::MessagePack)
+            }
+            "application/cbor" => Some(MediaFormat::Cbor),
+            "text/csv" => Some(MediaFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, including `+json` types such as `application/merge-patch+json`.
+    pub fn of_content_type(headers: &HeaderMap) -> Option<Self> {
+        let media_type = essence(headers.get(header::CONTENT_TYPE)?.to_str().ok()?);
+        if media_type.starts_with("application/") && media_type.ends_with("+json") {
+            return Some(MediaFormat::Json);
+        }
+        Self::from_media_type(&media_type)
+    }
+
+    /// Preferred format of `Accept`, JSON without one or for wildcards.
+    pub fn of_accept(headers: &HeaderMap) -> Option<Self> {
+        preferred_format(
+            headers,
+            &MediaFormat::ALL,
+            |format| format.content_type(),
+            Self::from_media_type,
+        )
+    }
+
+    pub fn encode<T: Serialize + ToCsv + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            MediaFormat::Json => serde_json::to_vec(value)?,
+            MediaFormat::MessagePack => rmp_serde::to_vec_named(value)?,
+            MediaFormat::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+                bytes
+            }
+            MediaFormat::Csv => value.to_csv()?,
+        })
+    }
+
+    /// `ValidatedBody` leaves JSON to `ValidatedJson`, whose rejections tell syntax errors from invalid data.
+    fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T> {
+        Ok(match self {
+            MediaFormat::Json => serde_json::from_slice(body)?,
+            MediaFormat::MessagePack => rmp_serde::from_slice(body)?,
+            MediaFormat::Cbor => ciborium::from_reader(body)?,
+            MediaFormat::Csv => {
+                let mut records = csv::Reader::from_reader(body).into_deserialize();
+                let value = records
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("a CSV body needs a header and one row"))??;
+                if records.next().is_some() {
+                    anyhow::bail!("a CSV body holds a single row");
+                }
+                value
+            }
+        })
+    }
+}
+
+fn supported_types() -> String {
+    MediaFormat::ALL
+        .iter()
+        .map(|format| format.content_type())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Domain types written as one CSV row each.
+pub trait CsvRecord {
+    const HEADER: &'static [&'static str];
+    fn record(&self) -> Result<Vec<String>>;
+}
+
+impl CsvRecord for User {
+    const HEADER: &'static [&'static str] = &[
+        "uid",
+        "name",
+        "updated_at",
+        "created_at",
+        "deleted_at",
+        "created_by",
+        "version",
+    ];
+
+    // Field by field: serde skips missing fields, which would leave the row short of columns.
+    fn record(&self) -> Result<Vec<String>> {
+        let timestamp = |value: Option<OffsetDateTime>| -> Result<String> {
+            Ok(match value {
+                Some(value) => value.format(&Rfc3339)?,
+                None => String::new(),
+            })
+        };
+        Ok(vec![
+            self.uid.to_string(),
+            self.name.clone(),
+            timestamp(self.updated_at)?,
+            timestamp(self.created_at)?,
+            timestamp(self.deleted_at)?,
+            self.created_by.clone().unwrap_or_default(),
+            self.version.to_string(),
+        ])
+    }
+}
+
+/// A whole CSV body, the header row included.
+pub trait ToCsv {
+    fn to_csv(&self) -> Result<Vec<u8>>;
+}
+
+fn csv_body<'a, T: CsvRecord + 'a>(records: impl IntoIterator<Item = &'a T>) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(T::HEADER)?;
+    for record in records {
+        writer.write_record(record.record()?)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+impl<T: CsvRecord> ToCsv for T {
+    fn to_csv(&self) -> Result<Vec<u8>> {
+        csv_body([self])
+    }
+}
+
+impl<T: CsvRecord> ToCsv for [T] {
+    fn to_csv(&self) -> Result<Vec<u8>> {
+        csv_body(self)
+    }
+}
+
+impl<T: CsvRecord> ToCsv for Vec<T> {
+    fn to_csv(&self) -> Result<Vec<u8>> {
+        csv_body(self)
+    }
+}
+
+/// The response format `Accept` prefers, rejecting the request with 406 when none is offered.
+#[derive(Clone, Copy, Debug)]
+pub struct AcceptedFormat(pub MediaFormat);
+
+impl<S> FromRequestParts<S> for AcceptedFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Problem;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        MediaFormat::of_accept(&parts.headers)
+            .map(AcceptedFormat)
+            .ok_or_else(|| {
+                Problem::new(
+                    StatusCode::NOT_ACCEPTABLE,
+                    format!("Accept must allow one of {}", supported_types()),
+                )
+            })
+    }
+}
+
+/// `value` in the negotiated format, with `Vary: Accept` for caches.
+/// Validators are taken `in_format`, so each format has its own `ETag`.
+pub struct Negotiated<T>(pub MediaFormat, pub T);
+
+impl<T: Serialize + ToCsv> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        match format.encode(&value) {
+            Ok(body) => {
+                let mut response = (
+                    [(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(format.content_type()),
+                    )],
+                    body,
+                )
+                    .into_response();
+                response
+                    .headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("accept"));
+                response
+            }
+            Err(err) => {
+                error!("failed to encode {:?} response: {:#}", format, err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("failed to encode the response: {}", err),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// A body decoded by its `Content-Type`, then checked against the rules declared on `T`.
+/// Unsupported types are rejected with 415, like `Json` does for anything but JSON.
+#[derive(Debug)]
+pub struct ValidatedBody<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedBody<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Problem;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let value: T = match MediaFormat::of_content_type(request.headers()) {
+            Some(MediaFormat::Json) => {
+                let ValidatedJson(value) = ValidatedJson::from_request(request, state).await?;
+                return Ok(ValidatedBody(value));
+            }
+            Some(format) => {
+                let body = Bytes::from_request(request, state)
+                    .await
+                    .map_err(|rejection| Problem::new(rejection.status(), rejection.body_text()))?;
+                format.decode(&body).map_err(|err| {
+                    Problem::new(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "Failed to decode the {} body: {:#}",
+                            format.content_type(),
+                            err
+                        ),
+                    )
+                })?
+            }
+            None => {
+                return Err(Problem::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Content-Type must be one of {}", supported_types()),
+                ));
+            }
+        };
+        value.validate()?;
+        Ok(ValidatedBody(value))
+    }
+}
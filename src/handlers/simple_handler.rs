@@ -1,17 +1,28 @@
 use crate::domain::general::{FilterParams, Message, Params};
+use crate::handlers::negotiation::ValidatedBody;
+use crate::handlers::validation::{Problem, ValidatedPath, ValidatedQuery};
 use axum::{
-    extract::{Path, Query},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Json,
 };
 use tracing::{error, info, warn};
 
+#[utoipa::path(get, path = "/ping", tag = "demo", responses((status = 200, body = String)))]
 pub async fn get_pong() -> Response {
     info!("PONG!");
     (StatusCode::OK, "PONG!").into_response()
 }
 
+/// Relays the `/pong` answer of `EXTERNAL_SERVICE_URL`.
+#[utoipa::path(
+    get,
+    path = "/its-a-rainy-day",
+    tag = "demo",
+    responses(
+        (status = 200, description = "Status and text of the external answer", body = String),
+        (status = 417, description = "External service unreachable", body = String),
+    )
+)]
 pub async fn call_external_service() -> Response {
     info!("call_external_service called");
     let base_url = std::env::var("EXTERNAL_SERVICE_URL")
@@ -45,6 +56,16 @@ pub async fn call_external_service() -> Response {
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/protected-enter",
+    tag = "demo",
+    params(("X-Custom-Header" = String, Header)),
+    responses(
+        (status = 200, body = String),
+        (status = 401, description = "Invalid or missing header", body = String),
+    )
+)]
 pub async fn protected_route(headers: HeaderMap) -> Response {
     // Verifies if the specific header exists and have the correct value
     match headers.get("X-Custom-Header") {
@@ -55,7 +76,19 @@ pub async fn protected_route(headers: HeaderMap) -> Response {
     }
 }
 
-pub async fn get_params(Path(Params { param_1, param_2 }): Path<Params>) -> Response {
+#[utoipa::path(
+    get,
+    path = "/params/{param_1}/another_p/{param_2}",
+    tag = "demo",
+    params(Params),
+    responses(
+        (status = 200, body = String),
+        (status = 422, description = "Invalid parameters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_params(
+    ValidatedPath(Params { param_1, param_2 }): ValidatedPath<Params>,
+) -> Response {
     (
         StatusCode::OK,
         format!("Parameter 1: {}, Parameter 2: {}", param_1, param_2),
@@ -63,7 +96,17 @@ pub async fn get_params(Path(Params { param_1, param_2 }): Path<Params>) -> Resp
         .into_response()
 }
 
-pub async fn get_question(Query(params): Query<FilterParams>) -> Response {
+#[utoipa::path(
+    get,
+    path = "/question_separator",
+    tag = "demo",
+    params(FilterParams),
+    responses(
+        (status = 200, body = String),
+        (status = 422, description = "Invalid filters", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_question(ValidatedQuery(params): ValidatedQuery<FilterParams>) -> Response {
     let response = format!(
         "Filters: name={}, age={}, active={}",
         params.name.unwrap_or_default(),
@@ -74,7 +117,23 @@ pub async fn get_question(Query(params): Query<FilterParams>) -> Response {
 }
 
 /// Test route for POST body data
-pub async fn post_body_data(Json(payload): Json<Message>) -> Response {
+#[utoipa::path(
+    post,
+    path = "/body-data",
+    tag = "demo",
+    request_body(content(
+        (Message = "application/json"),
+        (Message = "application/msgpack"),
+        (Message = "application/cbor"),
+        (Message = "text/csv"),
+    )),
+    responses(
+        (status = 200, body = String),
+        (status = 415, description = "Unsupported Content-Type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid payload", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn post_body_data(ValidatedBody(payload): ValidatedBody<Message>) -> Response {
     info!("Received payload: {:?}", payload);
     let response = format!(
         "Received message with code: {}, text: {}",
@@ -0,0 +1,346 @@
+use crate::domain::database::User;
+use crate::engine::db_engine::{DbPool, MockDatabaseExecutor};
+use crate::engine::transaction::{MockDatabaseTransaction, Transaction};
+use crate::handlers::admin_handler::Actor;
+use crate::handlers::bulk_handler::*;
+use crate::state::AppState;
+use anyhow::anyhow;
+use axum::body::{Bytes, to_bytes};
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::Response;
+use futures::{StreamExt, stream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+use time::macros::datetime;
+
+fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_static(value));
+    headers
+}
+
+/// The transaction of one batch, inserting it with `create` then committed or rolled back.
+fn batch(
+    mut create: impl FnMut(Vec<String>) -> anyhow::Result<Vec<User>> + Send + 'static,
+    committed: bool,
+) -> Box<dyn Transaction> {
+    let mut transaction = MockDatabaseTransaction::new();
+    transaction
+        .expect_execute_create_users()
+        .times(1)
+        .returning(move |names, _| create(names));
+    transaction
+        .expect_commit()
+        .times(usize::from(committed))
+        .returning(|| Ok(()));
+    transaction
+        .expect_rollback()
+        .times(usize::from(!committed))
+        .returning(|| Ok(()));
+    Box::new(transaction)
+}
+
+/// Creates every batch, numbering users from 1 in insertion order.
+fn creating_db() -> MockDatabaseExecutor {
+    let mut mock_db = MockDatabaseExecutor::new();
+    let next_uid = Arc::new(AtomicI32::new(1));
+    mock_db.expect_begin().returning(move || {
+        let next_uid = next_uid.clone();
+        Ok(batch(
+            move |names| {
+                Ok(names
+                    .into_iter()
+                    .map(|name| User {
+                        uid: next_uid.fetch_add(1, Ordering::Relaxed),
+                        name,
+                        updated_at: None,
+                        ..Default::default()
+                    })
+                    .collect())
+            },
+            true,
+        ))
+    });
+    mock_db
+}
+
+async fn body_json(response: Response) -> serde_json::Value {
+    serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap()
+}
+
+async fn body_text(response: Response) -> String {
+    String::from_utf8(
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_format_of_content_type() {
+    assert_eq!(
+        BulkFormat::of_content_type(&HeaderMap::new()),
+        Some(BulkFormat::Json)
+    );
+    assert_eq!(
+        BulkFormat::of_content_type(&headers(header::CONTENT_TYPE, "text/csv; charset=utf-8")),
+        Some(BulkFormat::Csv)
+    );
+    assert_eq!(
+        BulkFormat::of_content_type(&headers(header::CONTENT_TYPE, "application/x-ndjson")),
+        Some(BulkFormat::Ndjson)
+    );
+    assert_eq!(
+        BulkFormat::of_content_type(&headers(header::CONTENT_TYPE, "application/xml")),
+        None
+    );
+}
+
+#[test]
+fn test_format_of_accept() {
+    let accept = |value| BulkFormat::of_accept(&headers(header::ACCEPT, value));
+    assert_eq!(
+        BulkFormat::of_accept(&HeaderMap::new()),
+        Some(BulkFormat::Json)
+    );
+    assert_eq!(accept("*/*"), Some(BulkFormat::Json));
+    assert_eq!(
+        accept("application/json;q=0.5, text/csv"),
+        Some(BulkFormat::Csv)
+    );
+    assert_eq!(
+        accept("application/xml, application/x-ndjson;q=0.1"),
+        Some(BulkFormat::Ndjson)
+    );
+    assert_eq!(accept("text/csv;q=0, application/xml"), None);
+    assert_eq!(
+        accept("application/json;q=0, */*"),
+        Some(BulkFormat::Ndjson)
+    );
+}
+
+#[test]
+fn test_parse_import_reports_rows_on_their_own() {
+    let rows = parse_import(
+        BulkFormat::Json,
+        br#"[{"name":"Alice"},{"name":"   "},{"nom":"Bob"}]"#,
+    )
+    .unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0].as_ref().unwrap().name, "Alice");
+    assert_eq!(rows[1].as_ref().unwrap_err()[0].code, "blank");
+    assert_eq!(rows[2].as_ref().unwrap_err()[0].code, "parse");
+
+    let rows = parse_import(
+        BulkFormat::Ndjson,
+        b"{\"name\":\"Alice\"}\n\n{\"name\":\"Bob\"}\nnot json\n",
+    )
+    .unwrap();
+    assert_eq!(rows.len(), 3);
+    assert!(rows[0].is_ok() && rows[1].is_ok() && rows[2].is_err());
+
+    let rows = parse_import(
+        BulkFormat::Csv,
+        b"uid,Name\n1, Alice \n2,Bartholomew-The-Great\n",
+    )
+    .unwrap();
+    assert_eq!(rows[0].as_ref().unwrap().name, "Alice");
+    assert_eq!(rows[1].as_ref().unwrap_err()[0].field, "name");
+}
+
+#[test]
+fn test_parse_import_rejects_unsplittable_bodies() {
+    let problem = parse_import(BulkFormat::Json, br#"{"name":"Alice"}"#).unwrap_err();
+    assert_eq!(problem.status(), StatusCode::BAD_REQUEST);
+    let problem = parse_import(BulkFormat::Csv, b"uid,email\n1,a@b.c\n").unwrap_err();
+    assert_eq!(problem.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_import_creates_every_row() {
+    let state = AppState::new(DbPool::Mock(creating_db()));
+
+    let response = import_users(
+        State(state),
+        Actor(None),
+        headers(header::CONTENT_TYPE, "text/csv"),
+        Bytes::from_static(b"name\nAlice\nBob\n"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let report = body_json(response).await;
+    assert_eq!(report["created"], 2);
+    assert_eq!(report["results"][1]["status"], "created");
+    assert_eq!(report["results"][1]["uid"], 2);
+}
+
+#[tokio::test]
+async fn test_import_reports_partial_failures() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    // Popped from the end: the first batch commits, the second fails and is rolled back.
+    let mut batches = vec![
+        batch(|_| Err(anyhow!("lock wait timeout")), false),
+        batch(
+            |names| {
+                Ok(names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, name)| User {
+                        uid: index as i32 + 1,
+                        name,
+                        updated_at: None,
+                        ..Default::default()
+                    })
+                    .collect())
+            },
+            true,
+        ),
+    ];
+    mock_db
+        .expect_begin()
+        .times(2)
+        .returning(move || Ok(batches.pop().unwrap()));
+    let state = AppState::new(DbPool::Mock(mock_db));
+    // One invalid row, then a full batch and one row in a second, failing batch.
+    let mut body = String::from("[{\"name\":\"\"}");
+    for index in 0..IMPORT_BATCH_SIZE + 1 {
+        body.push_str(&format!(",{{\"name\":\"user{}\"}}", index));
+    }
+    body.push(']');
+
+    let response = import_users(
+        State(state),
+        Actor(None),
+        HeaderMap::new(),
+        Bytes::from(body),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let report = body_json(response).await;
+    assert_eq!(report["created"], IMPORT_BATCH_SIZE);
+    assert_eq!(report["invalid"], 1);
+    assert_eq!(report["failed"], 1);
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results[0]["status"], "invalid");
+    assert_eq!(results[0]["errors"][0]["field"], "name");
+    let last = results.last().unwrap();
+    assert_eq!(last["row"], IMPORT_BATCH_SIZE + 2);
+    assert_eq!(last["status"], "failed");
+    assert!(
+        last["error"]
+            .as_str()
+            .unwrap()
+            .contains("lock wait timeout")
+    );
+}
+
+#[tokio::test]
+async fn test_import_rejects_unsupported_and_empty_bodies() {
+    let state = AppState::new(DbPool::Mock(MockDatabaseExecutor::new()));
+
+    let response = import_users(
+        State(state.clone()),
+        Actor(None),
+        headers(header::CONTENT_TYPE, "application/xml"),
+        Bytes::from_static(b"<users/>"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let response = import_users(
+        State(state),
+        Actor(None),
+        HeaderMap::new(),
+        Bytes::from_static(b"[]"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+fn exported_users() -> Vec<User> {
+    (1..=EXPORT_CHUNK_SIZE as i32 + 1)
+        .map(|uid| User {
+            uid,
+            name: format!("user{}", uid),
+            updated_at: (uid == 1).then_some(datetime!(2025-01-02 03:04:05.678 UTC)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn exporting_state() -> AppState {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db.expect_execute_get_users().never();
+    mock_db
+        .expect_stream_users()
+        .returning(|_| Ok(stream::iter(exported_users().into_iter().map(Ok)).boxed()));
+    AppState::new(DbPool::Mock(mock_db))
+}
+
+#[tokio::test]
+async fn test_export_formats() {
+    let response = export_users(State(exporting_state()), HeaderMap::new()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    let users: Vec<User> =
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(users.len(), EXPORT_CHUNK_SIZE + 1);
+
+    let response = export_users(
+        State(exporting_state()),
+        headers(header::ACCEPT, "application/x-ndjson"),
+    )
+    .await;
+    let body = body_text(response).await;
+    assert_eq!(body.lines().count(), EXPORT_CHUNK_SIZE + 1);
+    assert!(
+        body.lines()
+            .all(|line| serde_json::from_str::<User>(line).is_ok())
+    );
+
+    let response = export_users(
+        State(exporting_state()),
+        headers(header::ACCEPT, "text/csv"),
+    )
+    .await;
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"users.csv\""
+    );
+    let body = body_text(response).await;
+    let mut lines = body.lines();
+    assert_eq!(
+        lines.next(),
+        Some("uid,name,updated_at,created_at,deleted_at,created_by,version")
+    );
+    assert_eq!(lines.next(), Some("1,user1,2025-01-02T03:04:05.678Z,,,,0"));
+    assert_eq!(lines.next(), Some("2,user2,,,,,0"));
+    assert_eq!(lines.count(), EXPORT_CHUNK_SIZE - 1);
+}
+
+#[tokio::test]
+async fn test_export_without_acceptable_format() {
+    let state = AppState::new(DbPool::Mock(MockDatabaseExecutor::new()));
+    let response = export_users(State(state), headers(header::ACCEPT, "application/xml")).await;
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn test_export_ends_early_on_a_failed_read() {
+    let mut mock_db = MockDatabaseExecutor::new();
+    mock_db.expect_stream_users().returning(|_| {
+        let users = exported_users().into_iter().take(2).map(Ok);
+        Ok(stream::iter(users)
+            .chain(stream::once(async { Err(anyhow!("connection reset")) }))
+            .boxed())
+    });
+    let state = AppState::new(DbPool::Mock(mock_db));
+
+    let response = export_users(State(state), HeaderMap::new()).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(to_bytes(response.into_body(), usize::MAX).await.is_err());
+}
@@ -0,0 +1,119 @@
+use crate::domain::database::{User, UsersStamp};
+use crate::handlers::conditional::*;
+use crate::handlers::negotiation::MediaFormat;
+use axum::http::{HeaderMap, HeaderValue, header};
+use time::macros::datetime;
+
+fn user(name: &str) -> User {
+    User {
+        uid: 1,
+        name: name.to_string(),
+        updated_at: Some(datetime!(2025-01-02 03:04:05.678 UTC)),
+        ..Default::default()
+    }
+}
+
+fn headers_with(name: header::HeaderName, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+#[test]
+fn test_strong_etag_follows_representation() {
+    let etag = strong_etag(&user("John"));
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+    assert_eq!(etag, strong_etag(&user("John")));
+    assert_ne!(etag, strong_etag(&user("Jane")));
+}
+
+#[test]
+fn test_of_users_uses_latest_update() {
+    let mut newer = user("Jane");
+    newer.updated_at = Some(datetime!(2025-02-01 00:00:00 UTC));
+    let validators = Validators::of_users(&UsersStamp::of_users(&[user("John"), newer]));
+
+    let mut headers = HeaderMap::new();
+    validators.apply(&mut headers);
+    assert_eq!(
+        headers[header::LAST_MODIFIED],
+        "Sat, 01 Feb 2025 00:00:00 GMT"
+    );
+}
+
+#[test]
+fn test_if_none_match_weak_comparison() {
+    let validators = Validators::of_user(&user("John"));
+    let weak = format!("W/{}", validators.etag);
+
+    assert!(validators.not_modified(&headers_with(header::IF_NONE_MATCH, &weak)));
+    assert!(validators.not_modified(&headers_with(header::IF_NONE_MATCH, "\"a\", *")));
+    assert!(!validators.not_modified(&headers_with(header::IF_NONE_MATCH, "\"a\"")));
+}
+
+#[test]
+fn test_if_none_match_takes_precedence_over_if_modified_since() {
+    let validators = Validators::of_user(&user("John"));
+    let mut headers = headers_with(header::IF_NONE_MATCH, "\"a\"");
+    headers.insert(
+        header::IF_MODIFIED_SINCE,
+        HeaderValue::from_static("Fri, 01 Jan 2100 00:00:00 GMT"),
+    );
+
+    assert!(!validators.not_modified(&headers));
+}
+
+#[test]
+fn test_if_match_strong_comparison() {
+    let validators = Validators::of_user(&user("John"));
+    let weak = format!("W/{}", validators.etag);
+
+    assert!(validators.precondition_holds(&headers_with(header::IF_MATCH, &validators.etag)));
+    assert!(!validators.precondition_holds(&headers_with(header::IF_MATCH, &weak)));
+    assert!(validators.precondition_holds(&HeaderMap::new()));
+}
+
+#[test]
+fn test_if_unmodified_since() {
+    let validators = Validators::of_user(&user("John"));
+
+    let headers = headers_with(header::IF_UNMODIFIED_SINCE, "Thu, 02 Jan 2025 03:04:05 GMT");
+    assert!(validators.precondition_holds(&headers));
+    let headers = headers_with(header::IF_UNMODIFIED_SINCE, "Thu, 02 Jan 2025 03:04:04 GMT");
+    assert!(!validators.precondition_holds(&headers));
+    assert!(has_preconditions(&headers));
+}
+
+#[test]
+fn test_each_format_has_its_own_etag() {
+    let json = Validators::of_user(&user("John")).in_format(MediaFormat::Json);
+    let csv = Validators::of_user(&user("John")).in_format(MediaFormat::Csv);
+
+    assert_eq!(json.etag, Validators::of_user(&user("John")).etag);
+    assert_ne!(json.etag, csv.etag);
+    assert!(csv.etag.starts_with('"') && csv.etag.ends_with("-csv\""));
+    assert!(!csv.not_modified(&headers_with(header::IF_NONE_MATCH, &json.etag)));
+    assert!(csv.not_modified(&headers_with(header::IF_NONE_MATCH, &csv.etag)));
+}
+
+#[test]
+fn test_if_match_accepts_the_etag_of_any_format() {
+    let json = Validators::of_user(&user("John")).in_format(MediaFormat::Json);
+    let cbor = Validators::of_user(&user("John")).in_format(MediaFormat::Cbor);
+    let other = Validators::of_user(&user("Jane")).in_format(MediaFormat::Cbor);
+
+    assert!(json.precondition_holds(&headers_with(header::IF_MATCH, &cbor.etag)));
+    assert!(cbor.precondition_holds(&headers_with(header::IF_MATCH, &json.etag)));
+    assert!(!json.precondition_holds(&headers_with(header::IF_MATCH, &other.etag)));
+}
+
+#[test]
+fn test_not_modified_keeps_vary_of_negotiated_responses() {
+    let plain = Validators::of_user(&user("John")).not_modified_response();
+    assert!(!plain.headers().contains_key(header::VARY));
+
+    let negotiated = Validators::of_user(&user("John"))
+        .in_format(MediaFormat::MessagePack)
+        .not_modified_response();
+    assert_eq!(negotiated.headers()[header::VARY], "accept");
+}
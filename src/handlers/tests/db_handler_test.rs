@@ -1,12 +1,37 @@
-use crate::domain::database::{NewUser, User};
+use crate::domain::database::{ListUsers, NewUser, User, UserId, UsersStamp};
 use crate::engine::db_engine::{DbPool, MockDatabaseExecutor};
+use crate::handlers::admin_handler::{Actor, AdminAuth};
+use crate::handlers::conditional::Validators;
 use crate::handlers::db_handler::*;
+use crate::handlers::negotiation::{AcceptedFormat, MediaFormat, ValidatedBody};
+use crate::handlers::validation::{ValidatedPath, ValidatedQuery};
 use crate::state::AppState;
 use anyhow::anyhow;
-use axum::http::StatusCode;
-use axum::{extract::State, Json};
+use axum::body::to_bytes;
+use axum::extract::{OriginalUri, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri, header};
+use futures::{StreamExt, stream};
 use mockall::predicate::*;
-use std::sync::Arc;
+use time::macros::datetime;
+
+/// Expects the list to be stamped and streamed once each, `include_deleted` or not.
+fn expect_listing(
+    mock_executor: &mut MockDatabaseExecutor,
+    include_deleted: bool,
+    users: Vec<User>,
+) {
+    let stamp = UsersStamp::of_users(&users);
+    mock_executor
+        .expect_execute_get_users_stamp()
+        .with(eq(include_deleted))
+        .times(1)
+        .returning(move |_| Ok(stamp.clone()));
+    mock_executor
+        .expect_stream_users()
+        .with(eq(include_deleted))
+        .times(1)
+        .returning(move |_| Ok(stream::iter(users.clone().into_iter().map(Ok)).boxed()));
+}
 
 #[tokio::test]
 async fn test_get_users_success() {
@@ -15,24 +40,72 @@ async fn test_get_users_success() {
         User {
             uid: 1,
             name: "Test User 1".to_string(),
+            updated_at: None,
+            ..Default::default()
         },
         User {
             uid: 2,
             name: "Test User 2".to_string(),
+            updated_at: None,
+            ..Default::default()
         },
     ];
 
-    mock_executor
-        .expect_execute_get_users()
-        .times(1)
-        .returning(move || Ok(mock_users.clone()));
+    expect_listing(&mut mock_executor, false, mock_users);
 
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_executor)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_executor));
 
-    let response = get_users(State(state)).await;
+    let response = get_users(
+        State(state),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers::default()),
+        HeaderMap::new(),
+    )
+    .await;
     assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let users: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(users[1]["name"], "Test User 2");
+}
+
+#[tokio::test]
+async fn test_get_users_streams_csv_and_collects_binary_formats() {
+    for format in [
+        MediaFormat::Csv,
+        MediaFormat::MessagePack,
+        MediaFormat::Cbor,
+    ] {
+        let mut mock_executor = MockDatabaseExecutor::new();
+        expect_listing(&mut mock_executor, false, vec![stored_user()]);
+        let state = AppState::new(DbPool::Mock(mock_executor));
+
+        let response = get_users(
+            State(state),
+            AcceptedFormat(format),
+            ValidatedQuery(ListUsers::default()),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            format.content_type()
+        );
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let users: Vec<User> = match format {
+            MediaFormat::MessagePack => rmp_serde::from_slice(&body).unwrap(),
+            MediaFormat::Cbor => ciborium::from_reader(&body[..]).unwrap(),
+            _ => {
+                let csv = String::from_utf8(body.to_vec()).unwrap();
+                assert!(csv.starts_with("uid,name,"), "{}", csv);
+                assert!(csv.lines().nth(1).unwrap().starts_with("7,Test User,"));
+                continue;
+            }
+        };
+        assert_eq!(users[0].uid, 7, "{:?}", format);
+    }
 }
 
 #[tokio::test]
@@ -40,15 +113,20 @@ async fn test_get_users_error() {
     let mut mock_executor = MockDatabaseExecutor::new();
 
     mock_executor
-        .expect_execute_get_users()
+        .expect_execute_get_users_stamp()
         .times(1)
-        .returning(|| Err(anyhow!("Database error")));
+        .returning(|_| Err(anyhow!("Database error")));
+    mock_executor.expect_stream_users().never();
 
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_executor)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_executor));
 
-    let response = get_users(State(state)).await;
+    let response = get_users(
+        State(state),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers::default()),
+        HeaderMap::new(),
+    )
+    .await;
     assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
 }
 
@@ -61,16 +139,30 @@ async fn test_create_user_success() {
 
     mock_executor
         .expect_execute_create_user()
-        .with(eq("Test User".to_string()))
+        .with(eq("Test User".to_string()), eq(None))
         .times(1)
-        .returning(|_| Ok("OK".to_string()));
+        .returning(|_, _| Ok(stored_user()));
 
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_executor)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_executor));
 
-    let response = create_user(State(state), Json(new_user)).await;
+    let response = create_user(
+        State(state),
+        OriginalUri(Uri::from_static("/v1/users")),
+        Actor(None),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedBody(new_user),
+    )
+    .await;
     assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()[header::LOCATION], "/v1/users/7");
+    assert_eq!(
+        response.headers()[header::ETAG],
+        Validators::of_user(&stored_user()).etag
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(created["uid"], 7);
+    assert_eq!(created["name"], "Test User");
 }
 
 #[tokio::test]
@@ -83,12 +175,507 @@ async fn test_create_user_error() {
     mock_executor
         .expect_execute_create_user()
         .times(1)
-        .returning(|_| Err(anyhow!("Database error")));
+        .returning(|_, _| Err(anyhow!("Database error")));
 
-    let state = AppState {
-        db_pool: Arc::new(DbPool::Mock(mock_executor)),
-    };
+    let state = AppState::new(DbPool::Mock(mock_executor));
 
-    let response = create_user(State(state), Json(new_user)).await;
+    let response = create_user(
+        State(state),
+        OriginalUri(Uri::from_static("/users")),
+        Actor(None),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedBody(new_user),
+    )
+    .await;
     assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
 }
+
+fn stored_user() -> User {
+    User {
+        uid: 7,
+        name: "Test User".to_string(),
+        updated_at: Some(datetime!(2025-01-02 03:04:05.678 UTC)),
+        version: 3,
+        ..Default::default()
+    }
+}
+
+fn headers_with(name: header::HeaderName, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn test_get_users_sets_validators_and_honours_if_none_match() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_users_stamp()
+        .times(2)
+        .returning(|_| Ok(UsersStamp::of_users(&[stored_user()])));
+    // A current client copy is answered without reading the users.
+    mock_executor
+        .expect_stream_users()
+        .times(1)
+        .returning(|_| Ok(stream::iter([Ok(stored_user())]).boxed()));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = get_users(
+        State(state.clone()),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers::default()),
+        HeaderMap::new(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        response.headers()[header::LAST_MODIFIED],
+        "Thu, 02 Jan 2025 03:04:05 GMT"
+    );
+
+    let response = get_users(
+        State(state),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers::default()),
+        headers_with(header::IF_NONE_MATCH, &etag),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    assert_eq!(response.headers()[header::VARY], "accept");
+}
+
+#[tokio::test]
+async fn test_get_user_not_found() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .with(eq(7))
+        .times(1)
+        .returning(|_| Ok(None));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = get_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        HeaderMap::new(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_get_user_if_modified_since() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .times(2)
+        .returning(|_| Ok(Some(stored_user())));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let headers = headers_with(header::IF_MODIFIED_SINCE, "Thu, 02 Jan 2025 03:04:05 GMT");
+    let response = get_user(
+        State(state.clone()),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        headers,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::VARY], "accept");
+
+    let headers = headers_with(header::IF_MODIFIED_SINCE, "Thu, 02 Jan 2025 03:04:04 GMT");
+    let response = get_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        headers,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_get_user_in_other_formats_has_its_own_etag() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .returning(|_| Ok(Some(stored_user())));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = get_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Cbor),
+        HeaderMap::new(),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/cbor");
+    assert_eq!(
+        response.headers()[header::ETAG],
+        Validators::of_user(&stored_user())
+            .in_format(MediaFormat::Cbor)
+            .etag
+            .as_str()
+    );
+    assert_ne!(
+        response.headers()[header::ETAG],
+        Validators::of_user(&stored_user()).etag.as_str()
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let user: User = ciborium::from_reader(body.as_ref()).unwrap();
+    assert_eq!(user.name, "Test User");
+}
+
+#[tokio::test]
+async fn test_update_user_if_match_mismatch_returns_412() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .times(1)
+        .returning(|_| Ok(Some(stored_user())));
+    mock_executor.expect_execute_update_user().never();
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let headers = headers_with(header::IF_MATCH, "\"stale\"");
+    let payload = NewUser {
+        name: "Renamed".to_string(),
+    };
+    let response = update_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        headers,
+        ValidatedBody(payload),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert!(response.headers().contains_key(header::ETAG));
+}
+
+#[tokio::test]
+async fn test_update_user_if_match_passes_expected_version() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .times(1)
+        .returning(|_| Ok(Some(stored_user())));
+    mock_executor
+        .expect_execute_update_user()
+        .with(eq(7), eq("Renamed".to_string()), eq(Some(3)))
+        .times(1)
+        .returning(|uid, name, _| {
+            Ok(Some(User {
+                uid,
+                name,
+                updated_at: Some(datetime!(2025-01-02 03:05:00 UTC)),
+                version: 4,
+                ..Default::default()
+            }))
+        });
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    // Read as CSV, the update is still based on the same state.
+    let etag = Validators::of_user(&stored_user())
+        .in_format(MediaFormat::Csv)
+        .etag;
+    let headers = headers_with(header::IF_MATCH, &etag);
+    let payload = NewUser {
+        name: "Renamed".to_string(),
+    };
+    let response = update_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        headers,
+        ValidatedBody(payload),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[header::ETAG], etag.as_str());
+}
+
+#[tokio::test]
+async fn test_update_user_lost_race_returns_412() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .times(1)
+        .returning(|_| Ok(Some(stored_user())));
+    mock_executor
+        .expect_execute_update_user()
+        .times(1)
+        .returning(|_, _, _| Ok(None));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let headers = headers_with(header::IF_MATCH, "*");
+    let payload = NewUser {
+        name: "Renamed".to_string(),
+    };
+    let response = update_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+        headers,
+        ValidatedBody(payload),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn test_delete_user_without_preconditions() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor.expect_execute_get_user().never();
+    mock_executor
+        .expect_execute_delete_user()
+        .with(eq(7), eq(None))
+        .times(1)
+        .returning(|_, _| Ok(true));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = delete_user(
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        HeaderMap::new(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_delete_missing_user_with_if_match_returns_412() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_user()
+        .times(1)
+        .returning(|_| Ok(None));
+    mock_executor.expect_execute_delete_user().never();
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let headers = headers_with(header::IF_MATCH, "*");
+    let response = delete_user(State(state), ValidatedPath(UserId { uid: 7 }), headers).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+fn admin_state(mock_executor: MockDatabaseExecutor) -> AppState {
+    AppState::new(DbPool::Mock(mock_executor)).with_admin_token(Some("secret"))
+}
+
+#[tokio::test]
+async fn test_get_users_include_deleted_requires_the_admin_token() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor.expect_execute_get_users_stamp().never();
+    mock_executor.expect_stream_users().never();
+    let state = admin_state(mock_executor);
+
+    let response = get_users(
+        State(state),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers {
+            include_deleted: true,
+        }),
+        headers_with(header::AUTHORIZATION, "Bearer wrong"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_get_users_include_deleted_lists_deleted_users_to_admins() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    expect_listing(
+        &mut mock_executor,
+        true,
+        vec![User {
+            deleted_at: Some(datetime!(2025-02-03 04:05:06 UTC)),
+            ..stored_user()
+        }],
+    );
+    let state = admin_state(mock_executor);
+
+    let response = get_users(
+        State(state),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedQuery(ListUsers {
+            include_deleted: true,
+        }),
+        headers_with(header::AUTHORIZATION, "Bearer secret"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let users: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(users[0]["deleted_at"], "2025-02-03T04:05:06Z");
+}
+
+#[tokio::test]
+async fn test_create_user_records_the_actor() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_create_user()
+        .with(eq("Test User".to_string()), eq(Some("alice".to_string())))
+        .times(1)
+        .returning(|name, created_by| {
+            Ok(User {
+                name,
+                created_by,
+                ..stored_user()
+            })
+        });
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = create_user(
+        State(state),
+        OriginalUri(Uri::from_static("/users")),
+        Actor(Some("alice".to_string())),
+        AcceptedFormat(MediaFormat::Json),
+        ValidatedBody(NewUser {
+            name: "Test User".to_string(),
+        }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(created["created_by"], "alice");
+}
+
+#[tokio::test]
+async fn test_restore_user() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_restore_user()
+        .with(eq(7))
+        .times(1)
+        .returning(|_| Ok(Some(stored_user())));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = restore_user(
+        AdminAuth,
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ETAG],
+        Validators::of_user(&stored_user()).etag
+    );
+}
+
+#[tokio::test]
+async fn test_restore_user_not_deleted_returns_404() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_restore_user()
+        .times(1)
+        .returning(|_| Ok(None));
+    let state = AppState::new(DbPool::Mock(mock_executor));
+
+    let response = restore_user(
+        AdminAuth,
+        State(state),
+        ValidatedPath(UserId { uid: 7 }),
+        AcceptedFormat(MediaFormat::Json),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn actor(state: &AppState, request: axum::http::Request<()>) -> Option<String> {
+    use axum::extract::FromRequestParts;
+    let (mut parts, _) = request.into_parts();
+    Actor::from_request_parts(&mut parts, state)
+        .await
+        .unwrap()
+        .0
+}
+
+#[tokio::test]
+async fn test_actor_prefers_the_client_certificate() {
+    use crate::server::connection::{ClientIdentity, ConnectionInfo};
+    use axum::extract::ConnectInfo;
+    let state = admin_state(MockDatabaseExecutor::new());
+    let request = |common_name: Option<&str>| {
+        axum::http::Request::builder()
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .extension(ConnectInfo(ConnectionInfo {
+                remote_addr: None,
+                client_identity: Some(ClientIdentity {
+                    subject: "CN=alice,O=Example".to_string(),
+                    common_name: common_name.map(str::to_string),
+                    dns_names: vec![],
+                    uris: vec![],
+                    serial: "01".to_string(),
+                }),
+            }))
+            .body(())
+            .unwrap()
+    };
+
+    assert_eq!(
+        actor(&state, request(Some("alice"))).await.as_deref(),
+        Some("alice")
+    );
+    assert_eq!(
+        actor(&state, request(None)).await.as_deref(),
+        Some("CN=alice,O=Example")
+    );
+}
+
+#[tokio::test]
+async fn test_actor_is_admin_for_the_admin_token_only() {
+    let state = admin_state(MockDatabaseExecutor::new());
+    let request = |token: &str| {
+        axum::http::Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+    };
+
+    assert_eq!(
+        actor(&state, request("secret")).await.as_deref(),
+        Some("admin")
+    );
+    assert_eq!(actor(&state, request("wrong")).await, None);
+}
+
+#[tokio::test]
+async fn test_restore_user_requires_the_admin_token() {
+    use axum::extract::FromRequestParts;
+    let state = admin_state(MockDatabaseExecutor::new());
+    let authorized = |token: &str| {
+        axum::http::Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    };
+
+    let (mut parts, _) = axum::http::Request::new(()).into_parts();
+    assert!(
+        AdminAuth::from_request_parts(&mut parts, &state)
+            .await
+            .is_err()
+    );
+    assert!(
+        AdminAuth::from_request_parts(&mut authorized("wrong"), &state)
+            .await
+            .is_err()
+    );
+    assert!(
+        AdminAuth::from_request_parts(&mut authorized("secret"), &state)
+            .await
+            .is_ok()
+    );
+}
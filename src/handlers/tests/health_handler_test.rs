@@ -0,0 +1,22 @@
+use crate::engine::db_engine::{DbPool, MockDatabaseExecutor};
+use crate::handlers::health_handler::*;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn test_get_ready_when_serving() {
+    let state = AppState::new(DbPool::Mock(MockDatabaseExecutor::new()));
+
+    let response = get_ready(State(state)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_get_ready_during_shutdown() {
+    let state = AppState::new(DbPool::Mock(MockDatabaseExecutor::new()));
+    state.lifecycle.set_ready(false);
+
+    let response = get_ready(State(state)).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
@@ -1,2 +1,7 @@
+mod bulk_handler_test;
+mod conditional_test;
 mod db_handler_test;
+mod health_handler_test;
+mod negotiation_test;
 mod simple_handler_test;
+mod validation_test;
@@ -0,0 +1,291 @@
+use crate::domain::database::{NewUser, User};
+use crate::handlers::negotiation::*;
+use crate::handlers::validation::PROBLEM_JSON;
+use axum::body::{Body, to_bytes};
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode, header};
+use axum::response::Response;
+use axum::{Router, routing::post};
+use serde::Deserialize;
+use time::macros::datetime;
+use tower::ServiceExt;
+
+fn user() -> User {
+    User {
+        uid: 7,
+        name: "Alice".to_string(),
+        updated_at: Some(datetime!(2025-01-02 03:04:05.678 UTC)),
+        ..Default::default()
+    }
+}
+
+/// Echoes the posted user back, as a user created from it.
+fn app() -> Router {
+    Router::new().route(
+        "/users",
+        post(
+            |AcceptedFormat(format): AcceptedFormat,
+             ValidatedBody(new_user): ValidatedBody<NewUser>| async move {
+                Negotiated(
+                    format,
+                    User {
+                        name: new_user.name,
+                        ..user()
+                    },
+                )
+            },
+        ),
+    )
+}
+
+fn post_as(content_type: &str, accept: &str, body: Vec<u8>) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri("/users")
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT, accept)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn body_of(response: Response) -> Vec<u8> {
+    to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+fn accepting(value: &'static str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+    headers
+}
+
+#[test]
+fn test_accept_picks_the_preferred_format() {
+    assert_eq!(
+        MediaFormat::of_accept(&HeaderMap::new()),
+        Some(MediaFormat::Json)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("*/*")),
+        Some(MediaFormat::Json)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("application/json;q=0.5, application/cbor")),
+        Some(MediaFormat::Cbor)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("text/html, application/x-msgpack;q=0.2")),
+        Some(MediaFormat::MessagePack)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("text/*")),
+        Some(MediaFormat::Csv)
+    );
+    assert_eq!(MediaFormat::of_accept(&accepting("text/html")), None);
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("application/json;q=0")),
+        None
+    );
+}
+
+#[test]
+fn test_accept_exclusions_win_over_wildcards() {
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("application/json;q=0, */*")),
+        Some(MediaFormat::MessagePack)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("*/*, application/*;q=0")),
+        Some(MediaFormat::Csv)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("text/*;q=0, text/csv;q=0.5, application/*;q=0")),
+        Some(MediaFormat::Csv)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("*/*;q=0.5, application/json;q=0.1")),
+        Some(MediaFormat::MessagePack)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("application/cbor, application/json")),
+        Some(MediaFormat::Cbor)
+    );
+    assert_eq!(
+        MediaFormat::of_accept(&accepting("*/*;q=0, application/json;q=0")),
+        None
+    );
+}
+
+#[test]
+fn test_formats_round_trip_users() {
+    let users = vec![
+        user(),
+        User {
+            updated_at: None,
+            created_by: Some("admin".to_string()),
+            ..user()
+        },
+    ];
+    for format in [
+        MediaFormat::Json,
+        MediaFormat::MessagePack,
+        MediaFormat::Cbor,
+    ] {
+        let bytes = format.encode(&users).unwrap();
+        let decoded: Vec<User> = match format {
+            MediaFormat::Json => serde_json::from_slice(&bytes).unwrap(),
+            MediaFormat::MessagePack => rmp_serde::from_slice(&bytes).unwrap(),
+            _ => ciborium::from_reader(bytes.as_slice()).unwrap(),
+        };
+        assert_eq!(decoded.len(), 2, "{:?}", format);
+        assert_eq!(decoded[0].updated_at, users[0].updated_at, "{:?}", format);
+        assert_eq!(decoded[1].updated_at, None, "{:?}", format);
+    }
+}
+
+#[test]
+fn test_csv_keeps_every_column() {
+    let users = vec![
+        user(),
+        User {
+            updated_at: None,
+            created_by: Some("admin".to_string()),
+            ..user()
+        },
+    ];
+    let csv = String::from_utf8(MediaFormat::Csv.encode(&users).unwrap()).unwrap();
+    assert_eq!(
+        csv,
+        "uid,name,updated_at,created_at,deleted_at,created_by,version\n7,Alice,2025-01-02T03:04:05.678Z,,,,0\n7,Alice,,,,admin,0\n"
+    );
+}
+
+#[tokio::test]
+async fn test_bodies_are_decoded_by_content_type() {
+    #[derive(serde::Serialize)]
+    struct Body<'a> {
+        name: &'a str,
+    }
+    let mut cbor = Vec::new();
+    ciborium::into_writer(&Body { name: "Cbor" }, &mut cbor).unwrap();
+    let bodies = [
+        ("application/json", br#"{"name":"Json"}"#.to_vec(), "Json"),
+        (
+            "application/msgpack",
+            rmp_serde::to_vec_named(&Body { name: "Msgpack" }).unwrap(),
+            "Msgpack",
+        ),
+        ("application/cbor", cbor, "Cbor"),
+        ("text/csv; charset=utf-8", b"name\nCsv\n".to_vec(), "Csv"),
+    ];
+
+    for (content_type, body, name) in bodies {
+        let response = app()
+            .oneshot(post_as(content_type, "application/json", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", content_type);
+        let created: User = serde_json::from_slice(&body_of(response).await).unwrap();
+        assert_eq!(created.name, name);
+    }
+}
+
+#[tokio::test]
+async fn test_responses_follow_accept() {
+    let response = app()
+        .oneshot(post_as(
+            "application/json",
+            "application/msgpack",
+            br#"{"name":"Alice"}"#.to_vec(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/msgpack"
+    );
+    assert_eq!(response.headers()[header::VARY], "accept");
+    let created: User = rmp_serde::from_slice(&body_of(response).await).unwrap();
+    assert_eq!(created.uid, 7);
+
+    let response = app()
+        .oneshot(post_as(
+            "application/json",
+            "text/csv",
+            br#"{"name":"Alice"}"#.to_vec(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+    let csv = String::from_utf8(body_of(response).await).unwrap();
+    assert!(
+        csv.starts_with("uid,name,updated_at,created_at,deleted_at,created_by,version\n7,Alice,")
+    );
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    detail: String,
+}
+
+async fn problem_of(response: Response) -> Problem {
+    assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+    serde_json::from_slice(&body_of(response).await).unwrap()
+}
+
+#[tokio::test]
+async fn test_unacceptable_formats_get_406() {
+    let response = app()
+        .oneshot(post_as(
+            "application/json",
+            "application/xml",
+            br#"{"name":"Alice"}"#.to_vec(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    assert!(
+        problem_of(response)
+            .await
+            .detail
+            .contains("application/cbor")
+    );
+}
+
+#[tokio::test]
+async fn test_unsupported_bodies_get_415() {
+    let response = app()
+        .oneshot(post_as("application/xml", "*/*", b"<user/>".to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(problem_of(response).await.detail.contains("text/csv"));
+}
+
+#[tokio::test]
+async fn test_undecodable_and_invalid_bodies() {
+    let response = app()
+        .oneshot(post_as("application/cbor", "*/*", b"\xff\x00".to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app()
+        .oneshot(post_as("text/csv", "*/*", b"name\nAlice\nBob\n".to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(problem_of(response).await.detail.contains("single row"));
+
+    // Decoded bodies are validated like JSON ones.
+    let response = app()
+        .oneshot(post_as(
+            "application/msgpack",
+            "*/*",
+            rmp_serde::to_vec_named(&serde_json::json!({"name": "   "})).unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -1,10 +1,11 @@
 use crate::domain::general::{FilterParams, Message, Params};
+use crate::handlers::negotiation::ValidatedBody;
 use crate::handlers::simple_handler::*;
+use crate::handlers::validation::{ValidatedPath, ValidatedQuery};
 use axum::body::to_bytes;
-use axum::extract::Path;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::{Json, extract::Query, http::HeaderMap};
 use httpmock::prelude::*;
 
 #[tokio::test]
@@ -75,7 +76,7 @@ async fn test_protected_route_with_auth() {
 
 #[tokio::test]
 async fn test_get_params() {
-    let param_in = Path(Params {
+    let param_in = ValidatedPath(Params {
         param_1: 1,
         param_2: "test2".to_string(),
     });
@@ -90,7 +91,7 @@ async fn test_get_params() {
 
 #[tokio::test]
 async fn test_get_question() {
-    let query = Query(FilterParams {
+    let query = ValidatedQuery(FilterParams {
         name: Option::from("Jack".to_string()),
         age: Option::from(25),
         active: Option::from(true),
@@ -112,7 +113,7 @@ async fn test_post_body_data() {
         message_text: "Received body data successfully!".to_string(),
     };
 
-    let response = post_body_data(Json(body_data)).await;
+    let response = post_body_data(ValidatedBody(body_data)).await;
     let body = to_bytes(response.into_body(), usize::MAX).await;
 
     match body {
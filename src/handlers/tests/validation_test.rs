@@ -0,0 +1,131 @@
+use crate::domain::database::NewUser;
+use crate::domain::general::{FilterParams, Params};
+use crate::handlers::validation::*;
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header};
+use axum::response::Response;
+use axum::{
+    Router,
+    routing::{get, post},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+fn app() -> Router {
+    Router::new()
+        .route(
+            "/users",
+            post(|ValidatedJson(user): ValidatedJson<NewUser>| async move { user.name }),
+        )
+        .route(
+            "/params/{param_1}/another_p/{param_2}",
+            get(|ValidatedPath(params): ValidatedPath<Params>| async move { params.param_2 }),
+        )
+        .route(
+            "/question_separator",
+            get(
+                |ValidatedQuery(filters): ValidatedQuery<FilterParams>| async move {
+                    filters.name.unwrap_or_default()
+                },
+            ),
+        )
+}
+
+fn post_json(body: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri("/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+async fn problem_of(response: Response) -> Value {
+    assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_valid_payload_is_accepted() {
+    let response = app()
+        .oneshot(post_json(r#"{"name":"Alice"}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_invalid_fields_are_listed() {
+    let response = app().oneshot(post_json(r#"{"name":"   "}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let problem = problem_of(response).await;
+    assert_eq!(problem["status"], 422);
+    assert_eq!(
+        problem["errors"],
+        json!([{"field": "name", "code": "blank", "message": "must not be blank"}])
+    );
+
+    let long_name = format!(r#"{{"name":"{}"}}"#, "a".repeat(16));
+    let response = app().oneshot(post_json(&long_name)).await.unwrap();
+    let problem = problem_of(response).await;
+    assert_eq!(problem["errors"][0]["code"], "length");
+}
+
+#[tokio::test]
+async fn test_malformed_json_is_a_problem() {
+    let response = app().oneshot(post_json(r#"{"name":"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let problem = problem_of(response).await;
+    assert_eq!(problem["title"], "Bad Request");
+    assert!(problem.get("errors").is_none());
+
+    let response = app()
+        .oneshot(post_json(r#"{"nickname":"Alice"}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        problem_of(response).await["detail"]
+            .as_str()
+            .unwrap()
+            .contains("name")
+    );
+}
+
+#[tokio::test]
+async fn test_query_rules_and_rejections() {
+    let response = app()
+        .oneshot(get_request("/question_separator?name=Jack&age=25"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app()
+        .oneshot(get_request("/question_separator?age=200"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(problem_of(response).await["errors"][0]["field"], "age");
+
+    let response = app()
+        .oneshot(get_request("/question_separator?age=old"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    problem_of(response).await;
+}
+
+#[tokio::test]
+async fn test_path_rejection_is_a_problem() {
+    let response = app()
+        .oneshot(get_request("/params/abc/another_p/test"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    problem_of(response).await;
+}
@@ -2,6 +2,12 @@ pub mod database;
 pub mod domain;
 pub mod engine;
 pub mod handlers;
+pub mod middleware;
+pub mod modules;
 pub mod routes;
+pub mod server;
 pub mod state;
 pub mod utils;
+
+#[cfg(test)]
+mod test_support;
@@ -1,12 +1,4 @@
-use crate::utils::main_utils::service_starter;
-
-mod database;
-mod domain;
-mod engine;
-mod handlers;
-mod routes;
-mod state;
-mod utils;
+use ms1::utils::main_utils::service_starter;
 
 #[tokio::main]
 async fn main() {
@@ -0,0 +1,31 @@
+use crate::engine::cache::bypassing;
+use axum::extract::Request;
+use axum::http::{HeaderMap, header};
+use axum::middleware::Next;
+use axum::response::Response;
+
+pub const CACHE_BYPASS_HEADER: &str = "x-cache-bypass";
+
+/// True for `X-Cache-Bypass: true|1` or `Cache-Control: no-cache`.
+pub fn wants_bypass(headers: &HeaderMap) -> bool {
+    let bypass_header = headers
+        .get(CACHE_BYPASS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1");
+    let no_cache = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"));
+    bypass_header || no_cache
+}
+
+/// Middleware making database reads of the request skip the query cache when asked to.
+pub async fn cache_bypass(request: Request, next: Next) -> Response {
+    if wants_bypass(request.headers()) {
+        bypassing(next.run(request)).await
+    } else {
+        next.run(request).await
+    }
+}
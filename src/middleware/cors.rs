@@ -0,0 +1,236 @@
+use crate::utils::config_utils::{env_duration, env_parse};
+use anyhow::{Result, anyhow};
+use axum::http::request::Parts;
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+/// An allowed origin: `*`, an exact origin or every subdomain of one (`https://*.example.com`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    /// Scheme and `://` before the `*`, the parent domain (and port) after it.
+    Subdomains {
+        scheme: String,
+        parent: String,
+    },
+}
+
+impl std::str::FromStr for OriginPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim().trim_end_matches('/');
+        if value == "*" {
+            return Ok(OriginPattern::Any);
+        }
+        let (scheme, host) = value
+            .split_once("://")
+            .ok_or_else(|| anyhow!("origin must look like <scheme>://<host>: {}", value))?;
+        if scheme.is_empty() || host.is_empty() || host.contains('/') {
+            return Err(anyhow!(
+                "origin must look like <scheme>://<host>: {}",
+                value
+            ));
+        }
+        match host.strip_prefix("*.") {
+            Some(parent) if !parent.is_empty() && !parent.contains('*') => {
+                Ok(OriginPattern::Subdomains {
+                    scheme: format!("{}://", scheme.to_ascii_lowercase()),
+                    parent: format!(".{}", parent.to_ascii_lowercase()),
+                })
+            }
+            _ if host.contains('*') => Err(anyhow!(
+                "only a leading *. wildcard is supported in origins: {}",
+                value
+            )),
+            _ => Ok(OriginPattern::Exact(value.to_ascii_lowercase())),
+        }
+    }
+}
+
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(allowed) => origin == *allowed,
+            OriginPattern::Subdomains { scheme, parent } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|host| host.strip_suffix(parent.as_str()))
+                // The parent domain itself is not a subdomain, nor is anything with a path or port.
+                .is_some_and(|sub| {
+                    !sub.is_empty() && !sub.starts_with('.') && !sub.contains(['/', ':', '@'])
+                }),
+        }
+    }
+}
+
+/// Which browser origins may call the API and what they may send and read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsConfig {
+    pub origins: Vec<OriginPattern>,
+    /// `None` allows any method.
+    pub methods: Option<Vec<Method>>,
+    /// `None` allows any request header.
+    pub headers: Option<Vec<HeaderName>>,
+    /// Response headers readable by scripts besides the CORS-safelisted ones.
+    pub expose_headers: Vec<HeaderName>,
+    /// Lets cookies and `Authorization` through, never combined with a `*` origin.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            origins: Vec::new(),
+            methods: Some(vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ]),
+            headers: Some(
+                [
+                    "content-type",
+                    "authorization",
+                    "idempotency-key",
+                    "if-match",
+                    "if-none-match",
+                    "if-modified-since",
+                    "if-unmodified-since",
+                ]
+                .into_iter()
+                .map(HeaderName::from_static)
+                .collect(),
+            ),
+            expose_headers: [
+                "etag",
+                "last-modified",
+                "location",
+                "idempotent-replayed",
+                "deprecation",
+                "sunset",
+                "link",
+                "retry-after",
+                "ratelimit-limit",
+                "ratelimit-remaining",
+                "ratelimit-reset",
+                "ratelimit-policy",
+            ]
+            .into_iter()
+            .map(HeaderName::from_static)
+            .collect(),
+            allow_credentials: false,
+            max_age: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+/// Comma separated list, `*` meaning any.
+fn parse_list<T: std::str::FromStr>(name: &str, value: &str) -> Option<Vec<T>> {
+    if value.trim() == "*" {
+        return None;
+    }
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.parse()
+                    .unwrap_or_else(|_| panic!("{} has an invalid entry: {}", name, item))
+            })
+            .collect(),
+    )
+}
+
+impl CorsConfig {
+    /// Reads `CORS_ALLOWED_ORIGINS` (`https://app.example.com,https://*.example.com` or `*`),
+    /// `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS`, `CORS_EXPOSED_HEADERS`,
+    /// `CORS_ALLOW_CREDENTIALS` and `CORS_MAX_AGE`. Without allowed origins CORS is off and
+    /// browsers keep blocking cross-origin calls.
+    pub fn from_env() -> Option<Self> {
+        let origins = std::env::var("CORS_ALLOWED_ORIGINS").ok()?;
+        let defaults = CorsConfig::default();
+        let origins = origins
+            .split(',')
+            .filter(|origin| !origin.trim().is_empty())
+            .map(|origin| {
+                origin
+                    .parse()
+                    .unwrap_or_else(|err| panic!("CORS_ALLOWED_ORIGINS is invalid: {:#}", err))
+            })
+            .collect();
+        Some(CorsConfig {
+            origins,
+            methods: match std::env::var("CORS_ALLOWED_METHODS") {
+                Ok(methods) => parse_list("CORS_ALLOWED_METHODS", &methods),
+                Err(_) => defaults.methods,
+            },
+            headers: match std::env::var("CORS_ALLOWED_HEADERS") {
+                Ok(headers) => parse_list("CORS_ALLOWED_HEADERS", &headers),
+                Err(_) => defaults.headers,
+            },
+            expose_headers: match std::env::var("CORS_EXPOSED_HEADERS") {
+                Ok(headers) => parse_list("CORS_EXPOSED_HEADERS", &headers)
+                    .expect("CORS_EXPOSED_HEADERS cannot be *."),
+                Err(_) => defaults.expose_headers,
+            },
+            allow_credentials: env_parse("CORS_ALLOW_CREDENTIALS", false),
+            max_age: env_duration("CORS_MAX_AGE").or(defaults.max_age),
+        })
+    }
+
+    /// Preflights are answered by the layer itself, other requests get the CORS headers added
+    /// when their `Origin` is allowed.
+    pub fn layer(&self) -> Result<CorsLayer> {
+        if self.origins.is_empty() {
+            return Err(anyhow!("at least one allowed origin is required"));
+        }
+        let any_origin = self.origins.contains(&OriginPattern::Any);
+        if self.allow_credentials && any_origin {
+            // Browsers refuse `Access-Control-Allow-Origin: *` on credentialed requests.
+            return Err(anyhow!(
+                "credentials cannot be allowed for every origin, list the origins instead"
+            ));
+        }
+
+        let origin = if any_origin {
+            AllowOrigin::any()
+        } else {
+            let origins = self.origins.clone();
+            AllowOrigin::predicate(move |origin: &HeaderValue, _: &Parts| {
+                origin
+                    .to_str()
+                    .is_ok_and(|origin| origins.iter().any(|allowed| allowed.matches(origin)))
+            })
+        };
+        // With credentials `*` is taken literally by browsers, so the request is mirrored instead.
+        let methods = match (&self.methods, self.allow_credentials) {
+            (Some(methods), _) => AllowMethods::list(methods.clone()),
+            (None, false) => AllowMethods::any(),
+            (None, true) => AllowMethods::mirror_request(),
+        };
+        let headers = match (&self.headers, self.allow_credentials) {
+            (Some(headers), _) => AllowHeaders::list(headers.clone()),
+            (None, false) => AllowHeaders::any(),
+            (None, true) => AllowHeaders::mirror_request(),
+        };
+
+        let mut layer = CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(ExposeHeaders::list(self.expose_headers.clone()))
+            .allow_credentials(self.allow_credentials);
+        if let Some(max_age) = self.max_age {
+            layer = layer.max_age(max_age);
+        }
+        Ok(layer)
+    }
+}
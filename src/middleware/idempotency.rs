@@ -0,0 +1,407 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::utils::config_utils::env_duration;
+use anyhow::Result;
+use axum::body::{Body, to_bytes};
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http_body_util::LengthLimitError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, warn};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses replayed from the store.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+
+/// Response kept for a key, replayed as is to retries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// What is known about a key: the request it was first used with and, once done, its response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub fingerprint: String,
+    /// `None` while the first request is still being processed.
+    pub response: Option<StoredResponse>,
+}
+
+/// Where idempotency records live.
+#[async_trait::async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Claims `key` for `record` for `lease`, unless it is already taken.
+    /// Returns the existing record when it is.
+    async fn begin(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        lease: Duration,
+    ) -> Result<Option<IdempotencyRecord>>;
+    /// Replaces the record of `key`, keeping it for `ttl`.
+    async fn complete(&self, key: &str, record: &IdempotencyRecord, ttl: Duration) -> Result<()>;
+    /// Frees `key` so the request can be retried.
+    async fn release(&self, key: &str) -> Result<()>;
+}
+
+/// Records kept in process memory, only retries reaching the same replica are deduplicated.
+#[derive(Debug, Default)]
+pub struct InMemoryIdempotencyStore {
+    records: Mutex<Records>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    entries: HashMap<String, (IdempotencyRecord, Instant)>,
+    /// Every expiry handed out, soonest first. Entries replaced or released since are skipped
+    /// when popped, so expiring costs a pop per record rather than a scan per request.
+    expiries: BinaryHeap<Reverse<(Instant, String)>>,
+}
+
+impl Records {
+    fn insert(&mut self, key: &str, record: IdempotencyRecord, expires: Instant) {
+        self.entries.insert(key.to_string(), (record, expires));
+        self.expiries.push(Reverse((expires, key.to_string())));
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(Reverse((expires, _))) = self.expiries.peek() {
+            if *expires > now {
+                break;
+            }
+            let Some(Reverse((_, key))) = self.expiries.pop() else {
+                break;
+            };
+            if self
+                .entries
+                .get(&key)
+                .is_some_and(|(_, current)| *current <= now)
+            {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+impl InMemoryIdempotencyStore {
+    /// Keys currently remembered, expired ones included until the next `begin`.
+    #[cfg(test)]
+    pub(crate) fn tracked(&self) -> usize {
+        self.records
+            .lock()
+            .expect("idempotency lock poisoned")
+            .entries
+            .len()
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn begin(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        lease: Duration,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let now = Instant::now();
+        let mut records = self.records.lock().expect("idempotency lock poisoned");
+        records.expire(now);
+        if let Some((existing, _)) = records.entries.get(key) {
+            return Ok(Some(existing.clone()));
+        }
+        records.insert(key, record.clone(), now + lease);
+        Ok(None)
+    }
+
+    async fn complete(&self, key: &str, record: &IdempotencyRecord, ttl: Duration) -> Result<()> {
+        self.records
+            .lock()
+            .expect("idempotency lock poisoned")
+            .insert(key, record.clone(), Instant::now() + ttl);
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        self.records
+            .lock()
+            .expect("idempotency lock poisoned")
+            .entries
+            .remove(key);
+        Ok(())
+    }
+}
+
+/// Records shared by every replica, claimed with `SET NX` and expired through Redis TTLs.
+#[derive(Clone)]
+pub struct RedisIdempotencyStore {
+    redis: RedisConnection,
+    prefix: String,
+}
+
+impl RedisIdempotencyStore {
+    pub fn new(redis: RedisConnection) -> Self {
+        RedisIdempotencyStore {
+            redis,
+            prefix: "idempotency:".to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyStore for RedisIdempotencyStore {
+    async fn begin(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        lease: Duration,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let key = format!("{}{}", self.prefix, key);
+        let value = serde_json::to_vec(record)?;
+        let mut connection = self.redis.connection().await?;
+        // Retried when the existing record expires between the two commands.
+        loop {
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg(&value)
+                .arg("PX")
+                .arg(lease.as_millis().max(1) as u64)
+                .arg("NX")
+                .query_async(&mut connection)
+                .await?;
+            if claimed.is_some() {
+                return Ok(None);
+            }
+            let existing: Option<Vec<u8>> = redis::cmd("GET")
+                .arg(&key)
+                .query_async(&mut connection)
+                .await?;
+            if let Some(bytes) = existing {
+                return Ok(Some(serde_json::from_slice(&bytes)?));
+            }
+        }
+    }
+
+    async fn complete(&self, key: &str, record: &IdempotencyRecord, ttl: Duration) -> Result<()> {
+        let mut connection = self.redis.connection().await?;
+        let _: () = redis::cmd("SET")
+            .arg(format!("{}{}", self.prefix, key))
+            .arg(serde_json::to_vec(record)?)
+            .arg("PX")
+            .arg(ttl.as_millis().max(1) as u64)
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        let mut connection = self.redis.connection().await?;
+        let _: i64 = redis::cmd("DEL")
+            .arg(format!("{}{}", self.prefix, key))
+            .query_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Store and expiry settings of the idempotency middleware.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    /// How long a completed response is replayed.
+    ttl: Duration,
+    /// How long a key stays claimed by a request that never completes, e.g. after a crash.
+    lease: Duration,
+}
+
+impl Idempotency {
+    pub fn new(store: Arc<dyn IdempotencyStore>, ttl: Duration, lease: Duration) -> Self {
+        Idempotency { store, ttl, lease }
+    }
+
+    /// Reads `IDEMPOTENCY_STORE` (`memory` or `redis`), `IDEMPOTENCY_TTL` (24h by default)
+    /// and `IDEMPOTENCY_LEASE` (60s by default, the request timeout).
+    pub fn from_env() -> Self {
+        let store: Arc<dyn IdempotencyStore> = match std::env::var("IDEMPOTENCY_STORE").as_deref() {
+            Ok("redis") => Arc::new(RedisIdempotencyStore::new(
+                RedisConnection::from_env()
+                    .expect("REDIS_URL or REDIS_HOST must be set when IDEMPOTENCY_STORE=redis."),
+            )),
+            Ok("memory") | Err(_) => Arc::new(InMemoryIdempotencyStore::default()),
+            Ok(other) => panic!("IDEMPOTENCY_STORE must be memory or redis, got {}.", other),
+        };
+        Idempotency::new(
+            store,
+            env_duration("IDEMPOTENCY_TTL").unwrap_or(Duration::from_secs(24 * 3600)),
+            env_duration("IDEMPOTENCY_LEASE").unwrap_or(Duration::from_secs(60)),
+        )
+    }
+}
+
+/// Hash of what makes two requests the same: method, target and body.
+pub fn fingerprint(method: &str, uri: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(uri.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+fn replay(stored: &StoredResponse) -> Response {
+    let mut response = Body::from(stored.body.clone()).into_response();
+    *response.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    for (name, value) in &stored.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+/// Makes retries of a request carrying `Idempotency-Key` safe.
+/// The first response for a key is stored and replayed to retries with the same body,
+/// reusing the key for another payload is `422`, retrying while the first attempt is still
+/// running is `409`. Server errors are not stored so the request can be retried.
+/// A failing store lets the request through unprotected rather than failing it.
+pub async fn idempotent(
+    State(idempotency): State<Idempotency>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters",
+                    MAX_KEY_LEN
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            // The route body limit wraps the body, see `route_policy`.
+            let over_limit =
+                std::iter::successors(std::error::Error::source(&err), |error| error.source())
+                    .any(|error| error.is::<LengthLimitError>());
+            let status = if over_limit {
+                StatusCode::PAYLOAD_TOO_LARGE
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            return (status, format!("failed to read body: {}", err)).into_response();
+        }
+    };
+    let record = IdempotencyRecord {
+        fingerprint: fingerprint(parts.method.as_str(), &parts.uri.to_string(), &body),
+        response: None,
+    };
+    let store_key = format!("{} {}|{}", parts.method, parts.uri.path(), key);
+
+    match idempotency
+        .store
+        .begin(&store_key, &record, idempotency.lease)
+        .await
+    {
+        Ok(None) => {}
+        Ok(Some(existing)) if existing.fingerprint != record.fingerprint => {
+            warn!("idempotency key {} reused with a different payload", key);
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different payload",
+            )
+                .into_response();
+        }
+        Ok(Some(IdempotencyRecord {
+            response: Some(stored),
+            ..
+        })) => return replay(&stored),
+        Ok(Some(_)) => {
+            return (
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still being processed",
+            )
+                .into_response();
+        }
+        Err(err) => {
+            error!(
+                "idempotency store failed, running {} unprotected: {:#}",
+                key, err
+            );
+            return next.run(Request::from_parts(parts, Body::from(body))).await;
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status().is_server_error() {
+        if let Err(err) = idempotency.store.release(&store_key).await {
+            error!("failed to release idempotency key {}: {:#}", key, err);
+        }
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            error!(
+                "failed to buffer response for idempotency key {}: {}",
+                key, err
+            );
+            if let Err(err) = idempotency.store.release(&store_key).await {
+                error!("failed to release idempotency key {}: {:#}", key, err);
+            }
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: body.to_vec(),
+    };
+    let record = IdempotencyRecord {
+        response: Some(stored),
+        ..record
+    };
+    if let Err(err) = idempotency
+        .store
+        .complete(&store_key, &record, idempotency.ttl)
+        .await
+    {
+        error!(
+            "failed to store response for idempotency key {}: {:#}",
+            key, err
+        );
+    }
+    Response::from_parts(parts, Body::from(body))
+}
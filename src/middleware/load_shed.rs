@@ -0,0 +1,300 @@
+use crate::middleware::policy_path::policy_path;
+use crate::middleware::route_policy::RouteMatcher;
+use crate::utils::config_utils::env_duration;
+use anyhow::{Context, Result, anyhow};
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use opentelemetry::metrics::Counter;
+use opentelemetry::{KeyValue, global};
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::debug;
+use utoipa::ToSchema;
+
+/// How many requests may run at once, and how many more may wait for a slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcurrencyLimit {
+    pub max_in_flight: usize,
+    pub max_queued: usize,
+}
+
+impl std::str::FromStr for ConcurrencyLimit {
+    type Err = anyhow::Error;
+
+    /// Parses `<max in flight>[:<max queued>]`, e.g. `64:128`. Nothing waits without a queue.
+    fn from_str(value: &str) -> Result<Self> {
+        let (in_flight, queued) = value.trim().split_once(':').unwrap_or((value.trim(), "0"));
+        let max_in_flight: usize = in_flight
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid in-flight limit in {}", value))?;
+        let max_queued = queued
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid queue limit in {}", value))?;
+        if max_in_flight == 0 {
+            return Err(anyhow!(
+                "concurrency limit must allow at least one request: {}",
+                value
+            ));
+        }
+        Ok(ConcurrencyLimit {
+            max_in_flight,
+            max_queued,
+        })
+    }
+}
+
+/// Why a request was turned away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShedReason {
+    /// Every slot busy and the queue full, answered at once.
+    QueueFull,
+    /// Waited in the queue for longer than the queue timeout.
+    QueueTimeout,
+}
+
+impl ShedReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ShedReason::QueueFull => "queue_full",
+            ShedReason::QueueTimeout => "queue_timeout",
+        }
+    }
+}
+
+/// Slots of one scope, the whole server or a route.
+struct Gate {
+    scope: String,
+    limit: ConcurrencyLimit,
+    slots: Semaphore,
+    queued: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Gate {
+    fn new(scope: String, limit: ConcurrencyLimit) -> Self {
+        Gate {
+            scope,
+            limit,
+            slots: Semaphore::new(limit.max_in_flight),
+            queued: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    async fn enter(&self, queue_timeout: Duration) -> Result<SemaphorePermit<'_>, ShedReason> {
+        if let Ok(permit) = self.slots.try_acquire() {
+            return Ok(permit);
+        }
+        let place = QueuePlace::take(&self.queued);
+        if place.ahead >= self.limit.max_queued {
+            return Err(ShedReason::QueueFull);
+        }
+        let permit = tokio::time::timeout(queue_timeout, self.slots.acquire()).await;
+        drop(place);
+        match permit {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(ShedReason::QueueTimeout),
+        }
+    }
+
+    fn stats(&self) -> ScopeStats {
+        ScopeStats {
+            scope: self.scope.clone(),
+            max_in_flight: self.limit.max_in_flight,
+            max_queued: self.limit.max_queued,
+            in_flight: self.limit.max_in_flight - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            shed: self.shed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A request counted in the queue of a gate until dropped, also when its future is dropped
+/// while waiting (route timeout, client gone).
+struct QueuePlace<'a> {
+    queued: &'a AtomicUsize,
+    /// Requests queued before this one.
+    ahead: usize,
+}
+
+impl<'a> QueuePlace<'a> {
+    fn take(queued: &'a AtomicUsize) -> Self {
+        let ahead = queued.fetch_add(1, Ordering::SeqCst);
+        QueuePlace { queued, ahead }
+    }
+}
+
+impl Drop for QueuePlace<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Current load of a scope and what it shed since startup.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ScopeStats {
+    pub scope: String,
+    pub max_in_flight: usize,
+    pub max_queued: usize,
+    pub in_flight: usize,
+    pub queued: usize,
+    pub shed: u64,
+}
+
+/// Global and per-route in-flight limits. Requests over a limit wait in a bounded queue,
+/// and are answered `503` as soon as it is full, instead of piling up on the database pool.
+/// Shed requests are counted per scope and exported as `http.server.shed_requests`.
+pub struct LoadShedder {
+    global: Option<Gate>,
+    /// Most specific first.
+    routes: Vec<(RouteMatcher, Gate)>,
+    queue_timeout: Duration,
+    /// Routes never shed, such as the health probes.
+    exempt_paths: Vec<String>,
+    shed_counter: Counter<u64>,
+}
+
+impl Default for LoadShedder {
+    /// No limits.
+    fn default() -> Self {
+        LoadShedder {
+            global: None,
+            routes: Vec::new(),
+            // Below the 5s the database pool waits for a connection.
+            queue_timeout: Duration::from_secs(1),
+            exempt_paths: vec!["/ping".to_string(), "/ready".to_string()],
+            shed_counter: global::meter("excelsior")
+                .u64_counter("http.server.shed_requests")
+                .build(),
+        }
+    }
+}
+
+impl LoadShedder {
+    pub fn with_global(mut self, limit: ConcurrencyLimit) -> Self {
+        self.global = Some(Gate::new("global".to_string(), limit));
+        self
+    }
+
+    pub fn with_route(mut self, route: &str, limit: ConcurrencyLimit) -> Result<Self> {
+        let matcher: RouteMatcher = route.parse()?;
+        self.routes
+            .push((matcher, Gate::new(route.trim().to_string(), limit)));
+        self.routes
+            .sort_by_key(|(matcher, _)| std::cmp::Reverse(matcher.specificity()));
+        Ok(self)
+    }
+
+    pub fn with_queue_timeout(mut self, queue_timeout: Duration) -> Self {
+        self.queue_timeout = queue_timeout;
+        self
+    }
+
+    /// Reads `LOAD_SHED_MAX_IN_FLIGHT` (`64:128`, the global limit and queue),
+    /// `LOAD_SHED_ROUTES` (`POST /v1/users=4:8;/v1/admin/*=1`) and `LOAD_SHED_QUEUE_TIMEOUT`.
+    /// Without them nothing is limited.
+    pub fn from_env() -> Self {
+        let mut shedder = LoadShedder::default();
+        if let Ok(limit) = std::env::var("LOAD_SHED_MAX_IN_FLIGHT") {
+            shedder = shedder.with_global(
+                limit
+                    .parse()
+                    .expect("LOAD_SHED_MAX_IN_FLIGHT must look like <in flight>[:<queued>]."),
+            );
+        }
+        if let Ok(routes) = std::env::var("LOAD_SHED_ROUTES") {
+            for entry in routes.split(';').filter(|entry| !entry.trim().is_empty()) {
+                let (route, limit) = entry
+                    .split_once('=')
+                    .unwrap_or_else(|| panic!("LOAD_SHED_ROUTES entry is invalid: {}", entry));
+                shedder = limit
+                    .parse()
+                    .and_then(|limit| shedder.with_route(route, limit))
+                    .unwrap_or_else(|err| panic!("LOAD_SHED_ROUTES is invalid: {:#}", err));
+            }
+        }
+        if let Some(queue_timeout) = env_duration("LOAD_SHED_QUEUE_TIMEOUT") {
+            shedder = shedder.with_queue_timeout(queue_timeout);
+        }
+        shedder
+    }
+
+    /// Load of every scope, the global one first.
+    pub fn stats(&self) -> Vec<ScopeStats> {
+        self.global
+            .iter()
+            .chain(self.routes.iter().map(|(_, gate)| gate))
+            .map(Gate::stats)
+            .collect()
+    }
+
+    fn route_gate(&self, request: &Request) -> Option<&Gate> {
+        let path = policy_path(request.extensions())?;
+        self.routes
+            .iter()
+            .find(|(matcher, _)| matcher.matches(request.method(), path))
+            .map(|(_, gate)| gate)
+    }
+
+    fn shed(&self, gate: &Gate, reason: ShedReason) -> Response {
+        gate.shed.fetch_add(1, Ordering::Relaxed);
+        self.shed_counter.add(
+            1,
+            &[
+                KeyValue::new("scope", gate.scope.clone()),
+                KeyValue::new("reason", reason.as_str()),
+            ],
+        );
+        debug!(
+            "shed a request over the {} limit ({})",
+            gate.scope,
+            reason.as_str()
+        );
+        let mut response = (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server overloaded, retry later",
+        )
+            .into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(1));
+        response
+    }
+}
+
+/// Runs the request once it holds a slot of its route and a global slot.
+/// The route slot is taken first so a saturated route cannot hold global slots while waiting.
+pub async fn load_shed(
+    State(shedder): State<Arc<LoadShedder>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let exempt = policy_path(request.extensions())
+        .is_some_and(|path| shedder.exempt_paths.iter().any(|exempt| exempt == path));
+    if exempt {
+        return next.run(request).await;
+    }
+
+    let _route_permit = match shedder.route_gate(&request) {
+        Some(gate) => match gate.enter(shedder.queue_timeout).await {
+            Ok(permit) => Some(permit),
+            Err(reason) => return shedder.shed(gate, reason),
+        },
+        None => None,
+    };
+    let _global_permit = match &shedder.global {
+        Some(gate) => match gate.enter(shedder.queue_timeout).await {
+            Ok(permit) => Some(permit),
+            Err(reason) => return shedder.shed(gate, reason),
+        },
+        None => None,
+    };
+    next.run(request).await
+}
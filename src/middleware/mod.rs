@@ -0,0 +1,13 @@
+pub mod cache_bypass;
+pub mod cors;
+pub mod idempotency;
+pub mod load_shed;
+pub mod policy_path;
+pub mod rate_limit;
+pub mod rate_limit_store;
+pub mod read_your_writes;
+pub mod route_policy;
+pub mod security_headers;
+
+#[cfg(test)]
+mod tests;
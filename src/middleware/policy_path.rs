@@ -0,0 +1,55 @@
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::Extensions;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Route an alias stands for, set by `alias_policy_path`.
+#[derive(Debug, Clone)]
+struct AliasOf(Arc<str>);
+
+/// Route paths also served without a prefix, e.g. the unversioned `/users` serving `/v1/users`.
+#[derive(Debug, Clone, Default)]
+pub struct RouteAliases {
+    prefix: String,
+    paths: Arc<HashSet<String>>,
+}
+
+impl RouteAliases {
+    /// `paths` are served as they are and under `prefix`, the prefixed route being the real one.
+    pub fn new(prefix: &str, paths: impl IntoIterator<Item = String>) -> Self {
+        RouteAliases {
+            prefix: prefix.to_string(),
+            paths: Arc::new(paths.into_iter().collect()),
+        }
+    }
+}
+
+/// Tags requests to an alias with the route it stands for. Must sit outside every layer
+/// reading `policy_path`, so that rate limits, route policies and load shedding configured
+/// for `/v1/users` hold for `/users` as well.
+pub async fn alias_policy_path(
+    State(aliases): State<RouteAliases>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let alias = request
+        .extensions()
+        .get::<MatchedPath>()
+        .filter(|path| aliases.paths.contains(path.as_str()))
+        .map(|path| AliasOf(format!("{}{}", aliases.prefix, path.as_str()).into()));
+    if let Some(alias) = alias {
+        request.extensions_mut().insert(alias);
+    }
+    next.run(request).await
+}
+
+/// Route path the policies of a request are looked up by: the matched route, or for an
+/// alias the route it stands for.
+pub fn policy_path(extensions: &Extensions) -> Option<&str> {
+    match extensions.get::<AliasOf>() {
+        Some(AliasOf(path)) => Some(path),
+        None => extensions.get::<MatchedPath>().map(MatchedPath::as_str),
+    }
+}
@@ -0,0 +1,462 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::middleware::policy_path::policy_path;
+use crate::middleware::rate_limit_store::{
+    InMemoryRateLimitStore, RateLimitStore, RedisRateLimitStore,
+};
+use crate::server::connection::ConnectionInfo;
+use crate::utils::config_utils::{env_parse, parse_duration};
+use anyhow::{Context, Result, anyhow};
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tower::{Layer, Service};
+use tracing::{error, warn};
+
+/// What identifies a client for a policy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyStrategy {
+    /// One bucket shared by every client.
+    Global,
+    ClientIp,
+    /// Value of the API key header when it is one of the known keys, falling back to the client IP.
+    ApiKey,
+    /// `sub` claim of a bearer JWT signed with the configured secret, falling back to the client IP.
+    JwtSubject,
+}
+
+impl std::str::FromStr for KeyStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim() {
+            "global" => Ok(KeyStrategy::Global),
+            "ip" => Ok(KeyStrategy::ClientIp),
+            "api-key" => Ok(KeyStrategy::ApiKey),
+            "jwt-sub" => Ok(KeyStrategy::JwtSubject),
+            other => Err(anyhow!("unknown rate limit key: {}", other)),
+        }
+    }
+}
+
+/// `limit` requests per `period` for each key, allowing bursts of up to `limit` requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitPolicy {
+    pub limit: u32,
+    pub period: Duration,
+    pub key: KeyStrategy,
+}
+
+impl std::str::FromStr for RateLimitPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `<limit>/<period>[:<key>]`, e.g. `100/1s` or `5/1m:api-key`.
+    fn from_str(value: &str) -> Result<Self> {
+        let (rate, key) = match value.trim().split_once(':') {
+            Some((rate, key)) => (rate, key.parse()?),
+            None => (value.trim(), KeyStrategy::ClientIp),
+        };
+        let (limit, period) = rate
+            .split_once('/')
+            .ok_or_else(|| anyhow!("rate limit must look like <limit>/<period>: {}", value))?;
+        let limit: u32 = limit
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid limit in {}", value))?;
+        let period = parse_duration(period)?;
+        if limit == 0 || period.is_zero() {
+            return Err(anyhow!(
+                "rate limit must allow at least one request: {}",
+                value
+            ));
+        }
+        Ok(RateLimitPolicy { limit, period, key })
+    }
+}
+
+impl RateLimitPolicy {
+    pub(crate) fn refill_per_sec(&self) -> f64 {
+        self.limit as f64 / self.period.as_secs_f64()
+    }
+
+    /// `RateLimit-Policy` header value.
+    pub(crate) fn describe(&self) -> String {
+        format!("{};w={}", self.limit, self.period.as_secs().max(1))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    /// Applied to every route.
+    pub default: Option<RateLimitPolicy>,
+    /// Applied on top of the default, keyed by method and route path (e.g. `POST /users`).
+    pub routes: HashMap<(Method, String), RateLimitPolicy>,
+    pub api_key_header: Option<HeaderName>,
+    /// Keys given their own quota. Any other key is limited by client IP, so clients
+    /// cannot mint fresh quotas by making keys up.
+    pub api_keys: HashSet<String>,
+    /// HS256 secret JWTs must be signed with to be keyed by subject.
+    pub jwt_secret: Option<Vec<u8>>,
+    /// Use the left-most `X-Forwarded-For` entry as client IP, only safe behind a trusted proxy.
+    pub trust_forwarded_for: bool,
+    /// Routes never limited, such as the health probes.
+    pub exempt_paths: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            default: None,
+            routes: HashMap::new(),
+            api_key_header: None,
+            api_keys: HashSet::new(),
+            jwt_secret: None,
+            trust_forwarded_for: false,
+            exempt_paths: vec!["/ping".to_string(), "/ready".to_string()],
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Reads `RATE_LIMIT_DEFAULT` and `RATE_LIMIT_ROUTES`
+    /// (`POST /v1/users=5/1m:ip;GET /v1/users=100/1s`). Without them nothing is limited.
+    /// `api-key` needs the keys in `RATE_LIMIT_API_KEYS` (comma separated) and `jwt-sub` the
+    /// secret in `RATE_LIMIT_JWT_SECRET`, without them every client is keyed by IP.
+    pub fn from_env() -> Self {
+        let default = std::env::var("RATE_LIMIT_DEFAULT").ok().map(|policy| {
+            policy
+                .parse()
+                .expect("RATE_LIMIT_DEFAULT must look like <limit>/<period>[:<key>].")
+        });
+        let routes = std::env::var("RATE_LIMIT_ROUTES")
+            .ok()
+            .map(|routes| parse_route_policies(&routes).expect("RATE_LIMIT_ROUTES is invalid."))
+            .unwrap_or_default();
+        let api_key_header = std::env::var("RATE_LIMIT_API_KEY_HEADER")
+            .ok()
+            .map(|name| name.parse().expect("RATE_LIMIT_API_KEY_HEADER is invalid."));
+
+        let api_keys = std::env::var("RATE_LIMIT_API_KEYS")
+            .map(|keys| {
+                keys.split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        RateLimitConfig {
+            default,
+            routes,
+            api_key_header,
+            api_keys,
+            jwt_secret: std::env::var("RATE_LIMIT_JWT_SECRET")
+                .ok()
+                .map(String::into_bytes),
+            trust_forwarded_for: env_parse("RATE_LIMIT_TRUST_FORWARDED_FOR", false),
+            ..RateLimitConfig::default()
+        }
+    }
+
+    fn policies_for(&self, method: &Method, path: Option<&str>) -> Vec<(String, &RateLimitPolicy)> {
+        let mut policies = Vec::new();
+        if path.is_some_and(|path| self.exempt_paths.iter().any(|exempt| exempt == path)) {
+            return policies;
+        }
+        // The route first, a request over its own route's limit spends nothing of the default.
+        if let Some(path) = path
+            && let Some(route) = self.routes.get(&(method.clone(), path.to_string()))
+        {
+            policies.push((format!("{} {}", method, path), route));
+        }
+        if let Some(default) = &self.default {
+            policies.push(("default".to_string(), default));
+        }
+        policies
+    }
+
+    fn client_key(&self, strategy: &KeyStrategy, request: &Parts) -> String {
+        match strategy {
+            KeyStrategy::Global => "*".to_string(),
+            KeyStrategy::ClientIp => format!("ip:{}", self.client_ip(request)),
+            KeyStrategy::ApiKey => {
+                let header = self
+                    .api_key_header
+                    .clone()
+                    .unwrap_or(HeaderName::from_static("x-api-key"));
+                match request.headers.get(header).and_then(|v| v.to_str().ok()) {
+                    Some(api_key) if self.api_keys.contains(api_key) => format!("key:{}", api_key),
+                    _ => format!("ip:{}", self.client_ip(request)),
+                }
+            }
+            KeyStrategy::JwtSubject => match self
+                .jwt_secret
+                .as_deref()
+                .and_then(|secret| jwt_subject(&request.headers, secret))
+            {
+                Some(subject) => format!("sub:{}", subject),
+                None => format!("ip:{}", self.client_ip(request)),
+            },
+        }
+    }
+
+    fn client_ip(&self, request: &Parts) -> String {
+        if self.trust_forwarded_for
+            && let Some(forwarded) = request
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+        {
+            return forwarded.trim().to_string();
+        }
+        request
+            .extensions
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .and_then(|ConnectInfo(info)| info.remote_addr)
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Parses `METHOD /path=<policy>` entries separated by `;`.
+pub fn parse_route_policies(value: &str) -> Result<HashMap<(Method, String), RateLimitPolicy>> {
+    let mut routes = HashMap::new();
+    for entry in value.split(';').filter(|entry| !entry.trim().is_empty()) {
+        let (route, policy) = entry.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "route rate limit must look like METHOD /path=policy: {}",
+                entry
+            )
+        })?;
+        let (method, path) = route
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("missing method or path in {}", entry))?;
+        let method = Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+            .with_context(|| format!("invalid method in {}", entry))?;
+        routes.insert((method, path.trim().to_string()), policy.parse()?);
+    }
+    Ok(routes)
+}
+
+/// The `sub` claim of a bearer JWT, when it is signed with `secret` (HS256) and not expired.
+pub fn jwt_subject(headers: &HeaderMap, secret: &[u8]) -> Option<String> {
+    let token = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part.trim_end_matches('=')).ok();
+    let mut parts = token.split('.');
+    let (header, payload, signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let jose: serde_json::Value = serde_json::from_slice(&decode(header)?).ok()?;
+    if jose.get("alg")?.as_str()? != "HS256" {
+        return None;
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+    mac.update(format!("{}.{}", header, payload).as_bytes());
+    mac.verify_slice(&decode(signature)?).ok()?;
+
+    let claims: serde_json::Value = serde_json::from_slice(&decode(payload)?).ok()?;
+    if let Some(exp) = claims.get("exp") {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        if exp.as_u64()? <= now {
+            return None;
+        }
+    }
+    claims.get("sub")?.as_str().map(str::to_string)
+}
+
+/// Outcome of taking one token from a bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset_after: Duration,
+    /// Time until the next request would be allowed, zero when allowed.
+    pub retry_after: Duration,
+    pub policy: String,
+}
+
+impl RateLimitDecision {
+    fn apply_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("ratelimit-limit", self.limit.to_string()),
+            ("ratelimit-remaining", self.remaining.to_string()),
+            ("ratelimit-reset", ceil_secs(self.reset_after).to_string()),
+            ("ratelimit-policy", self.policy.clone()),
+        ];
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    fn into_response(self) -> Response {
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests").into_response();
+        self.apply_headers(response.headers_mut());
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(ceil_secs(self.retry_after).max(1)),
+        );
+        response
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+/// Rejects requests over their policies with `429 Too Many Requests`
+/// and reports the quota with `RateLimit-*` headers.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    config: Arc<RateLimitConfig>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimitLayer {
+    /// Layer keeping its buckets in process memory.
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimitLayer::with_store(config, Arc::new(InMemoryRateLimitStore::default()))
+    }
+
+    pub fn with_store(config: RateLimitConfig, store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimitLayer {
+            config: Arc::new(config),
+            store,
+        }
+    }
+
+    /// Uses Redis when `RATE_LIMIT_STORE=redis`, so every replica shares the same quotas.
+    pub fn from_env() -> Self {
+        let config = RateLimitConfig::from_env();
+        match std::env::var("RATE_LIMIT_STORE").as_deref() {
+            Ok("redis") => {
+                let redis = RedisConnection::from_env()
+                    .expect("REDIS_URL or REDIS_HOST must be set when RATE_LIMIT_STORE=redis.");
+                RateLimitLayer::with_store(config, Arc::new(RedisRateLimitStore::new(redis)))
+            }
+            Ok("memory") | Err(_) => RateLimitLayer::new(config),
+            Ok(other) => panic!("RATE_LIMIT_STORE must be memory or redis, got {}.", other),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            config: self.config.clone(),
+            store: self.store.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    config: Arc<RateLimitConfig>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+/// Takes a token from each policy matching the request in turn and keeps the most restrictive
+/// answer. Stops at the first denial, so a rejected request spends nothing of the policies after it.
+/// A failing store lets the request through rather than turning an outage into a 429 storm.
+async fn check(
+    config: &RateLimitConfig,
+    store: &dyn RateLimitStore,
+    request: &Parts,
+) -> Option<RateLimitDecision> {
+    let path = policy_path(&request.extensions);
+
+    let mut verdict: Option<RateLimitDecision> = None;
+    for (scope, policy) in config.policies_for(&request.method, path) {
+        let key = format!("{}|{}", scope, config.client_key(&policy.key, request));
+        let decision = match store.acquire(&key, policy).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                error!(
+                    "rate limit store failed, letting {} through: {:#}",
+                    key, err
+                );
+                continue;
+            }
+        };
+        if !decision.allowed {
+            warn!("rate limit exceeded for {}", key);
+            return Some(decision);
+        }
+        verdict = match verdict {
+            Some(current)
+                if (current.allowed, current.remaining)
+                    <= (decision.allowed, decision.remaining) =>
+            {
+                Some(current)
+            }
+            _ => Some(decision),
+        };
+    }
+    verdict
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // The clone is not ready yet, keep the one poll_ready was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            // Only the head is inspected, the body is not `Sync` and must not be held across awaits.
+            let (parts, body) = request.into_parts();
+            let decision = check(&config, store.as_ref(), &parts).await;
+            if let Some(decision) = &decision
+                && !decision.allowed
+            {
+                return Ok(decision.clone().into_response());
+            }
+
+            let mut response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(decision) = decision {
+                decision.apply_headers(response.headers_mut());
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,186 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::middleware::rate_limit::{RateLimitDecision, RateLimitPolicy};
+use anyhow::Result;
+use redis::Script;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Above this many tracked keys, buckets that refilled completely are dropped, then the
+/// least recently used ones until a tenth of the room is free again.
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+/// Where the rate limit counters live.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one request from the quota of `key` under `policy`.
+    async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitDecision>;
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Refill of the policy the bucket was filled under, to tell when it is full again.
+    capacity: f64,
+    rate: f64,
+}
+
+impl Bucket {
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * self.rate >= self.capacity
+    }
+}
+
+/// Token buckets kept in process memory, limits are per replica.
+#[derive(Debug, Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl InMemoryRateLimitStore {
+    /// Makes room for a new key. Every prune frees at least a tenth of the room, so its
+    /// O(n) cost is spread over the keys added until the next one.
+    fn prune(buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| !bucket.is_full(now));
+        let target = MAX_TRACKED_KEYS - MAX_TRACKED_KEYS / 10;
+        if buckets.len() > target {
+            let excess = buckets.len() - target;
+            let mut by_age: Vec<(Instant, &String)> = buckets
+                .iter()
+                .map(|(key, bucket)| (bucket.updated, key))
+                .collect();
+            by_age.select_nth_unstable(excess - 1);
+            let oldest: Vec<String> = by_age[..excess]
+                .iter()
+                .map(|(_, key)| key.to_string())
+                .collect();
+            for key in oldest {
+                buckets.remove(&key);
+            }
+        }
+    }
+
+    fn take(&self, key: &str, policy: &RateLimitPolicy) -> RateLimitDecision {
+        let now = Instant::now();
+        let capacity = policy.limit as f64;
+        let rate = policy.refill_per_sec();
+
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            Self::prune(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            capacity,
+            rate,
+        });
+        bucket.capacity = capacity;
+        bucket.rate = rate;
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        decision(allowed, bucket.tokens, policy)
+    }
+}
+
+/// Answer for a bucket left with `tokens` under `policy`.
+fn decision(allowed: bool, tokens: f64, policy: &RateLimitPolicy) -> RateLimitDecision {
+    let capacity = policy.limit as f64;
+    let rate = policy.refill_per_sec();
+    RateLimitDecision {
+        allowed,
+        limit: policy.limit,
+        remaining: tokens.floor() as u32,
+        reset_after: Duration::from_secs_f64((capacity - tokens).max(0.0) / rate),
+        retry_after: if allowed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - tokens).max(0.0) / rate)
+        },
+        policy: policy.describe(),
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitDecision> {
+        Ok(self.take(key, policy))
+    }
+}
+
+/// Token bucket of `KEYS[1]` holding `ARGV[1]` tokens refilled at `ARGV[2]` per millisecond,
+/// the same algorithm as `InMemoryRateLimitStore`. Runs atomically in Redis on the Redis clock,
+/// so replicas neither race nor disagree on time. Returns whether a token was taken and the
+/// tokens left, as a string since Lua numbers are truncated to integers on the way out.
+pub const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+local tokens, updated = capacity, now
+local bucket = redis.call('GET', KEYS[1])
+if bucket then
+    local separator = string.find(bucket, ':', 1, true)
+    tokens = tonumber(string.sub(bucket, 1, separator - 1))
+    updated = tonumber(string.sub(bucket, separator + 1))
+end
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * rate)
+
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+-- Kept until full again, a missing bucket is a full one.
+local ttl = math.ceil((capacity - tokens) / rate) + 1
+redis.call('SET', KEYS[1], string.format('%.17g:%.17g', tokens, now), 'PX', ttl)
+return {allowed, string.format('%.17g', tokens)}
+"#;
+
+/// Token buckets shared by every replica through Redis, see `TOKEN_BUCKET_SCRIPT`.
+#[derive(Clone)]
+pub struct RedisRateLimitStore {
+    redis: RedisConnection,
+    prefix: String,
+    script: Arc<Script>,
+}
+
+impl RedisRateLimitStore {
+    pub fn new(redis: RedisConnection) -> Self {
+        RedisRateLimitStore {
+            redis,
+            prefix: "ratelimit:".to_string(),
+            script: Arc::new(Script::new(TOKEN_BUCKET_SCRIPT)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> Result<RateLimitDecision> {
+        let capacity = policy.limit as f64;
+        let rate_per_ms = policy.refill_per_sec() / 1000.0;
+        let mut connection = self.redis.connection().await?;
+
+        let (allowed, tokens): (i64, String) = self
+            .script
+            .key(format!("{}{}", self.prefix, key))
+            .arg(capacity)
+            .arg(rate_per_ms)
+            .invoke_async(&mut connection)
+            .await?;
+        let tokens: f64 = tokens.parse()?;
+
+        Ok(decision(allowed == 1, tokens, policy))
+    }
+}
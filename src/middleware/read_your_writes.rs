@@ -0,0 +1,10 @@
+use crate::engine::replicas::tracking_writes;
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// Middleware sending the reads of a request to the primary once it wrote,
+/// so it never reads back stale data from a lagging replica.
+pub async fn read_your_writes(request: Request, next: Next) -> Response {
+    tracking_writes(next.run(request)).await
+}
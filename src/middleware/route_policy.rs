@@ -0,0 +1,375 @@
+use crate::middleware::policy_path::policy_path;
+use crate::utils::config_utils::{env_duration, parse_duration, parse_size};
+use anyhow::{Context, Result, anyhow};
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http_body_util::Limited;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::util::MapRequestLayer;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::{CompressionLayer, DefaultPredicate};
+use tower_http::decompression::{DecompressionBody, RequestDecompressionLayer};
+use tracing::warn;
+
+/// What a route gets: how long it may run, how large a body it accepts and from which
+/// response size it is compressed (`None` never compresses).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoutePolicy {
+    pub timeout: Duration,
+    pub body_limit: usize,
+    pub compress_above: Option<u16>,
+}
+
+impl Default for RoutePolicy {
+    fn default() -> Self {
+        RoutePolicy {
+            timeout: Duration::from_secs(60),
+            body_limit: 10 * 1024 * 1024,
+            // The threshold of tower-http's default predicate.
+            compress_above: Some(32),
+        }
+    }
+}
+
+/// The parts of a `RoutePolicy` a route or group changes, the others are inherited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyOverride {
+    pub timeout: Option<Duration>,
+    pub body_limit: Option<usize>,
+    pub compress_above: Option<Option<u16>>,
+}
+
+impl std::str::FromStr for PolicyOverride {
+    type Err = anyhow::Error;
+
+    /// Parses `timeout:5m,body:1kb,compress:off|<min bytes>`, every part optional.
+    fn from_str(value: &str) -> Result<Self> {
+        let mut policy = PolicyOverride::default();
+        for part in value.split(',').filter(|part| !part.trim().is_empty()) {
+            let (name, setting) = part
+                .split_once(':')
+                .ok_or_else(|| anyhow!("route policy parts look like <name>:<value>: {}", part))?;
+            match name.trim() {
+                "timeout" => policy.timeout = Some(parse_duration(setting)?),
+                "body" => policy.body_limit = Some(parse_size(setting)?),
+                "compress" => {
+                    policy.compress_above =
+                        Some(match setting.trim() {
+                            "off" => None,
+                            min => Some(parse_size(min)?.try_into().with_context(|| {
+                                format!("compress threshold too large: {}", min)
+                            })?),
+                        })
+                }
+                other => return Err(anyhow!("unknown route policy part: {}", other)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl PolicyOverride {
+    fn apply(&self, policy: &mut RoutePolicy) {
+        if let Some(timeout) = self.timeout {
+            policy.timeout = timeout;
+        }
+        if let Some(body_limit) = self.body_limit {
+            policy.body_limit = body_limit;
+        }
+        if let Some(compress_above) = self.compress_above {
+            policy.compress_above = compress_above;
+        }
+    }
+}
+
+/// Which routes an override applies to, matched against the route path (`/v1/users/{uid}`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteMatcher {
+    /// `/v1/admin/*`, every route under a prefix.
+    Group(String),
+    /// `/v1/users`, every method of one route.
+    Route(String),
+    /// `POST /v1/users`.
+    Endpoint(Method, String),
+}
+
+impl std::str::FromStr for RouteMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Some((method, path)) = value.split_once(' ') {
+            let method = Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+                .with_context(|| format!("invalid method in {}", value))?;
+            return Ok(RouteMatcher::Endpoint(method, path.trim().to_string()));
+        }
+        if !value.starts_with('/') {
+            return Err(anyhow!("route must start with /: {}", value));
+        }
+        match value.strip_suffix('*') {
+            Some(prefix) => Ok(RouteMatcher::Group(prefix.to_string())),
+            None => Ok(RouteMatcher::Route(value.to_string())),
+        }
+    }
+}
+
+impl RouteMatcher {
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        match self {
+            RouteMatcher::Group(prefix) => path.starts_with(prefix.as_str()),
+            RouteMatcher::Route(route) => path == route,
+            RouteMatcher::Endpoint(endpoint_method, route) => {
+                endpoint_method == method && path == route
+            }
+        }
+    }
+
+    /// Overrides are applied from the least to the most specific, so an endpoint wins over
+    /// its route, which wins over its groups (the longest prefix last).
+    pub fn specificity(&self) -> (u8, usize) {
+        match self {
+            RouteMatcher::Group(prefix) => (0, prefix.len()),
+            RouteMatcher::Route(_) => (1, 0),
+            RouteMatcher::Endpoint(..) => (2, 0),
+        }
+    }
+}
+
+/// Content-Encodings used for responses and accepted on requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Encodings {
+    pub gzip: bool,
+    pub br: bool,
+    pub deflate: bool,
+    pub zstd: bool,
+}
+
+impl Encodings {
+    pub const ALL: Encodings = Encodings {
+        gzip: true,
+        br: true,
+        deflate: true,
+        zstd: true,
+    };
+    pub const NONE: Encodings = Encodings {
+        gzip: false,
+        br: false,
+        deflate: false,
+        zstd: false,
+    };
+}
+
+impl std::str::FromStr for Encodings {
+    type Err = anyhow::Error;
+
+    /// `gzip,br,deflate,zstd`, `off` for none.
+    fn from_str(value: &str) -> Result<Self> {
+        let mut encodings = Encodings::NONE;
+        if value.trim() == "off" {
+            return Ok(encodings);
+        }
+        for encoding in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match encoding {
+                "gzip" => encodings.gzip = true,
+                "br" => encodings.br = true,
+                "deflate" => encodings.deflate = true,
+                "zstd" => encodings.zstd = true,
+                other => return Err(anyhow!("unknown encoding: {}", other)),
+            }
+        }
+        Ok(encodings)
+    }
+}
+
+/// Timeouts, body limits and compression of every route, with overrides per route or group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutePolicies {
+    pub default: RoutePolicy,
+    /// Kept sorted by specificity.
+    overrides: Arc<Vec<(RouteMatcher, PolicyOverride)>>,
+    /// Response encodings offered to clients.
+    pub compression: Encodings,
+    /// Request encodings decoded before the handlers, others are refused with `415`.
+    pub decompression: Encodings,
+}
+
+impl Default for RoutePolicies {
+    fn default() -> Self {
+        RoutePolicies {
+            default: RoutePolicy::default(),
+            overrides: Arc::new(Vec::new()),
+            compression: Encodings::ALL,
+            decompression: Encodings::ALL,
+        }
+    }
+}
+
+impl RoutePolicies {
+    pub fn with_override(mut self, route: RouteMatcher, policy: PolicyOverride) -> Self {
+        let overrides = Arc::make_mut(&mut self.overrides);
+        overrides.push((route, policy));
+        overrides.sort_by_key(|(route, _)| route.specificity());
+        self
+    }
+
+    /// Reads `HTTP_TIMEOUT`, `HTTP_BODY_LIMIT`, `HTTP_COMPRESS_ABOVE`, `HTTP_COMPRESSION`,
+    /// `HTTP_DECOMPRESSION` and `HTTP_ROUTE_POLICIES`
+    /// (`POST /v1/users=body:1kb;/v1/users/import=timeout:5m,body:50mb;/v1/admin/*=compress:off`).
+    pub fn from_env() -> Self {
+        let defaults = RoutePolicies::default();
+        let mut default = defaults.default;
+        if let Some(timeout) = env_duration("HTTP_TIMEOUT") {
+            default.timeout = timeout;
+        }
+        if let Ok(limit) = std::env::var("HTTP_BODY_LIMIT") {
+            default.body_limit = parse_size(&limit).expect("HTTP_BODY_LIMIT must be a size.");
+        }
+        if let Ok(threshold) = std::env::var("HTTP_COMPRESS_ABOVE") {
+            let compress: PolicyOverride = format!("compress:{}", threshold)
+                .parse()
+                .expect("HTTP_COMPRESS_ABOVE must be off or a size below 64kb.");
+            compress.apply(&mut default);
+        }
+        let encodings = |name: &str, fallback: Encodings| match std::env::var(name) {
+            Ok(value) => value
+                .parse()
+                .unwrap_or_else(|err| panic!("{} is invalid: {:#}", name, err)),
+            Err(_) => fallback,
+        };
+
+        let mut policies = RoutePolicies {
+            default,
+            compression: encodings("HTTP_COMPRESSION", defaults.compression),
+            decompression: encodings("HTTP_DECOMPRESSION", defaults.decompression),
+            ..defaults
+        };
+        if let Ok(routes) = std::env::var("HTTP_ROUTE_POLICIES") {
+            for (route, policy) in
+                parse_route_overrides(&routes).expect("HTTP_ROUTE_POLICIES is invalid.")
+            {
+                policies = policies.with_override(route, policy);
+            }
+        }
+        policies
+    }
+
+    /// The policy of `path` (a route path, not the request URI) for `method`.
+    pub fn resolve(&self, method: &Method, path: Option<&str>) -> RoutePolicy {
+        let mut policy = self.default;
+        if let Some(path) = path {
+            self.overrides
+                .iter()
+                .filter(|(route, _)| route.matches(method, path))
+                .for_each(|(_, route_policy)| route_policy.apply(&mut policy));
+        }
+        policy
+    }
+
+    /// Compresses responses with the enabled encodings, above the threshold of their route.
+    pub fn compression_layer(&self) -> CompressionLayer<RouteCompression> {
+        CompressionLayer::new()
+            .gzip(self.compression.gzip)
+            .br(self.compression.br)
+            .deflate(self.compression.deflate)
+            .zstd(self.compression.zstd)
+            .compress_when(RouteCompression)
+    }
+
+    /// Decodes request bodies before the routes, which keep receiving plain `Body`s.
+    pub fn decompression_layer(&self) -> (RequestDecompressionLayer, MapRequestLayer<IntoBody>) {
+        let enabled = self.decompression != Encodings::NONE;
+        let decompression = RequestDecompressionLayer::new()
+            .gzip(self.decompression.gzip)
+            .br(self.decompression.br)
+            .deflate(self.decompression.deflate)
+            .zstd(self.decompression.zstd)
+            // Disabled, encoded bodies reach the handlers as they are.
+            .pass_through_unaccepted(!enabled);
+        let into_body: IntoBody = |request| request.map(Body::new);
+        (decompression, MapRequestLayer::new(into_body))
+    }
+}
+
+type IntoBody = fn(axum::http::Request<DecompressionBody<Body>>) -> Request;
+
+/// Parses `<route>=<override>` entries separated by `;`.
+pub fn parse_route_overrides(value: &str) -> Result<Vec<(RouteMatcher, PolicyOverride)>> {
+    value
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (route, policy) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("route policy must look like <route>=<policy>: {}", entry)
+            })?;
+            Ok((route.parse()?, policy.parse()?))
+        })
+        .collect()
+}
+
+/// Compression threshold chosen by `route_policy`, read back by `RouteCompression`.
+#[derive(Clone, Copy, Debug)]
+struct CompressAbove(Option<u16>);
+
+/// tower-http's default predicate with the threshold of the route.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteCompression;
+
+impl Predicate for RouteCompression {
+    fn should_compress<B>(&self, response: &axum::http::Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        match response.extensions().get::<CompressAbove>() {
+            Some(CompressAbove(Some(min))) => SizeAbove::new(*min)
+                .and(NotForContentType::GRPC)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::SSE)
+                .should_compress(response),
+            Some(CompressAbove(None)) => false,
+            None => DefaultPredicate::new().should_compress(response),
+        }
+    }
+}
+
+/// Applies the policy of the matched route: rejects bodies over the limit with `413`
+/// (up front when `Content-Length` says so, while reading otherwise) and answers `408`
+/// when the handler runs out of time. Must sit inside the decompression layer so the limit
+/// counts decoded bytes, and over `DefaultBodyLimit::disable()` so axum's own 2MB limit
+/// does not apply on top of it.
+pub async fn route_policy(
+    State(policies): State<RoutePolicies>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = policy_path(request.extensions()).map(str::to_string);
+    let policy = policies.resolve(request.method(), path.as_deref());
+
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > policy.body_limit as u64) {
+        warn!(
+            "rejected a {} byte body over the {} byte limit of {}",
+            declared.unwrap_or_default(),
+            policy.body_limit,
+            path.as_deref().unwrap_or("unmatched route")
+        );
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+    let request = request.map(|body| Body::new(Limited::new(body, policy.body_limit)));
+
+    let mut response = match tokio::time::timeout(policy.timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => StatusCode::REQUEST_TIMEOUT.into_response(),
+    };
+    response
+        .extensions_mut()
+        .insert(CompressAbove(policy.compress_above));
+    response
+}
@@ -0,0 +1,192 @@
+use crate::utils::config_utils::env_parse;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// Nothing but the JSON itself may load, and no page may frame it.
+const API_CSP: &str = "default-src 'none'; frame-ancestors 'none'";
+/// Swagger UI loads its own bundle and styles, sets inline styles and uses data: icons.
+const DOCS_CSP: &str = "default-src 'self'; img-src 'self' data:; style-src 'self' 'unsafe-inline'; \
+     frame-ancestors 'none'; form-action 'none'";
+
+/// Hardening headers of a route group. A header already set by the handler is left as is,
+/// which is how a single handler overrides the group default.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityHeaders {
+    headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    /// Defaults of the API routes, responses hold user data and are never cached.
+    pub fn api() -> Self {
+        SecurityHeaders::default()
+            .with(
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static("max-age=31536000; includeSubDomains"),
+            )
+            .with(
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            )
+            .with(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"))
+            .with(
+                header::REFERRER_POLICY,
+                HeaderValue::from_static("no-referrer"),
+            )
+            .with(
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static(API_CSP),
+            )
+            .with(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))
+    }
+
+    /// Defaults of the Swagger UI and the OpenAPI documents, public and cacheable for a while.
+    pub fn docs() -> Self {
+        SecurityHeaders::api()
+            .with(
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static(DOCS_CSP),
+            )
+            .with(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("public, max-age=300"),
+            )
+    }
+
+    /// Sets `name`, replacing the current default.
+    pub fn with(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        let headers = Arc::make_mut(&mut self.headers);
+        headers.retain(|(existing, _)| *existing != name);
+        headers.push((name, value));
+        self
+    }
+
+    /// Stops setting `name`.
+    pub fn without(mut self, name: HeaderName) -> Self {
+        Arc::make_mut(&mut self.headers).retain(|(existing, _)| *existing != name);
+        self
+    }
+
+    pub fn get(&self, name: &HeaderName) -> Option<&HeaderValue> {
+        self.headers
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Adds the headers the response does not have yet.
+    fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in self.headers.iter() {
+            if !headers.contains_key(name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    /// Applies the overrides of the environment. `SECURITY_HSTS`, `SECURITY_FRAME_OPTIONS` and
+    /// `SECURITY_REFERRER_POLICY` apply to every group, `SECURITY_<GROUP>_CSP` and
+    /// `SECURITY_<GROUP>_CACHE_CONTROL` to `group` only (`SECURITY_API_CSP`...).
+    /// `off` removes the header, `SECURITY_HEADERS=false` removes them all.
+    pub fn from_env(self, group: &str) -> Self {
+        if !env_parse("SECURITY_HEADERS", true) {
+            return SecurityHeaders::default();
+        }
+        let group = group.to_ascii_uppercase();
+        [
+            (
+                "SECURITY_HSTS".to_string(),
+                header::STRICT_TRANSPORT_SECURITY,
+            ),
+            (
+                "SECURITY_FRAME_OPTIONS".to_string(),
+                header::X_FRAME_OPTIONS,
+            ),
+            (
+                "SECURITY_REFERRER_POLICY".to_string(),
+                header::REFERRER_POLICY,
+            ),
+            (
+                format!("SECURITY_{}_CSP", group),
+                header::CONTENT_SECURITY_POLICY,
+            ),
+            (
+                format!("SECURITY_{}_CACHE_CONTROL", group),
+                header::CACHE_CONTROL,
+            ),
+        ]
+        .into_iter()
+        .fold(self, |headers, (variable, name)| {
+            match std::env::var(&variable).as_deref() {
+                Ok("off") => headers.without(name),
+                Ok(value) => headers.with(
+                    name,
+                    HeaderValue::from_str(value)
+                        .unwrap_or_else(|_| panic!("{} is not a valid header value.", variable)),
+                ),
+                Err(_) => headers,
+            }
+        })
+    }
+}
+
+/// Adds the headers of the group the route belongs to, unless the handler set them.
+pub async fn security_headers(
+    State(security): State<SecurityHeaders>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    security.apply(response.headers_mut());
+    response
+}
+
+/// The groups of the whole router, told apart by path: the Swagger UI and the OpenAPI
+/// documents are `docs`, everything else (fallback included) is `api`.
+#[derive(Clone, Debug)]
+pub struct RouteSecurityHeaders {
+    api: SecurityHeaders,
+    docs: SecurityHeaders,
+    docs_prefix: &'static str,
+}
+
+impl RouteSecurityHeaders {
+    pub fn new(api: SecurityHeaders, docs: SecurityHeaders, docs_prefix: &'static str) -> Self {
+        RouteSecurityHeaders {
+            api,
+            docs,
+            docs_prefix,
+        }
+    }
+
+    /// Both groups with the overrides of the environment, see `SecurityHeaders::from_env`.
+    pub fn from_env(docs_prefix: &'static str) -> Self {
+        RouteSecurityHeaders::new(
+            SecurityHeaders::api().from_env("api"),
+            SecurityHeaders::docs().from_env("docs"),
+            docs_prefix,
+        )
+    }
+
+    fn group(&self, path: &str) -> &SecurityHeaders {
+        if path.starts_with(self.docs_prefix) || path.ends_with("/openapi.json") {
+            &self.docs
+        } else {
+            &self.api
+        }
+    }
+}
+
+/// `security_headers` for the whole router. Meant to be the outermost layer, so that answers
+/// of the other layers (429, 408, 413, 503...) and of the fallback get the headers too.
+pub async fn route_security_headers(
+    State(groups): State<RouteSecurityHeaders>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let mut response = next.run(request).await;
+    groups.group(&path).apply(response.headers_mut());
+    response
+}
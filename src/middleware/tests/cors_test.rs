@@ -0,0 +1,254 @@
+use crate::middleware::cors::*;
+use crate::test_support::http::header_of;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, Response, StatusCode, header};
+use axum::routing::get;
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn config(origins: &[&str]) -> CorsConfig {
+    CorsConfig {
+        origins: origins
+            .iter()
+            .map(|origin| origin.parse().unwrap())
+            .collect(),
+        ..CorsConfig::default()
+    }
+}
+
+fn test_router(config: CorsConfig) -> Router {
+    Router::new()
+        .route(
+            "/users",
+            get(|| async { "users" }).post(|| async { "created" }),
+        )
+        .layer(config.layer().unwrap())
+}
+
+async fn simple(app: &Router, origin: &str) -> Response<Body> {
+    let request = Request::builder()
+        .uri("/users")
+        .header(header::ORIGIN, origin)
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+async fn preflight(app: &Router, origin: &str, method: &str, headers: &str) -> Response<Body> {
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/users")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[test]
+fn test_origin_patterns() {
+    let exact: OriginPattern = "https://app.example.com/".parse().unwrap();
+    assert!(exact.matches("https://app.example.com"));
+    assert!(exact.matches("HTTPS://APP.example.com"));
+    assert!(!exact.matches("http://app.example.com"));
+    assert!(!exact.matches("https://app.example.com.evil.io"));
+
+    let subdomains: OriginPattern = "https://*.example.com".parse().unwrap();
+    assert!(subdomains.matches("https://app.example.com"));
+    assert!(subdomains.matches("https://eu.app.example.com"));
+    assert!(!subdomains.matches("https://example.com"));
+    assert!(!subdomains.matches("https://evilexample.com"));
+    assert!(!subdomains.matches("http://app.example.com"));
+    assert!(!subdomains.matches("https://app.example.com:8443"));
+    assert!(!subdomains.matches("https://example.com.evil.io"));
+
+    let with_port: OriginPattern = "http://*.localhost:3000".parse().unwrap();
+    assert!(with_port.matches("http://web.localhost:3000"));
+    assert!(!with_port.matches("http://web.localhost:3001"));
+
+    assert_eq!("*".parse::<OriginPattern>().unwrap(), OriginPattern::Any);
+    for invalid in [
+        "example.com",
+        "https://",
+        "https://a.*.example.com",
+        "https://*",
+    ] {
+        assert!(invalid.parse::<OriginPattern>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_allowed_origin_gets_cors_headers() {
+    let app = test_router(config(&["https://app.example.com"]));
+
+    let response = simple(&app, "https://app.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some("https://app.example.com")
+    );
+    let exposed = header_of(&response, header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap();
+    assert!(exposed.contains("etag") && exposed.contains("ratelimit-remaining"));
+    assert!(
+        header_of(&response, header::VARY)
+            .unwrap()
+            .contains("origin")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_other_origin_gets_no_cors_headers() {
+    let app = test_router(config(&["https://app.example.com"]));
+
+    // Still served, it is the browser that withholds the response from the script.
+    let response = simple(&app, "https://evil.io").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        None
+    );
+
+    let response = preflight(&app, "https://evil.io", "POST", "content-type").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_wildcard_subdomains() {
+    let app = test_router(config(&["https://*.example.com"]));
+
+    let response = simple(&app, "https://admin.example.com").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some("https://admin.example.com")
+    );
+    let response = simple(&app, "https://example.com").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_any_origin() {
+    let app = test_router(config(&["*"]));
+
+    let response = simple(&app, "https://anything.io").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some("*")
+    );
+}
+
+#[tokio::test]
+async fn test_preflight() {
+    let app = test_router(config(&["https://app.example.com"]));
+
+    let response = preflight(
+        &app,
+        "https://app.example.com",
+        "POST",
+        "content-type,idempotency-key",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some("https://app.example.com")
+    );
+    let methods = header_of(&response, header::ACCESS_CONTROL_ALLOW_METHODS).unwrap();
+    assert_eq!(methods, "GET,POST,PUT,PATCH,DELETE");
+    let headers = header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap();
+    assert!(headers.contains("content-type") && headers.contains("idempotency-key"));
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_MAX_AGE),
+        Some("600")
+    );
+}
+
+#[tokio::test]
+async fn test_restricted_methods_and_headers() {
+    let app = test_router(CorsConfig {
+        methods: Some(vec![Method::GET]),
+        headers: Some(vec![header::CONTENT_TYPE]),
+        max_age: Some(Duration::from_secs(60)),
+        ..config(&["https://app.example.com"])
+    });
+
+    let response = preflight(&app, "https://app.example.com", "DELETE", "x-custom").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+        Some("GET")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+        Some("content-type")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_MAX_AGE),
+        Some("60")
+    );
+}
+
+#[tokio::test]
+async fn test_credentials_mirror_any_methods_and_headers() {
+    let app = test_router(CorsConfig {
+        methods: None,
+        headers: None,
+        allow_credentials: true,
+        max_age: None,
+        ..config(&["https://app.example.com"])
+    });
+
+    let response = preflight(&app, "https://app.example.com", "PATCH", "x-custom").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+        Some("true")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+        Some("PATCH")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+        Some("x-custom")
+    );
+    assert_eq!(header_of(&response, header::ACCESS_CONTROL_MAX_AGE), None);
+}
+
+#[tokio::test]
+async fn test_any_methods_and_headers_without_credentials() {
+    let app = test_router(CorsConfig {
+        methods: None,
+        headers: None,
+        ..config(&["*"])
+    });
+
+    let response = preflight(&app, "https://app.example.com", "PATCH", "x-custom").await;
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+        Some("*")
+    );
+    assert_eq!(
+        header_of(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+        Some("*")
+    );
+}
+
+#[test]
+fn test_invalid_configs_are_rejected() {
+    assert!(config(&[]).layer().is_err());
+    let credentials_for_all = CorsConfig {
+        allow_credentials: true,
+        ..config(&["*"])
+    };
+    assert!(credentials_for_all.layer().is_err());
+}
@@ -0,0 +1,265 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::middleware::idempotency::*;
+use crate::test_support::redis_stand_in::RedisStandIn;
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode};
+use axum::middleware::from_fn_with_state;
+use axum::response::IntoResponse;
+use axum::{Router, routing::post};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn app(store: Arc<dyn IdempotencyStore>, calls: Arc<AtomicUsize>, status: StatusCode) -> Router {
+    let idempotency = Idempotency::new(store, Duration::from_secs(60), Duration::from_secs(5));
+    Router::new().route(
+        "/users",
+        post(move |body: String| async move {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            (status, format!("{} #{}", body, call)).into_response()
+        })
+        .layer(from_fn_with_state(idempotency, idempotent)),
+    )
+}
+
+fn request(key: Option<&str>, body: &str) -> Request<Body> {
+    let mut builder = Request::builder().method("POST").uri("/users");
+    if let Some(key) = key {
+        builder = builder.header(IDEMPOTENCY_KEY_HEADER, key);
+    }
+    builder.body(Body::from(body.to_string())).unwrap()
+}
+
+async fn body_of(response: axum::response::Response) -> String {
+    String::from_utf8(
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec(),
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_retry_with_same_body_is_replayed() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        calls.clone(),
+        StatusCode::CREATED,
+    );
+
+    let first = app
+        .clone()
+        .oneshot(request(Some("k1"), "alice"))
+        .await
+        .unwrap();
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert!(first.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+    assert_eq!(body_of(first).await, "alice #1");
+
+    let retry = app.oneshot(request(Some("k1"), "alice")).await.unwrap();
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(retry.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+    assert_eq!(body_of(retry).await, "alice #1");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_key_reuse_with_other_payload_is_rejected() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        calls.clone(),
+        StatusCode::CREATED,
+    );
+
+    app.clone()
+        .oneshot(request(Some("k1"), "alice"))
+        .await
+        .unwrap();
+    let reuse = app.oneshot(request(Some("k1"), "bob")).await.unwrap();
+    assert_eq!(reuse.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_requests_without_key_are_not_deduplicated() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        calls.clone(),
+        StatusCode::CREATED,
+    );
+
+    app.clone().oneshot(request(None, "alice")).await.unwrap();
+    app.oneshot(request(None, "alice")).await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_server_errors_release_the_key() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        calls.clone(),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    );
+
+    app.clone()
+        .oneshot(request(Some("k1"), "alice"))
+        .await
+        .unwrap();
+    let retry = app.oneshot(request(Some("k1"), "alice")).await.unwrap();
+    assert!(retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_in_flight_key_conflicts() {
+    let store = Arc::new(InMemoryIdempotencyStore::default());
+    let in_flight = IdempotencyRecord {
+        fingerprint: fingerprint("POST", "/users", b"alice"),
+        response: None,
+    };
+    store
+        .begin("POST /users|k1", &in_flight, Duration::from_secs(5))
+        .await
+        .unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(store, calls.clone(), StatusCode::CREATED);
+
+    let response = app.oneshot(request(Some("k1"), "alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_invalid_key_is_rejected() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = app(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        calls,
+        StatusCode::CREATED,
+    );
+
+    let long_key = "k".repeat(256);
+    let response = app
+        .oneshot(request(Some(&long_key), "alice"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_in_memory_records_expire() {
+    let store = InMemoryIdempotencyStore::default();
+    let record = IdempotencyRecord {
+        fingerprint: "f".to_string(),
+        response: None,
+    };
+
+    assert!(
+        store
+            .begin("k", &record, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        store
+            .begin("k", &record, Duration::from_secs(1))
+            .await
+            .unwrap(),
+        Some(record.clone())
+    );
+    tokio::time::advance(Duration::from_secs(2)).await;
+    assert!(
+        store
+            .begin("k", &record, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_in_memory_expiry_follows_the_latest_ttl() {
+    let store = InMemoryIdempotencyStore::default();
+    let record = IdempotencyRecord {
+        fingerprint: "f".to_string(),
+        response: None,
+    };
+
+    for key in ["a", "b", "c"] {
+        store
+            .begin(key, &record, Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+    // Completing outlives the lease, the lease running out must not drop "c".
+    store
+        .complete("c", &record, Duration::from_secs(10))
+        .await
+        .unwrap();
+    store.release("b").await.unwrap();
+    assert_eq!(store.tracked(), 2);
+
+    tokio::time::advance(Duration::from_secs(2)).await;
+    assert!(
+        store
+            .begin("d", &record, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(store.tracked(), 2);
+    assert_eq!(
+        store
+            .begin("c", &record, Duration::from_secs(1))
+            .await
+            .unwrap(),
+        Some(record.clone())
+    );
+
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert!(
+        store
+            .begin("c", &record, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(store.tracked(), 1);
+}
+
+#[tokio::test]
+async fn test_replicas_share_redis_records() {
+    let redis = RedisStandIn::start().await;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let replica = |calls: Arc<AtomicUsize>| {
+        app(
+            Arc::new(RedisIdempotencyStore::new(
+                RedisConnection::open(&redis.url()).unwrap(),
+            )),
+            calls,
+            StatusCode::CREATED,
+        )
+    };
+
+    let first = replica(calls.clone())
+        .oneshot(request(Some("k1"), "alice"))
+        .await
+        .unwrap();
+    assert_eq!(body_of(first).await, "alice #1");
+
+    let retry = replica(calls.clone())
+        .oneshot(request(Some("k1"), "alice"))
+        .await
+        .unwrap();
+    assert_eq!(retry.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+    assert_eq!(body_of(retry).await, "alice #1");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(redis.key_count(), 1);
+}
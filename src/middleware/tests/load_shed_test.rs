@@ -0,0 +1,203 @@
+use crate::middleware::load_shed::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use axum::middleware::from_fn_with_state;
+use axum::response::Response;
+use axum::routing::get;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+
+fn limit(value: &str) -> ConcurrencyLimit {
+    value.parse().unwrap()
+}
+
+/// `/slow` and `/other` hold their slot until `release` gets a permit.
+fn test_router(shedder: Arc<LoadShedder>, release: Arc<Semaphore>) -> Router {
+    let held = move || {
+        let release = release.clone();
+        async move {
+            release.acquire().await.unwrap().forget();
+            "done"
+        }
+    };
+    Router::new()
+        .route("/slow", get(held.clone()))
+        .route("/other", get(held))
+        .route("/fast", get(|| async { "fast" }))
+        .route("/ping", get(|| async { "PONG!" }))
+        .layer(from_fn_with_state(shedder, load_shed))
+}
+
+fn spawn_get(app: &Router, uri: &str) -> tokio::task::JoinHandle<Response> {
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let app = app.clone();
+    tokio::spawn(async move { app.oneshot(request).await.unwrap() })
+}
+
+async fn get_status(app: &Router, uri: &str) -> StatusCode {
+    spawn_get(app, uri).await.unwrap().status()
+}
+
+/// Lets the spawned requests reach their handler or queue.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(20)).await;
+}
+
+#[test]
+fn test_parse_concurrency_limit() {
+    assert_eq!(
+        limit("64:128"),
+        ConcurrencyLimit {
+            max_in_flight: 64,
+            max_queued: 128
+        }
+    );
+    assert_eq!(limit(" 4 ").max_queued, 0);
+    for invalid in ["", "0:10", "a:1", "4:b"] {
+        assert!(invalid.parse::<ConcurrencyLimit>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_full_queue_is_shed_at_once() {
+    let shedder = Arc::new(LoadShedder::default().with_global(limit("1:0")));
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder.clone(), release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    let response = spawn_get(&app, "/fast").await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+    let stats = shedder.stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].scope, "global");
+    assert_eq!(stats[0].in_flight, 1);
+    assert_eq!(stats[0].shed, 1);
+
+    release.add_permits(1);
+    assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+    assert_eq!(get_status(&app, "/fast").await, StatusCode::OK);
+    assert_eq!(shedder.stats()[0].in_flight, 0);
+}
+
+#[tokio::test]
+async fn test_queued_request_runs_when_a_slot_frees() {
+    let shedder = Arc::new(LoadShedder::default().with_global(limit("1:1")));
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder.clone(), release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    let queued = spawn_get(&app, "/fast");
+    settle().await;
+    assert_eq!(shedder.stats()[0].queued, 1);
+    // Queue full.
+    assert_eq!(
+        get_status(&app, "/fast").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    release.add_permits(1);
+    assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+    assert_eq!(queued.await.unwrap().status(), StatusCode::OK);
+    assert_eq!(shedder.stats()[0].queued, 0);
+}
+
+#[tokio::test]
+async fn test_queue_timeout() {
+    let shedder = Arc::new(
+        LoadShedder::default()
+            .with_global(limit("1:5"))
+            .with_queue_timeout(Duration::from_millis(50)),
+    );
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder.clone(), release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    assert_eq!(
+        get_status(&app, "/fast").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    let stats = shedder.stats();
+    assert_eq!((stats[0].queued, stats[0].shed), (0, 1));
+
+    release.add_permits(1);
+    first.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_dropped_queued_request_leaves_the_queue() {
+    let shedder = Arc::new(LoadShedder::default().with_global(limit("1:1")));
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder.clone(), release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    // A client going away, or a route timeout, drops the request while it waits.
+    let queued = spawn_get(&app, "/fast");
+    settle().await;
+    assert_eq!(shedder.stats()[0].queued, 1);
+    queued.abort();
+    assert!(queued.await.unwrap_err().is_cancelled());
+    assert_eq!(shedder.stats()[0].queued, 0);
+
+    // Its place is free again.
+    let queued = spawn_get(&app, "/fast");
+    settle().await;
+    release.add_permits(1);
+    assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+    assert_eq!(queued.await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_route_limit_leaves_other_routes_alone() {
+    let shedder = Arc::new(
+        LoadShedder::default()
+            .with_route("GET /slow", limit("1"))
+            .unwrap()
+            .with_route("/*", limit("10"))
+            .unwrap(),
+    );
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder.clone(), release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    assert_eq!(
+        get_status(&app, "/slow").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    let other = spawn_get(&app, "/other");
+    settle().await;
+    assert_eq!(get_status(&app, "/fast").await, StatusCode::OK);
+
+    let stats = shedder.stats();
+    assert_eq!(stats[0].scope, "GET /slow");
+    assert_eq!((stats[0].in_flight, stats[0].shed), (1, 1));
+    assert_eq!(stats[1].scope, "/*");
+    assert_eq!((stats[1].in_flight, stats[1].shed), (1, 0));
+
+    release.add_permits(2);
+    first.await.unwrap();
+    other.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_probes_are_never_shed() {
+    let shedder = Arc::new(LoadShedder::default().with_global(limit("1:0")));
+    let release = Arc::new(Semaphore::new(0));
+    let app = test_router(shedder, release.clone());
+
+    let first = spawn_get(&app, "/slow");
+    settle().await;
+    assert_eq!(get_status(&app, "/ping").await, StatusCode::OK);
+
+    release.add_permits(1);
+    first.await.unwrap();
+}
@@ -0,0 +1,8 @@
+mod cors_test;
+mod idempotency_test;
+mod load_shed_test;
+mod policy_path_test;
+mod rate_limit_store_test;
+mod rate_limit_test;
+mod route_policy_test;
+mod security_headers_test;
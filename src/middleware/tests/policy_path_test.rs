@@ -0,0 +1,81 @@
+use crate::middleware::policy_path::{RouteAliases, alias_policy_path, policy_path};
+use crate::middleware::route_policy::{RoutePolicies, route_policy};
+use axum::Router;
+use axum::body::{Body, Bytes, to_bytes};
+use axum::extract::{DefaultBodyLimit, Request};
+use axum::http::StatusCode;
+use axum::middleware::from_fn_with_state;
+use axum::response::Response;
+use axum::routing::{get, post};
+use tower::ServiceExt;
+
+fn aliases() -> RouteAliases {
+    RouteAliases::new("/v1", ["/users".to_string(), "/echo".to_string()])
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_aliases_are_looked_up_as_their_route() {
+    let echo_path = get(|request: Request| async move {
+        policy_path(request.extensions())
+            .unwrap_or_default()
+            .to_string()
+    });
+    let app = Router::new()
+        .route("/users", echo_path.clone())
+        .route("/v1/users", echo_path.clone())
+        .route("/ping", echo_path)
+        .layer(from_fn_with_state(aliases(), alias_policy_path));
+
+    for (uri, expected) in [
+        ("/users", "/v1/users"),
+        ("/v1/users", "/v1/users"),
+        ("/ping", "/ping"),
+    ] {
+        let response = send(&app, get_request(uri)).await;
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, expected, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_aliases_get_the_policy_of_their_route() {
+    let policies = RoutePolicies::default()
+        .with_override("/v1/echo".parse().unwrap(), "body:1kb".parse().unwrap());
+    let echo = post(|body: Bytes| async move { body });
+    let app = Router::new()
+        .route("/echo", echo.clone())
+        .route("/v1/echo", echo)
+        .layer((
+            from_fn_with_state(policies, route_policy),
+            DefaultBodyLimit::disable(),
+        ))
+        .layer(from_fn_with_state(aliases(), alias_policy_path));
+
+    for uri in ["/v1/echo", "/echo"] {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .body(Body::from("x".repeat(2048)))
+            .unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{}", uri);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .body(Body::from("small"))
+            .unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "small");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::database::redis_connection::RedisConnection;
+use crate::middleware::rate_limit::{RateLimitConfig, RateLimitLayer, RateLimitPolicy};
+use crate::middleware::rate_limit_store::*;
+use crate::server::connection::ConnectionInfo;
+use crate::test_support::redis_stand_in::RedisStandIn;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode, header};
+use axum::{Router, routing::get};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn request(client_ip: &str) -> Request<Body> {
+    let mut request = Request::builder()
+        .uri("/users")
+        .body(Body::empty())
+        .unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(ConnectionInfo::tcp(
+            format!("{}:40000", client_ip).parse().unwrap(),
+        )));
+    request
+}
+
+fn app(store: Arc<dyn RateLimitStore>, default: &str) -> Router {
+    let config = RateLimitConfig {
+        default: Some(default.parse().unwrap()),
+        ..RateLimitConfig::default()
+    };
+    Router::new()
+        .route("/users", get(|| async { "users" }))
+        .layer(RateLimitLayer::with_store(config, store))
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_in_memory_token_bucket_refills() {
+    let store = InMemoryRateLimitStore::default();
+    let policy: RateLimitPolicy = "2/1s".parse().unwrap();
+
+    assert!(store.acquire("k", &policy).await.unwrap().allowed);
+    assert!(store.acquire("k", &policy).await.unwrap().allowed);
+    let denied = store.acquire("k", &policy).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    assert_eq!(denied.retry_after, Duration::from_millis(500));
+
+    tokio::time::advance(Duration::from_millis(500)).await;
+    assert!(store.acquire("k", &policy).await.unwrap().allowed);
+    assert!(!store.acquire("k", &policy).await.unwrap().allowed);
+
+    assert!(store.acquire("other", &policy).await.unwrap().allowed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pruning_keeps_buckets_still_refilling_under_their_own_policy() {
+    let store = InMemoryRateLimitStore::default();
+    let hourly: RateLimitPolicy = "1/1h".parse().unwrap();
+    for index in 0..99_999 {
+        store
+            .acquire(&format!("k{}", index), &hourly)
+            .await
+            .unwrap();
+    }
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(store.acquire("recent", &hourly).await.unwrap().allowed);
+    assert!(!store.acquire("recent", &hourly).await.unwrap().allowed);
+
+    // A key under a fast policy fills the store, "recent" must not be forgotten.
+    let fast: RateLimitPolicy = "1000/1ms".parse().unwrap();
+    assert!(store.acquire("new", &fast).await.unwrap().allowed);
+    assert!(!store.acquire("recent", &hourly).await.unwrap().allowed);
+}
+
+#[tokio::test]
+async fn test_redis_store_is_a_token_bucket() {
+    let redis = RedisStandIn::start().await;
+    let store = RedisRateLimitStore::new(RedisConnection::open(&redis.url()).unwrap());
+    let policy: RateLimitPolicy = "2/1m".parse().unwrap();
+
+    let first = store.acquire("k", &policy).await.unwrap();
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 1);
+    assert!(first.reset_after > Duration::from_secs(29));
+    assert!(store.acquire("k", &policy).await.unwrap().allowed);
+
+    let denied = store.acquire("k", &policy).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    // One token back after half the period, like the in-memory store.
+    assert!(denied.retry_after > Duration::from_secs(29));
+    assert!(denied.retry_after <= Duration::from_secs(30));
+
+    assert!(store.acquire("other", &policy).await.unwrap().allowed);
+    assert_eq!(redis.key_count(), 2);
+}
+
+#[tokio::test]
+async fn test_redis_store_refills_without_window_boundary_bursts() {
+    let redis = RedisStandIn::start().await;
+    let store = RedisRateLimitStore::new(RedisConnection::open(&redis.url()).unwrap());
+    let policy: RateLimitPolicy = "4/400ms".parse().unwrap();
+
+    for _ in 0..4 {
+        assert!(store.acquire("k", &policy).await.unwrap().allowed);
+    }
+    assert!(!store.acquire("k", &policy).await.unwrap().allowed);
+
+    // A fixed window would hand out 4 fresh requests here, the bucket only refilled about 1.
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(store.acquire("k", &policy).await.unwrap().allowed);
+    assert!(!store.acquire("k", &policy).await.unwrap().allowed);
+
+    // Buckets expire once full again.
+    tokio::time::sleep(Duration::from_millis(450)).await;
+    assert_eq!(redis.key_count(), 0);
+}
+
+#[tokio::test]
+async fn test_replicas_share_redis_quota() {
+    let redis = RedisStandIn::start().await;
+    let replica_a = app(
+        Arc::new(RedisRateLimitStore::new(
+            RedisConnection::open(&redis.url()).unwrap(),
+        )),
+        "3/1m:ip",
+    );
+    let replica_b = app(
+        Arc::new(RedisRateLimitStore::new(
+            RedisConnection::open(&redis.url()).unwrap(),
+        )),
+        "3/1m:ip",
+    );
+
+    for replica in [&replica_a, &replica_b, &replica_a] {
+        let response = replica.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = replica_b.oneshot(request("10.0.0.1")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+}
+
+#[tokio::test]
+async fn test_unreachable_redis_fails_open() {
+    let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("redis://{}/", unused.local_addr().unwrap());
+    drop(unused);
+
+    let app = app(
+        Arc::new(RedisRateLimitStore::new(
+            RedisConnection::open(&url).unwrap(),
+        )),
+        "1/1m",
+    );
+    for _ in 0..2 {
+        let response = app.clone().oneshot(request("10.0.0.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("ratelimit-limit").is_none());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::middleware::rate_limit::*;
+use crate::server::connection::ConnectionInfo;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use axum::{
+    Router,
+    routing::{get, post},
+};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn test_router(config: RateLimitConfig) -> Router {
+    Router::new()
+        .route("/users", get(|| async { "users" }))
+        .route("/users", post(|| async { "created" }))
+        .route("/ping", get(|| async { "PONG!" }))
+        .layer(RateLimitLayer::new(config))
+}
+
+fn request(method: Method, uri: &str, client_ip: &str) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(ConnectionInfo::tcp(
+            format!("{}:40000", client_ip).parse().unwrap(),
+        )));
+    request
+}
+
+const SECRET: &[u8] = b"rate-limit-secret";
+
+fn signed_token(claims: &str, secret: &[u8]) -> String {
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+        URL_SAFE_NO_PAD.encode(claims)
+    );
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(signing_input.as_bytes());
+    format!(
+        "Bearer {}.{}",
+        signing_input,
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
+
+fn bearer(sub: &str) -> String {
+    signed_token(&format!(r#"{{"sub":"{}"}}"#, sub), SECRET)
+}
+
+#[test]
+fn test_parse_policy() {
+    let policy: RateLimitPolicy = "5/1m:api-key".parse().unwrap();
+    assert_eq!(policy.limit, 5);
+    assert_eq!(policy.period, Duration::from_secs(60));
+    assert_eq!(policy.key, KeyStrategy::ApiKey);
+
+    let policy: RateLimitPolicy = "100/1s".parse().unwrap();
+    assert_eq!(policy.key, KeyStrategy::ClientIp);
+
+    assert!("0/1s".parse::<RateLimitPolicy>().is_err());
+    assert!("10".parse::<RateLimitPolicy>().is_err());
+    assert!("10/1s:cookie".parse::<RateLimitPolicy>().is_err());
+}
+
+#[test]
+fn test_parse_route_policies() {
+    let routes = parse_route_policies("POST /users=5/1m:ip; get /users=100/1s:jwt-sub").unwrap();
+
+    assert_eq!(routes.len(), 2);
+    assert_eq!(routes[&(Method::POST, "/users".to_string())].limit, 5);
+    assert_eq!(
+        routes[&(Method::GET, "/users".to_string())].key,
+        KeyStrategy::JwtSubject
+    );
+    assert!(parse_route_policies("/users=5/1m").is_err());
+}
+
+#[test]
+fn test_jwt_subject() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(jwt_subject(&headers, SECRET), None);
+
+    headers.insert(header::AUTHORIZATION, bearer("user-42").parse().unwrap());
+    assert_eq!(jwt_subject(&headers, SECRET), Some("user-42".to_string()));
+
+    headers.insert(header::AUTHORIZATION, "Bearer not-a-jwt".parse().unwrap());
+    assert_eq!(jwt_subject(&headers, SECRET), None);
+}
+
+#[test]
+fn test_jwt_subject_needs_a_valid_signature() {
+    let subject = |token: String| {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, token.parse().unwrap());
+        jwt_subject(&headers, SECRET)
+    };
+
+    assert_eq!(
+        subject(signed_token(r#"{"sub":"mallory"}"#, b"guessed")),
+        None
+    );
+    let unsigned = format!(
+        "Bearer {}.{}.",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+        URL_SAFE_NO_PAD.encode(r#"{"sub":"mallory"}"#)
+    );
+    assert_eq!(subject(unsigned), None);
+    assert_eq!(
+        subject(signed_token(r#"{"sub":"alice","exp":1}"#, SECRET)),
+        None
+    );
+    assert_eq!(
+        subject(signed_token(r#"{"sub":"alice","exp":99999999999}"#, SECRET)),
+        Some("alice".to_string())
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_route_limit_returns_429_with_headers() {
+    let config = RateLimitConfig {
+        routes: parse_route_policies("POST /users=2/1m").unwrap(),
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    for remaining in ["1", "0"] {
+        let response = app
+            .clone()
+            .oneshot(request(Method::POST, "/users", "10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+        assert_eq!(response.headers()["ratelimit-policy"], "2;w=60");
+    }
+
+    let response = app
+        .clone()
+        .oneshot(request(Method::POST, "/users", "10.0.0.1"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+    // Other clients and other routes keep their own quota.
+    let response = app
+        .clone()
+        .oneshot(request(Method::POST, "/users", "10.0.0.2"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(request(Method::GET, "/users", "10.0.0.1"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("ratelimit-limit").is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_requests_over_the_route_limit_keep_the_default_quota() {
+    let config = RateLimitConfig {
+        default: Some("3/1m:ip".parse().unwrap()),
+        routes: parse_route_policies("POST /users=1/1m").unwrap(),
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    let statuses = [
+        StatusCode::OK,
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::TOO_MANY_REQUESTS,
+    ];
+    for status in statuses {
+        let response = app
+            .clone()
+            .oneshot(request(Method::POST, "/users", "10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+    }
+
+    // Only the accepted request came out of the default quota.
+    for remaining in ["1", "0"] {
+        let response = app
+            .clone()
+            .oneshot(request(Method::GET, "/users", "10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_default_limit_keyed_by_api_key() {
+    let config = RateLimitConfig {
+        default: Some("1/1m:api-key".parse().unwrap()),
+        api_keys: ["alpha", "beta"].map(str::to_string).into(),
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    let with_key = |key: &str| {
+        let mut request = request(Method::GET, "/users", "10.0.0.1");
+        request
+            .headers_mut()
+            .insert("x-api-key", key.parse().unwrap());
+        request
+    };
+
+    let response = app.clone().oneshot(with_key("alpha")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(with_key("alpha")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = app.clone().oneshot(with_key("beta")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Made up keys share the quota of their IP.
+    let response = app.clone().oneshot(with_key("made-up-1")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.oneshot(with_key("made-up-2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_default_limit_keyed_by_jwt_subject() {
+    let config = RateLimitConfig {
+        default: Some("1/1m:jwt-sub".parse().unwrap()),
+        jwt_secret: Some(SECRET.to_vec()),
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    let with_token = |sub: &str| {
+        let mut request = request(Method::GET, "/users", "10.0.0.1");
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, bearer(sub).parse().unwrap());
+        request
+    };
+
+    let response = app.clone().oneshot(with_token("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(with_token("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = app.clone().oneshot(with_token("bob")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Forged subjects share the quota of their IP.
+    let mut forged = request(Method::GET, "/users", "10.0.0.1");
+    forged.headers_mut().insert(
+        header::AUTHORIZATION,
+        signed_token(r#"{"sub":"carol"}"#, b"guessed")
+            .parse()
+            .unwrap(),
+    );
+    let response = app.clone().oneshot(forged).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(request(Method::GET, "/users", "10.0.0.1"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_health_probes_are_exempt() {
+    let config = RateLimitConfig {
+        default: Some("1/1m:global".parse().unwrap()),
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    for _ in 0..3 {
+        let response = app
+            .clone()
+            .oneshot(request(Method::GET, "/ping", "10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_forwarded_for_is_used_when_trusted() {
+    let config = RateLimitConfig {
+        default: Some("1/1m".parse().unwrap()),
+        trust_forwarded_for: true,
+        ..RateLimitConfig::default()
+    };
+    let app = test_router(config);
+
+    let forwarded = |ip: &str| {
+        let mut request = request(Method::GET, "/users", "172.16.0.1");
+        request.headers_mut().insert(
+            "x-forwarded-for",
+            format!("{}, 172.16.0.1", ip).parse().unwrap(),
+        );
+        request
+    };
+
+    let response = app.clone().oneshot(forwarded("203.0.113.7")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(forwarded("203.0.113.7")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = app.oneshot(forwarded("203.0.113.8")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
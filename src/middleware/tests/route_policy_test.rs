@@ -0,0 +1,308 @@
+use crate::middleware::idempotency::{
+    IDEMPOTENCY_KEY_HEADER, Idempotency, InMemoryIdempotencyStore, idempotent,
+};
+use crate::middleware::route_policy::*;
+use axum::body::{Body, Bytes, to_bytes};
+use axum::extract::DefaultBodyLimit;
+use axum::http::{Method, Request, StatusCode, header};
+use axum::middleware::from_fn_with_state;
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+const TEXT: &str = "rain on the roof, ";
+
+fn override_of(value: &str) -> PolicyOverride {
+    value.parse().unwrap()
+}
+
+fn test_router(policies: RoutePolicies) -> Router {
+    let idempotency = Idempotency::new(
+        Arc::new(InMemoryIdempotencyStore::default()),
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+    );
+    Router::new()
+        .route(
+            "/users",
+            post(|Json(value): Json<serde_json::Value>| async move { Json(value) })
+                .layer(from_fn_with_state(idempotency, idempotent)),
+        )
+        .route("/echo", post(|body: Bytes| async move { body }))
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            }),
+        )
+        .route("/text", get(|| async { TEXT.repeat(100) }))
+        .route("/admin/text", get(|| async { TEXT.repeat(100) }))
+        .layer((
+            policies.compression_layer(),
+            policies.decompression_layer(),
+            from_fn_with_state(policies.clone(), route_policy),
+            DefaultBodyLimit::disable(),
+        ))
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+fn post_request(uri: &str, body: Body) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .unwrap()
+}
+
+/// With `Content-Length`, so the limit can be checked before reading.
+fn declared(body: String) -> Request<Body> {
+    let mut request = post_request("/users", Body::empty());
+    request
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, body.len().into());
+    *request.body_mut() = Body::from(body);
+    request
+}
+
+fn json_of_size(size: usize) -> String {
+    format!("\"{}\"", "a".repeat(size - 2))
+}
+
+async fn gzipped(app: &Router, uri: &str) -> Response {
+    let request = Request::builder()
+        .uri(uri)
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    send(app, request).await
+}
+
+#[test]
+fn test_parse_route_overrides() {
+    let overrides = parse_route_overrides(
+        "POST /v1/users=body:1kb; /v1/users/import=timeout:5m,body:50mb;/v1/admin/*=compress:off",
+    )
+    .unwrap();
+    assert_eq!(
+        overrides,
+        vec![
+            (
+                RouteMatcher::Endpoint(Method::POST, "/v1/users".to_string()),
+                PolicyOverride {
+                    body_limit: Some(1024),
+                    ..PolicyOverride::default()
+                }
+            ),
+            (
+                RouteMatcher::Route("/v1/users/import".to_string()),
+                PolicyOverride {
+                    timeout: Some(Duration::from_secs(300)),
+                    body_limit: Some(50 * 1024 * 1024),
+                    ..PolicyOverride::default()
+                }
+            ),
+            (
+                RouteMatcher::Group("/v1/admin/".to_string()),
+                PolicyOverride {
+                    compress_above: Some(None),
+                    ..PolicyOverride::default()
+                }
+            ),
+        ]
+    );
+    assert_eq!(override_of("compress:1kb").compress_above, Some(Some(1024)));
+
+    for invalid in [
+        "/users",
+        "users=body:1kb",
+        "/users=body",
+        "/users=speed:1",
+        "/users=compress:1mb",
+    ] {
+        assert!(parse_route_overrides(invalid).is_err(), "{}", invalid);
+    }
+    assert_eq!(
+        "gzip, zstd".parse::<Encodings>().unwrap(),
+        Encodings {
+            gzip: true,
+            zstd: true,
+            ..Encodings::NONE
+        }
+    );
+    assert_eq!("off".parse::<Encodings>().unwrap(), Encodings::NONE);
+    assert!("lzma".parse::<Encodings>().is_err());
+}
+
+#[test]
+fn test_most_specific_override_wins() {
+    let policies = RoutePolicies::default()
+        .with_override("POST /v1/users".parse().unwrap(), override_of("body:1kb"))
+        .with_override(
+            "/v1/users".parse().unwrap(),
+            override_of("body:2kb,timeout:5s"),
+        )
+        .with_override("/v1/*".parse().unwrap(), override_of("timeout:10s"))
+        .with_override(
+            "/*".parse().unwrap(),
+            override_of("timeout:20s,compress:off"),
+        );
+
+    let create = policies.resolve(&Method::POST, Some("/v1/users"));
+    assert_eq!(create.body_limit, 1024);
+    assert_eq!(create.timeout, Duration::from_secs(5));
+    assert_eq!(create.compress_above, None);
+
+    let list = policies.resolve(&Method::GET, Some("/v1/users"));
+    assert_eq!(list.body_limit, 2048);
+
+    let other = policies.resolve(&Method::GET, Some("/v1/users/{uid}"));
+    assert_eq!(other.timeout, Duration::from_secs(10));
+    assert_eq!(other.body_limit, RoutePolicy::default().body_limit);
+
+    assert_eq!(policies.resolve(&Method::GET, None), RoutePolicy::default());
+}
+
+#[tokio::test]
+async fn test_body_limit_per_route() {
+    let app = test_router(
+        RoutePolicies::default()
+            .with_override("POST /users".parse().unwrap(), override_of("body:1kb")),
+    );
+
+    let response = send(&app, declared(json_of_size(1024))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Declared too large, refused before reading.
+    let response = send(&app, declared(json_of_size(1025))).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Found too large while reading, by the extractor or by the idempotency middleware.
+    let body = || Body::from(json_of_size(2000));
+    let response = send(&app, post_request("/users", body())).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let mut request = post_request("/users", body());
+    request
+        .headers_mut()
+        .insert(IDEMPOTENCY_KEY_HEADER, "k1".parse().unwrap());
+    assert_eq!(
+        send(&app, request).await.status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    // Other routes keep the default, above axum's own 2MB limit.
+    let large = "a".repeat(3 * 1024 * 1024);
+    let response = send(&app, post_request("/echo", Body::from(large.clone()))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body.len(), large.len());
+}
+
+#[tokio::test]
+async fn test_timeout_per_route() {
+    let app = test_router(
+        RoutePolicies::default()
+            .with_override("/slow".parse().unwrap(), override_of("timeout:50ms")),
+    );
+    let response = send(
+        &app,
+        Request::builder().uri("/slow").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+    let app = test_router(RoutePolicies::default());
+    let response = send(
+        &app,
+        Request::builder().uri("/slow").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_compression_per_route() {
+    let app = test_router(
+        RoutePolicies::default()
+            .with_override("/admin/*".parse().unwrap(), override_of("compress:off")),
+    );
+    let response = gzipped(&app, "/text").await;
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    let response = gzipped(&app, "/admin/text").await;
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+    // Smaller than the threshold.
+    let app = test_router(
+        RoutePolicies::default()
+            .with_override("/text".parse().unwrap(), override_of("compress:4kb")),
+    );
+    let response = gzipped(&app, "/text").await;
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+#[tokio::test]
+async fn test_compression_algorithms() {
+    let mut policies = RoutePolicies::default();
+    policies.compression = "br".parse().unwrap();
+    let app = test_router(policies);
+    let request = Request::builder()
+        .uri("/text")
+        .header(header::ACCEPT_ENCODING, "gzip, br")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+
+    let response = gzipped(&app, "/text").await;
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+#[tokio::test]
+async fn test_request_decompression() {
+    let app = test_router(
+        RoutePolicies::default().with_override("/echo".parse().unwrap(), override_of("body:1kb")),
+    );
+    let compressed = to_bytes(gzipped(&app, "/text").await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(compressed.len() < 1024);
+
+    let request = |encoding: &str| {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/echo")
+            .header(header::CONTENT_ENCODING, encoding)
+            .body(Body::from(compressed.clone()))
+            .unwrap()
+    };
+    // The limit applies to the decoded body.
+    assert_eq!(
+        send(&app, request("gzip")).await.status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert_eq!(
+        send(&app, request("lzma")).await.status(),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+
+    let app = test_router(RoutePolicies::default());
+    let response = send(&app, request("gzip")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, TEXT.repeat(100));
+
+    // Disabled, the body reaches the handler encoded.
+    let mut policies = RoutePolicies::default();
+    policies.decompression = Encodings::NONE;
+    let app = test_router(policies);
+    let response = send(&app, request("gzip")).await;
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body, compressed);
+}
@@ -0,0 +1,121 @@
+use crate::middleware::security_headers::*;
+use crate::test_support::http::{self, header_of};
+use axum::Router;
+use axum::http::{HeaderValue, header};
+use axum::middleware::from_fn_with_state;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+
+async fn get_response(security: SecurityHeaders, uri: &str) -> Response {
+    let app = Router::new()
+        .route("/users", get(|| async { "users" }))
+        .route(
+            "/cached",
+            get(|| async { ([(header::CACHE_CONTROL, "public, max-age=60")], "cached") }),
+        )
+        .route(
+            "/error",
+            get(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response() }),
+        )
+        .layer(from_fn_with_state(security, security_headers));
+    http::get_response(app, uri).await
+}
+
+#[tokio::test]
+async fn test_api_defaults() {
+    for uri in ["/users", "/error"] {
+        let response = get_response(SecurityHeaders::api(), uri).await;
+        assert_eq!(
+            header_of(&response, header::STRICT_TRANSPORT_SECURITY),
+            Some("max-age=31536000; includeSubDomains")
+        );
+        assert_eq!(
+            header_of(&response, header::X_CONTENT_TYPE_OPTIONS),
+            Some("nosniff")
+        );
+        assert_eq!(header_of(&response, header::X_FRAME_OPTIONS), Some("DENY"));
+        assert_eq!(
+            header_of(&response, header::REFERRER_POLICY),
+            Some("no-referrer")
+        );
+        assert_eq!(
+            header_of(&response, header::CONTENT_SECURITY_POLICY),
+            Some("default-src 'none'; frame-ancestors 'none'")
+        );
+        assert_eq!(
+            header_of(&response, header::CACHE_CONTROL),
+            Some("no-store")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_docs_relax_csp_and_caching() {
+    let response = get_response(SecurityHeaders::docs(), "/users").await;
+    let csp = header_of(&response, header::CONTENT_SECURITY_POLICY).unwrap();
+    assert!(csp.contains("default-src 'self'") && csp.contains("frame-ancestors 'none'"));
+    assert_eq!(
+        header_of(&response, header::CACHE_CONTROL),
+        Some("public, max-age=300")
+    );
+    assert_eq!(
+        header_of(&response, header::X_CONTENT_TYPE_OPTIONS),
+        Some("nosniff")
+    );
+}
+
+#[tokio::test]
+async fn test_handler_header_wins() {
+    let response = get_response(SecurityHeaders::api(), "/cached").await;
+    assert_eq!(
+        header_of(&response, header::CACHE_CONTROL),
+        Some("public, max-age=60")
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_with_and_without() {
+    let security = SecurityHeaders::api()
+        .without(header::STRICT_TRANSPORT_SECURITY)
+        .with(
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_static("SAMEORIGIN"),
+        );
+    assert_eq!(
+        security.get(&header::X_FRAME_OPTIONS),
+        Some(&HeaderValue::from_static("SAMEORIGIN"))
+    );
+
+    let response = get_response(security, "/users").await;
+    assert_eq!(
+        header_of(&response, header::STRICT_TRANSPORT_SECURITY),
+        None
+    );
+    assert_eq!(
+        header_of(&response, header::X_FRAME_OPTIONS),
+        Some("SAMEORIGIN")
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get_all(header::X_FRAME_OPTIONS)
+            .iter()
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_empty_group_adds_nothing() {
+    let response = get_response(SecurityHeaders::default(), "/users").await;
+    assert_eq!(header_of(&response, header::X_CONTENT_TYPE_OPTIONS), None);
+    assert_eq!(header_of(&response, header::CACHE_CONTROL), None);
+}
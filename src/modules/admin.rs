@@ -0,0 +1,33 @@
+use crate::handlers::admin_handler::*;
+use crate::modules::Module;
+use crate::state::AppState;
+use anyhow::{Result, anyhow};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// Operational endpoints under `/admin`, guarded by the state's `ADMIN_TOKEN`.
+pub struct AdminModule;
+
+#[async_trait::async_trait]
+impl Module for AdminModule {
+    fn name(&self) -> &'static str {
+        "admin"
+    }
+
+    fn unversioned_routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+            .routes(routes!(get_modules))
+            .routes(routes!(get_cache_stats))
+            .routes(routes!(get_load))
+            .routes(routes!(get_db_pool))
+    }
+
+    async fn on_startup(&self, state: &AppState) -> Result<()> {
+        match &state.admin_token {
+            Some(_) => Ok(()),
+            None => Err(anyhow!(
+                "ADMIN_TOKEN must be set when the admin module is enabled"
+            )),
+        }
+    }
+}
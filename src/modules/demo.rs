@@ -0,0 +1,23 @@
+use crate::handlers::simple_handler::*;
+use crate::modules::Module;
+use crate::state::AppState;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// Example handlers showing extractors and outbound calls, leave it out of `MODULES` in production.
+pub struct DemoModule;
+
+impl Module for DemoModule {
+    fn name(&self) -> &'static str {
+        "demo"
+    }
+
+    fn routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+            .routes(routes!(call_external_service))
+            .routes(routes!(protected_route))
+            .routes(routes!(get_params)) // localhost/v1/params/1/another_p/textTest
+            .routes(routes!(get_question)) // localhost/v1/question_separator?name=Jack&age=25&active=true
+            .routes(routes!(post_body_data))
+    }
+}
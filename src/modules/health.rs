@@ -0,0 +1,21 @@
+use crate::handlers::health_handler::*;
+use crate::handlers::simple_handler::*;
+use crate::modules::Module;
+use crate::state::AppState;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+/// Probes, kept outside the versions so orchestrators never have to follow a migration.
+pub struct HealthModule;
+
+impl Module for HealthModule {
+    fn name(&self) -> &'static str {
+        "health"
+    }
+
+    fn unversioned_routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+            .routes(routes!(get_pong))
+            .routes(routes!(get_ready))
+    }
+}
@@ -0,0 +1,137 @@
+use crate::state::AppState;
+use anyhow::{Result, anyhow};
+use axum::http::Extensions;
+use tracing::info;
+use utoipa_axum::router::OpenApiRouter;
+
+pub mod admin;
+pub mod demo;
+pub mod health;
+pub mod users;
+
+#[cfg(test)]
+mod tests;
+
+/// A feature of the service: its routes, what it needs in the state and what it does at startup.
+/// Modules are composed by `Modules`, the router never names handlers directly.
+#[async_trait::async_trait]
+pub trait Module: Send + Sync {
+    /// Identifier used in `MODULES`.
+    fn name(&self) -> &'static str;
+
+    /// Routes served under every API version, e.g. `/users` as `/v1/users`.
+    fn routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+    }
+
+    /// Routes served at the root, outside versioning.
+    fn unversioned_routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+    }
+
+    /// Adds the values the module's handlers read from `AppState::extensions`.
+    fn provide_state(&self, _extensions: &mut Extensions) {}
+
+    /// Runs once before the listeners accept traffic, an error aborts the startup.
+    async fn on_startup(&self, _state: &AppState) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Names of the enabled modules, available to handlers through the state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnabledModules(pub Vec<&'static str>);
+
+/// The enabled modules, in registration order.
+pub struct Modules {
+    modules: Vec<Box<dyn Module>>,
+}
+
+/// Enabled when `MODULES` is unset. `admin` is opt-in.
+pub const DEFAULT_MODULES: &[&str] = &["health", "users", "demo"];
+
+impl Modules {
+    pub fn new(modules: Vec<Box<dyn Module>>) -> Self {
+        Modules { modules }
+    }
+
+    /// Every module the service knows about.
+    pub fn available() -> Vec<Box<dyn Module>> {
+        vec![
+            Box::new(health::HealthModule),
+            Box::new(users::UsersModule),
+            Box::new(demo::DemoModule),
+            Box::new(admin::AdminModule),
+        ]
+    }
+
+    /// Keeps the available modules named in `names`, failing on unknown names.
+    pub fn enabled(names: &[&str]) -> Result<Self> {
+        let available = Modules::available();
+        if let Some(unknown) = names
+            .iter()
+            .find(|name| !available.iter().any(|module| module.name() == **name))
+        {
+            return Err(anyhow!("unknown module {}", unknown));
+        }
+        Ok(Modules::new(
+            available
+                .into_iter()
+                .filter(|module| names.contains(&module.name()))
+                .collect(),
+        ))
+    }
+
+    /// Reads the comma separated `MODULES` list, e.g. `health,users` to drop the demo endpoints.
+    pub fn from_env() -> Self {
+        let names = std::env::var("MODULES").unwrap_or_else(|_| DEFAULT_MODULES.join(","));
+        let names = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        Modules::enabled(&names).unwrap_or_else(|err| panic!("MODULES is invalid: {}.", err))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.modules.iter().map(|module| module.name()).collect()
+    }
+
+    pub fn routes(&self) -> OpenApiRouter<AppState> {
+        self.modules
+            .iter()
+            .fold(OpenApiRouter::new(), |router, module| {
+                router.merge(module.routes())
+            })
+    }
+
+    pub fn unversioned_routes(&self) -> OpenApiRouter<AppState> {
+        self.modules
+            .iter()
+            .fold(OpenApiRouter::new(), |router, module| {
+                router.merge(module.unversioned_routes())
+            })
+    }
+
+    /// `state` extended with what every module provides.
+    pub fn state(&self, state: AppState) -> AppState {
+        let mut extensions = (*state.extensions).clone();
+        extensions.insert(EnabledModules(self.names()));
+        for module in &self.modules {
+            module.provide_state(&mut extensions);
+        }
+        state.with_extensions(extensions)
+    }
+
+    /// Runs the startup hooks in registration order, stopping at the first failure.
+    pub async fn start(&self, state: &AppState) -> Result<()> {
+        for module in &self.modules {
+            module
+                .on_startup(state)
+                .await
+                .map_err(|err| err.context(format!("module {} failed to start", module.name())))?;
+            info!("module {} started", module.name());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1 @@
+mod modules_test;
@@ -0,0 +1,212 @@
+use crate::engine::db_engine::{DbPool, MockDatabaseExecutor};
+use crate::modules::admin::AdminModule;
+use crate::modules::*;
+use crate::routes::create_module_routes;
+use crate::state::AppState;
+use anyhow::anyhow;
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header};
+use axum::response::Response;
+use axum::routing::get;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tower::ServiceExt;
+use utoipa_axum::router::OpenApiRouter;
+
+fn state() -> AppState {
+    AppState::new(DbPool::Mock(MockDatabaseExecutor::new()))
+}
+
+async fn send(app: &axum::Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+#[derive(Clone)]
+struct Greeting(&'static str);
+
+/// Module exercising every hook.
+struct HelloModule {
+    started: Arc<AtomicUsize>,
+    fail: bool,
+}
+
+#[async_trait::async_trait]
+impl Module for HelloModule {
+    fn name(&self) -> &'static str {
+        "hello"
+    }
+
+    fn routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new().route(
+            "/hello",
+            get(
+                |axum::extract::State(state): axum::extract::State<AppState>| async move {
+                    state.extensions.get::<Greeting>().unwrap().0
+                },
+            ),
+        )
+    }
+
+    fn provide_state(&self, extensions: &mut axum::http::Extensions) {
+        extensions.insert(Greeting("hello"));
+    }
+
+    async fn on_startup(&self, _state: &AppState) -> anyhow::Result<()> {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        if self.fail {
+            return Err(anyhow!("boom"));
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_module_contributes_routes_and_state() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let modules = Modules::new(vec![Box::new(HelloModule {
+        started: started.clone(),
+        fail: false,
+    })]);
+    let state = modules.state(state());
+    modules.start(&state).await.unwrap();
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        state.extensions.get::<EnabledModules>().unwrap().0,
+        ["hello"]
+    );
+
+    let app = create_module_routes(state, &modules);
+    let response = send(&app, get_request("/v1/hello")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"hello");
+}
+
+#[tokio::test]
+async fn test_failing_startup_hook_names_the_module() {
+    let modules = Modules::new(vec![Box::new(HelloModule {
+        started: Arc::new(AtomicUsize::new(0)),
+        fail: true,
+    })]);
+    let err = modules.start(&state()).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("module hello failed to start: boom"));
+}
+
+#[tokio::test]
+async fn test_disabled_demo_module_is_not_routed() {
+    let modules = Modules::enabled(&["health", "users"]).unwrap();
+    let app = create_module_routes(modules.state(state()), &modules);
+
+    for uri in [
+        "/v1/its-a-rainy-day",
+        "/question_separator",
+        "/v1/question_separator",
+    ] {
+        assert_eq!(
+            send(&app, get_request(uri)).await.status(),
+            StatusCode::NOT_FOUND,
+            "{}",
+            uri
+        );
+    }
+    assert_eq!(
+        send(&app, get_request("/ping")).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[test]
+fn test_unknown_module_is_rejected() {
+    assert!(Modules::enabled(&["users", "billing"]).is_err());
+    assert_eq!(
+        Modules::enabled(DEFAULT_MODULES).unwrap().names(),
+        ["health", "users", "demo"]
+    );
+}
+
+#[tokio::test]
+async fn test_admin_module_requires_token() {
+    assert!(AdminModule.on_startup(&state()).await.is_err());
+    let empty = state().with_admin_token(Some(""));
+    assert!(AdminModule.on_startup(&empty).await.is_err());
+
+    let modules = Modules::new(vec![Box::new(AdminModule)]);
+    let state = modules.state(state().with_admin_token(Some("secret")));
+    modules.start(&state).await.unwrap();
+    let app = create_module_routes(state, &modules);
+
+    let response = send(&app, get_request("/admin/modules")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let request = Request::builder()
+        .uri("/admin/modules")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], br#"["admin"]"#);
+
+    let request = Request::builder()
+        .uri("/admin/cache")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let body = to_bytes(send(&app, request).await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], br#"{"enabled":false,"hits":0,"misses":0}"#);
+
+    let request = Request::builder()
+        .uri("/admin/load")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let body = to_bytes(send(&app, request).await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"[]");
+
+    let request = Request::builder()
+        .uri("/admin/db/pool")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_token_guards_users_without_the_admin_module() {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_restore_user()
+        .times(1)
+        .returning(|uid| {
+            Ok(Some(crate::domain::database::User {
+                uid,
+                ..Default::default()
+            }))
+        });
+    let modules = Modules::enabled(DEFAULT_MODULES).unwrap();
+    let state = AppState::new(DbPool::Mock(mock_executor)).with_admin_token(Some("secret"));
+    let app = create_module_routes(modules.state(state), &modules);
+    let restore = |token: Option<&str>| {
+        let request = Request::builder().method("POST").uri("/v1/users/7/restore");
+        match token {
+            Some(token) => request.header(header::AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    let response = send(&app, restore(None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(&app, restore(Some("secret"))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,32 @@
+use crate::handlers::bulk_handler::*;
+use crate::handlers::db_handler::*;
+use crate::middleware::idempotency::{Idempotency, idempotent};
+use crate::modules::Module;
+use crate::state::AppState;
+use axum::middleware::from_fn_with_state;
+use utoipa_axum::router::{OpenApiRouter, UtoipaMethodRouterExt};
+use utoipa_axum::routes;
+
+/// `/users` CRUD backed by the database pool, with bulk import and export.
+pub struct UsersModule;
+
+impl Module for UsersModule {
+    fn name(&self) -> &'static str {
+        "users"
+    }
+
+    fn routes(&self) -> OpenApiRouter<AppState> {
+        OpenApiRouter::new()
+            .routes(routes!(get_users))
+            .routes(
+                routes!(create_user).layer(from_fn_with_state(Idempotency::from_env(), idempotent)),
+            )
+            .routes(routes!(get_user, update_user, delete_user))
+            .routes(routes!(restore_user))
+            .routes(
+                routes!(import_users)
+                    .layer(from_fn_with_state(Idempotency::from_env(), idempotent)),
+            )
+            .routes(routes!(export_users))
+    }
+}
@@ -1,29 +1,131 @@
-use crate::handlers::simple_handler::*;
-use crate::{handlers::db_handler::*, state::AppState};
-use axum::http::StatusCode;
-use axum::{
-    Router,
-    routing::{get, post},
-};
-use std::time::Duration;
+use crate::api_version;
+use crate::middleware::cache_bypass::cache_bypass;
+use crate::middleware::cors::CorsConfig;
+use crate::middleware::load_shed::load_shed;
+use crate::middleware::policy_path::{RouteAliases, alias_policy_path};
+use crate::middleware::rate_limit::RateLimitLayer;
+use crate::middleware::read_your_writes::read_your_writes;
+use crate::middleware::route_policy::{RoutePolicies, route_policy};
+use crate::middleware::security_headers::{RouteSecurityHeaders, route_security_headers};
+use crate::modules::Modules;
+use crate::routes::openapi::SWAGGER_UI_PATH;
+use crate::routes::versioning::{ApiVersion, Deprecation, deprecated};
+use crate::server::shutdown::track_in_flight;
+use crate::state::AppState;
+use crate::utils::config_utils::env_parse;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::routing::get;
+use axum::{Json, Router};
+use std::time::{Duration, SystemTime};
+use time::Date;
+use time::macros::{datetime, format_description};
 use tower_http::classify::ServerErrorsFailureClass;
-use tower_http::compression::CompressionLayer;
-use tower_http::limit::RequestBodyLimitLayer;
-use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer};
 use tracing::Level;
 use tracing::Span;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_swagger_ui::{SwaggerUi, Url};
 
+pub mod openapi;
+pub mod versioning;
+
+#[cfg(test)]
+mod tests;
+
+/// Served versions, oldest first. To add `/v2`, append it here and deprecate `/v1`
+/// with a `Deprecation` naming `/v2` as successor.
+pub fn api_versions(modules: &Modules) -> Vec<ApiVersion> {
+    vec![api_version!("v1", modules.routes())]
+}
+
+/// The pre-versioning paths (`/users`..., and `/openapi.json` for the `/v1` document) still
+/// answer with the `/v1` handlers, flagged as deprecated. `API_UNVERSIONED_ROUTES=false`
+/// removes them, `API_UNVERSIONED_SUNSET` (`YYYY-MM-DD`) announces their removal date.
+/// The aliases come with the routes they stand for, whose policies they share.
+fn legacy_routes(
+    versions: &[ApiVersion],
+    documents: &[(Url<'static>, utoipa::openapi::OpenApi)],
+) -> Option<(Router<AppState>, RouteAliases)> {
+    if !env_parse("API_UNVERSIONED_ROUTES", true) {
+        return None;
+    }
+    let index = versions.iter().position(|version| version.name == "v1")?;
+    let v1 = &versions[index];
+    let deprecation = Deprecation {
+        // Release that introduced /v1.
+        since: SystemTime::from(datetime!(2026-10-19 00:00 UTC)),
+        sunset: std::env::var("API_UNVERSIONED_SUNSET").ok().map(|sunset| {
+            let date = Date::parse(&sunset, format_description!("[year]-[month]-[day]"))
+                .expect("API_UNVERSIONED_SUNSET must be a YYYY-MM-DD date.");
+            SystemTime::from(date.midnight().assume_utc())
+        }),
+        successor: Some(v1.prefix.to_string()),
+    };
+    let aliases = RouteAliases::new(
+        v1.prefix,
+        v1.routes.get_openapi().paths.paths.keys().cloned(),
+    );
+    let document = documents[index].1.clone();
+    let (router, _) = v1.routes.clone().split_for_parts();
+    let router = router
+        .route("/openapi.json", get(move || async move { Json(document) }))
+        .layer(from_fn_with_state(deprecation, deprecated));
+    Some((router, aliases))
+}
+
+/// Every version and the unversioned routes, with one OpenAPI document per version.
+/// Each document also lists the unversioned routes.
+pub fn api_routes(
+    versions: &[ApiVersion],
+    unversioned: OpenApiRouter<AppState>,
+) -> (
+    Router<AppState>,
+    Vec<(Url<'static>, utoipa::openapi::OpenApi)>,
+) {
+    let (mut router, unversioned) = unversioned.split_for_parts();
+    let mut documents = Vec::new();
+    for version in versions {
+        let (version_router, mut openapi) = version.build();
+        openapi.merge(unversioned.clone());
+        router = router.merge(version_router);
+        documents.push((Url::new(version.name, version.openapi_path), openapi));
+    }
+    (router, documents)
+}
+
+/// Routes of the modules enabled by `MODULES`.
 pub fn create_routes(state: AppState) -> Router {
-    Router::new()
-        .route("/users", get(get_users))
-        .route("/users", post(create_user))
-        .route("/ping", get(get_pong))
-        .route("/its-a-rainy-day", get(call_external_service))
-        .route("/protected-enter", get(protected_route))
-        .route("/params/{param_1}/another_p/{param_2}", get(get_params)) // localhost/params/1/another_p/textTest
-        .route("/question_separator", get(get_question)) // localhost/question_separator?name=Jack&age=25&active=true
-        .route("/body-data", post(post_body_data))
+    let modules = Modules::from_env();
+    let state = modules.state(state);
+    create_module_routes(state, &modules)
+}
+
+/// Routes of `modules`, `state` must already hold what they provide (see `Modules::state`).
+pub fn create_module_routes(state: AppState, modules: &Modules) -> Router {
+    create_versioned_routes(state, api_versions(modules), modules.unversioned_routes())
+}
+
+pub fn create_versioned_routes(
+    state: AppState,
+    versions: Vec<ApiVersion>,
+    unversioned: OpenApiRouter<AppState>,
+) -> Router {
+    let policies = RoutePolicies::from_env();
+    let (mut api, documents) = api_routes(&versions, unversioned);
+    let mut aliases = RouteAliases::default();
+    if let Some((legacy, legacy_aliases)) = legacy_routes(&versions, &documents) {
+        api = api.merge(legacy);
+        aliases = legacy_aliases;
+    }
+    let docs = Router::from(SwaggerUi::new(SWAGGER_UI_PATH).urls(documents));
+    let mut router = api
+        .merge(docs)
+        .layer(from_fn(cache_bypass))
+        .layer(from_fn(read_your_writes))
+        // Inside the rate limit, so requests over their quota never take a slot.
+        .layer(from_fn_with_state(state.load_shedder.clone(), load_shed))
+        .layer(RateLimitLayer::from_env())
         .layer((
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -42,11 +144,23 @@ pub fn create_routes(state: AppState) -> Router {
                         );
                     },
                 ),
-            CompressionLayer::new(),
-            RequestBodyLimitLayer::new(1024 * 1024 * 10), // 10MB limit
-            TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, Duration::from_secs(60)),
+            policies.compression_layer(),
+            policies.decompression_layer(),
+            from_fn_with_state(policies.clone(), route_policy),
+            DefaultBodyLimit::disable(),
+        ))
+        // Outside every layer keyed on the route, so aliases get the policies of their route.
+        .layer(from_fn_with_state(aliases, alias_policy_path));
+    // Outside the limits so that 408, 413 and 429 answers stay readable by browsers.
+    if let Some(cors) = CorsConfig::from_env() {
+        router = router.layer(cors.layer().expect("CORS settings are invalid."));
+    }
+    router
+        .layer(from_fn_with_state(state.lifecycle.clone(), track_in_flight))
+        // Outermost, so every answer gets them whichever layer produced it.
+        .layer(from_fn_with_state(
+            RouteSecurityHeaders::from_env(SWAGGER_UI_PATH),
+            route_security_headers,
         ))
         .with_state(state)
 }
-
-//more than 1 route file? search for "axum merge routes"
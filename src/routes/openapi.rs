@@ -0,0 +1,34 @@
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// Document metadata shared by every version, the paths and schemas come from its routes.
+#[derive(OpenApi)]
+#[openapi(
+    modifiers(&AdminTokenScheme),
+    info(title = "Excelsior", description = "Users service and demo endpoints."),
+    tags(
+        (name = "users", description = "User management"),
+        (name = "demo", description = "Example handlers"),
+        (name = "health", description = "Probes"),
+        (name = "admin", description = "Operations, needs the admin module and ADMIN_TOKEN"),
+    )
+)]
+pub struct ApiDoc;
+
+/// `admin_token` bearer scheme referenced by the admin routes.
+struct AdminTokenScheme;
+
+impl Modify for AdminTokenScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+/// Where the Swagger UI is served, listing the document of every version.
+pub const SWAGGER_UI_PATH: &str = "/swagger-ui";
@@ -0,0 +1,61 @@
+use crate::domain::database::User;
+use crate::engine::db_engine::{DbPool, MockDatabaseExecutor};
+use crate::modules::{DEFAULT_MODULES, Modules};
+use crate::routes::{api_routes, api_versions};
+use crate::state::AppState;
+use futures::StreamExt;
+use utoipa::openapi::OpenApi;
+use utoipa_swagger_ui::Url;
+
+mod openapi_test;
+mod security_headers_test;
+mod versioning_test;
+
+fn user(uid: i32) -> User {
+    User {
+        uid,
+        name: "Test User".to_string(),
+        updated_at: None,
+        ..Default::default()
+    }
+}
+
+// Existing records everywhere, so a 404 can only come from the router.
+pub fn state() -> AppState {
+    let mut mock_executor = MockDatabaseExecutor::new();
+    mock_executor
+        .expect_execute_get_users()
+        .returning(|| Ok(vec![]));
+    mock_executor
+        .expect_stream_users()
+        .returning(|_| Ok(futures::stream::empty().boxed()));
+    mock_executor
+        .expect_execute_get_users_stamp()
+        .returning(|_| Ok(Default::default()));
+    mock_executor
+        .expect_execute_create_user()
+        .returning(|_, _| Ok(user(1)));
+    mock_executor
+        .expect_execute_get_user()
+        .returning(|uid| Ok(Some(user(uid))));
+    mock_executor
+        .expect_execute_update_user()
+        .returning(|uid, _, _| Ok(Some(user(uid))));
+    mock_executor
+        .expect_execute_delete_user()
+        .returning(|_, _| Ok(true));
+    mock_executor
+        .expect_execute_restore_user()
+        .returning(|uid| Ok(Some(user(uid))));
+    AppState::new(DbPool::Mock(mock_executor))
+}
+
+pub fn default_modules() -> Modules {
+    Modules::enabled(DEFAULT_MODULES).unwrap()
+}
+
+/// Documents of every version with the default modules.
+pub fn documents() -> Vec<(Url<'static>, OpenApi)> {
+    let modules = default_modules();
+    api_routes(&api_versions(&modules), modules.unversioned_routes()).1
+}
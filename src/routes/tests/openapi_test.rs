@@ -0,0 +1,168 @@
+use crate::routes::tests::{default_modules, documents, state};
+use crate::routes::{api_routes, api_versions, create_routes};
+use crate::state::AppState;
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{Method, Request, StatusCode};
+use tower::ServiceExt;
+use utoipa::openapi::path::{Operation, PathItem};
+
+const METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::PATCH,
+];
+
+/// `/users/{uid}` becomes `/users/1`.
+fn concrete(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if segment.starts_with('{') {
+                "1"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn operation<'a>(item: &'a PathItem, method: &Method) -> Option<&'a Operation> {
+    match *method {
+        Method::GET => item.get.as_ref(),
+        Method::POST => item.post.as_ref(),
+        Method::PUT => item.put.as_ref(),
+        Method::DELETE => item.delete.as_ref(),
+        Method::PATCH => item.patch.as_ref(),
+        _ => None,
+    }
+}
+
+/// Paths `router` matches. Axum cannot list its routes, but its `Debug` output quotes them.
+fn routed_paths(router: &Router<AppState>) -> Vec<String> {
+    format!("{:?}", router)
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter(|literal| literal.starts_with('/') && !literal.contains("__private__"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `method` on `path` reaches a handler rather than the router's 404 or 405.
+async fn is_routed(app: &Router, method: &Method, path: &str) -> bool {
+    let request = Request::builder()
+        .method(method.clone())
+        .uri(concrete(path))
+        .body(Body::empty())
+        .unwrap();
+    let status = app.clone().oneshot(request).await.unwrap().status();
+    status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED
+}
+
+#[tokio::test]
+async fn test_every_documented_operation_is_routed() {
+    let documents = documents();
+    let app = create_routes(state());
+
+    let mut checked = 0;
+    let paths = documents
+        .iter()
+        .flat_map(|(_, openapi)| openapi.paths.paths.iter());
+    for (path, item) in paths {
+        for method in METHODS.iter() {
+            if operation(item, method).is_none() {
+                continue;
+            }
+            assert!(
+                is_routed(&app, method, path).await,
+                "{} {} is documented but not routed",
+                method,
+                path
+            );
+            checked += 1;
+        }
+    }
+    assert!(checked >= 12, "only {} operations documented", checked);
+}
+
+#[tokio::test]
+async fn test_every_routed_operation_is_documented() {
+    let modules = default_modules();
+    let (router, documents) = api_routes(&api_versions(&modules), modules.unversioned_routes());
+    let paths = routed_paths(&router);
+    assert!(paths.len() >= 10, "only {:?} found routed", paths);
+    let app = router.with_state(state());
+
+    for path in &paths {
+        for method in METHODS.iter() {
+            if !is_routed(&app, method, path).await {
+                continue;
+            }
+            let documented = documents.iter().any(|(_, openapi)| {
+                openapi
+                    .paths
+                    .paths
+                    .get(path)
+                    .and_then(|item| operation(item, method))
+                    .is_some()
+            });
+            assert!(
+                documented,
+                "{} {} is routed but not documented",
+                method, path
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_document_covers_domain_types() {
+    let documents = documents();
+    let openapi = &documents[0].1;
+    let schemas = &openapi.components.as_ref().unwrap().schemas;
+    for name in ["User", "NewUser", "Message", "Problem"] {
+        assert!(schemas.contains_key(name), "{} schema missing", name);
+    }
+
+    let parameters = |path: &str| {
+        openapi.paths.paths[path]
+            .get
+            .as_ref()
+            .unwrap()
+            .parameters
+            .iter()
+            .flatten()
+            .map(|parameter| parameter.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        parameters("/v1/params/{param_1}/another_p/{param_2}"),
+        ["param_1", "param_2"]
+    );
+    assert_eq!(
+        parameters("/v1/question_separator"),
+        ["name", "age", "active"]
+    );
+}
+
+#[tokio::test]
+async fn test_document_is_served() {
+    let response = create_routes(state())
+        .oneshot(
+            Request::builder()
+                .uri("/v1/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let served: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let documents = documents();
+    assert_eq!(served, serde_json::to_value(&documents[0].1).unwrap());
+}
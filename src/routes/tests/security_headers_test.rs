@@ -0,0 +1,78 @@
+use crate::routes::create_routes;
+use crate::routes::tests::state;
+use crate::test_support::http::{self, header_of};
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use axum::response::Response;
+use tower::ServiceExt;
+
+async fn get_response(uri: &str) -> Response {
+    http::get_response(create_routes(state()), uri).await
+}
+
+#[tokio::test]
+async fn test_route_groups_get_their_headers() {
+    for uri in ["/ping", "/v1/users", "/users"] {
+        let response = get_response(uri).await;
+        assert_eq!(
+            header_of(&response, header::CONTENT_SECURITY_POLICY),
+            Some("default-src 'none'; frame-ancestors 'none'"),
+            "{}",
+            uri
+        );
+        assert_eq!(
+            header_of(&response, header::X_CONTENT_TYPE_OPTIONS),
+            Some("nosniff")
+        );
+    }
+
+    for uri in ["/v1/openapi.json", "/openapi.json"] {
+        let response = get_response(uri).await;
+        assert!(
+            header_of(&response, header::CONTENT_SECURITY_POLICY)
+                .unwrap()
+                .contains("default-src 'self'")
+        );
+        assert_eq!(
+            header_of(&response, header::CACHE_CONTROL),
+            Some("public, max-age=300")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_answers_of_layers_and_fallback_get_headers() {
+    let response = get_response("/nowhere").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        header_of(&response, header::X_CONTENT_TYPE_OPTIONS),
+        Some("nosniff")
+    );
+
+    // Rejected by the route policy from its Content-Length alone.
+    let request = Request::post("/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_LENGTH, "1000000000000")
+        .body(Body::empty())
+        .unwrap();
+    let response = create_routes(state()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        header_of(&response, header::CONTENT_SECURITY_POLICY),
+        Some("default-src 'none'; frame-ancestors 'none'")
+    );
+    assert_eq!(
+        header_of(&response, header::CACHE_CONTROL),
+        Some("no-store")
+    );
+}
+
+#[tokio::test]
+async fn test_handlers_with_validators_allow_private_caching() {
+    let response = get_response("/v1/users/1").await;
+    assert_eq!(
+        header_of(&response, header::CACHE_CONTROL),
+        Some("private, no-cache")
+    );
+    assert!(response.headers().contains_key(header::ETAG));
+}
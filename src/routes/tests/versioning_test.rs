@@ -0,0 +1,147 @@
+use crate::api_version;
+use crate::routes::tests::{default_modules, state};
+use crate::routes::versioning::{ApiVersion, Deprecation};
+use crate::routes::{api_versions, create_routes, create_versioned_routes};
+use crate::state::AppState;
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode, header};
+use axum::response::Response;
+use axum::routing::get;
+use std::time::{Duration, SystemTime};
+use tower::ServiceExt;
+use utoipa_axum::router::OpenApiRouter;
+
+fn v2_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().route("/users", get(|| async { "v2 users" }))
+}
+
+fn v1_deprecated_for_v2() -> Vec<ApiVersion> {
+    let v1 = api_version!("v1", default_modules().routes()).deprecated(Deprecation {
+        since: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        sunset: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000)),
+        successor: Some("/v2".to_string()),
+    });
+    vec![v1, api_version!("v2", v2_routes())]
+}
+
+async fn send(app: &axum::Router, uri: &str) -> Response {
+    app.clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_current_version_is_not_deprecated() {
+    let app = create_routes(state());
+
+    let response = send(&app, "/v1/users").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
+
+    let response = send(&app, "/ready").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
+}
+
+#[tokio::test]
+async fn test_unversioned_paths_are_deprecated_aliases() {
+    let app = create_routes(state());
+
+    let response = send(&app, "/users").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response.headers()["deprecation"]
+            .to_str()
+            .unwrap()
+            .starts_with('@')
+    );
+    assert_eq!(
+        response.headers()[header::LINK],
+        "</v1/openapi.json>; rel=\"successor-version\""
+    );
+}
+
+#[tokio::test]
+async fn test_unversioned_document_is_a_deprecated_alias() {
+    let app = create_routes(state());
+
+    let response = send(&app, "/openapi.json").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("deprecation"));
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let alias: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    let response = send(&app, "/v1/openapi.json").await;
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let v1: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(alias, v1);
+}
+
+#[tokio::test]
+async fn test_versions_are_served_side_by_side() {
+    let app = create_versioned_routes(
+        state(),
+        v1_deprecated_for_v2(),
+        default_modules().unversioned_routes(),
+    );
+
+    let response = send(&app, "/v2/users").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
+
+    let response = send(&app, "/v1/users").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "@1700000000");
+    assert_eq!(
+        response.headers()["sunset"],
+        "Fri, 15 Jan 2027 08:00:00 GMT"
+    );
+    assert_eq!(
+        response.headers()[header::LINK],
+        "</v2/openapi.json>; rel=\"successor-version\""
+    );
+}
+
+#[tokio::test]
+async fn test_each_version_has_its_own_document() {
+    let app = create_versioned_routes(
+        state(),
+        v1_deprecated_for_v2(),
+        default_modules().unversioned_routes(),
+    );
+
+    let response = send(&app, "/v1/openapi.json").await;
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let v1: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(v1["info"]["version"], "v1");
+    assert_eq!(v1["paths"]["/v1/users"]["get"]["deprecated"], true);
+    assert!(v1["paths"].get("/v2/users").is_none());
+    assert!(v1["paths"].get("/ready").is_some());
+
+    let response = send(&app, "/v2/openapi.json").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_api_version_paths() {
+    let versions = api_versions(&default_modules());
+    assert_eq!(versions[0].name, "v1");
+    assert_eq!(versions[0].prefix, "/v1");
+    assert_eq!(versions[0].openapi_path, "/v1/openapi.json");
+}
+
+#[tokio::test]
+async fn test_created_user_location_keeps_the_version() {
+    let app = create_routes(state());
+
+    for (path, location) in [("/v1/users", "/v1/users/1"), ("/users", "/users/1")] {
+        let request = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name":"Test User"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::LOCATION], location);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::routes::openapi::ApiDoc;
+use crate::state::AppState;
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::middleware::{Next, from_fn_with_state};
+use axum::response::Response;
+use std::time::SystemTime;
+use utoipa::OpenApi;
+use utoipa::openapi::Deprecated;
+use utoipa_axum::router::OpenApiRouter;
+
+/// When a version stopped being recommended, and when it will be removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deprecation {
+    pub since: SystemTime,
+    pub sunset: Option<SystemTime>,
+    /// Prefix of the version replacing this one, e.g. `/v2`.
+    pub successor: Option<String>,
+}
+
+impl Deprecation {
+    /// `Deprecation` (RFC 9745), `Sunset` (RFC 8594) and a `successor-version` link to the
+    /// document of the replacing version.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let since = self
+            .since
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        headers.insert(
+            "deprecation",
+            HeaderValue::from_str(&format!("@{}", since)).expect("valid header value"),
+        );
+        if let Some(sunset) = self.sunset {
+            headers.insert(
+                "sunset",
+                HeaderValue::from_str(&httpdate::fmt_http_date(sunset))
+                    .expect("http date is a valid header value"),
+            );
+        }
+        if let Some(successor) = &self.successor
+            && let Ok(link) = HeaderValue::from_str(&format!(
+                "<{}/openapi.json>; rel=\"successor-version\"",
+                successor
+            ))
+        {
+            headers.append(header::LINK, link);
+        }
+    }
+}
+
+/// Middleware marking every response of a deprecated version.
+pub async fn deprecated(
+    State(deprecation): State<Deprecation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    deprecation.apply(response.headers_mut());
+    response
+}
+
+/// A set of routes mounted under `prefix`, with its own OpenAPI document.
+/// Several versions can be served side by side, e.g. `/v1` kept for old clients next to `/v2`.
+pub struct ApiVersion {
+    /// `v1`, shown in the Swagger UI version selector.
+    pub name: &'static str,
+    /// `/v1`.
+    pub prefix: &'static str,
+    /// `/v1/openapi.json`.
+    pub openapi_path: &'static str,
+    pub routes: OpenApiRouter<AppState>,
+    pub deprecation: Option<Deprecation>,
+}
+
+/// `api_version!("v1", modules.routes())` mounts the routes under `/v1`.
+#[macro_export]
+macro_rules! api_version {
+    ($name:literal, $routes:expr) => {
+        $crate::routes::versioning::ApiVersion {
+            name: $name,
+            prefix: concat!("/", $name),
+            openapi_path: concat!("/", $name, "/openapi.json"),
+            routes: $routes,
+            deprecation: None,
+        }
+    };
+}
+
+impl ApiVersion {
+    pub fn deprecated(mut self, deprecation: Deprecation) -> Self {
+        self.deprecation = Some(deprecation);
+        self
+    }
+
+    /// The routes nested under the prefix, and their document with prefixed paths.
+    /// Operations of a deprecated version are flagged as such in the document.
+    pub fn build(&self) -> (Router<AppState>, utoipa::openapi::OpenApi) {
+        let mut doc = ApiDoc::openapi();
+        doc.info.version = self.name.to_string();
+        let (router, mut openapi) = OpenApiRouter::with_openapi(doc)
+            .nest(self.prefix, self.routes.clone())
+            .split_for_parts();
+
+        match &self.deprecation {
+            Some(deprecation) => {
+                for item in openapi.paths.paths.values_mut() {
+                    for operation in [
+                        &mut item.get,
+                        &mut item.put,
+                        &mut item.post,
+                        &mut item.delete,
+                        &mut item.patch,
+                    ]
+                    .into_iter()
+                    .flatten()
+                    {
+                        operation.deprecated = Some(Deprecated::True);
+                    }
+                }
+                let router = router.layer(from_fn_with_state(deprecation.clone(), deprecated));
+                (router, openapi)
+            }
+            None => (router, openapi),
+        }
+    }
+}
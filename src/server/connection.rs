@@ -0,0 +1,73 @@
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::serve::IncomingStream;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+/// Information about the peer of an accepted connection, available to handlers
+/// through `ConnectInfo<ConnectionInfo>`.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionInfo {
+    pub remote_addr: Option<SocketAddr>,
+    pub client_identity: Option<ClientIdentity>,
+}
+
+/// Identity taken from a verified client certificate (mutual TLS).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub dns_names: Vec<String>,
+    pub uris: Vec<String>,
+    pub serial: String,
+}
+
+impl ConnectionInfo {
+    pub fn tcp(remote_addr: SocketAddr) -> Self {
+        ConnectionInfo {
+            remote_addr: Some(remote_addr),
+            client_identity: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        ConnectionInfo::tcp(*stream.remote_addr())
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientIdentity
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let identity =
+            <ClientIdentity as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+                .await
+                .unwrap_or_default();
+        identity.ok_or((StatusCode::UNAUTHORIZED, "Client certificate required"))
+    }
+}
+
+impl<S> OptionalFromRequestParts<S> for ClientIdentity
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .and_then(|ConnectInfo(info)| info.client_identity.clone()))
+    }
+}
@@ -0,0 +1,271 @@
+use crate::server::connection::ConnectionInfo;
+use crate::server::tls::{ReloadableTlsConfig, TlsListener};
+use anyhow::{Context, Result, anyhow};
+use axum::Router;
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::warn;
+
+/// First file descriptor passed by the service manager (SD_LISTEN_FDS_START).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// One entry of the `MS_LISTEN` list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenSpec {
+    /// `0.0.0.0:3000`, `[::]:3000`, `127.0.0.1:8080`...
+    Tcp(SocketAddr),
+    /// `unix:/run/excelsior.sock` or `unix:/run/excelsior.sock?mode=660`
+    Unix { path: PathBuf, mode: Option<u32> },
+    /// `fd:3`, a socket inherited from the parent process.
+    Fd(i32),
+    /// `systemd`, every socket passed through `LISTEN_FDS`.
+    Systemd,
+}
+
+impl std::str::FromStr for ListenSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec == "systemd" {
+            return Ok(ListenSpec::Systemd);
+        }
+        if let Some(fd) = spec.strip_prefix("fd:") {
+            return Ok(ListenSpec::Fd(
+                fd.parse()
+                    .with_context(|| format!("invalid fd in {}", spec))?,
+            ));
+        }
+        if let Some(rest) = spec.strip_prefix("unix:") {
+            let (path, mode) = match rest.split_once("?mode=") {
+                Some((path, mode)) => (
+                    path,
+                    Some(
+                        u32::from_str_radix(mode, 8)
+                            .with_context(|| format!("invalid socket mode in {}", spec))?,
+                    ),
+                ),
+                None => (rest, None),
+            };
+            if path.is_empty() {
+                return Err(anyhow!("missing socket path in {}", spec));
+            }
+            return Ok(ListenSpec::Unix {
+                path: PathBuf::from(path),
+                mode,
+            });
+        }
+        spec.parse::<SocketAddr>()
+            .map(ListenSpec::Tcp)
+            .with_context(|| format!("invalid listen address: {}", spec))
+    }
+}
+
+/// Parses a comma separated list of listen specs.
+pub fn parse_listen_specs(value: &str) -> Result<Vec<ListenSpec>> {
+    let specs = value
+        .split(',')
+        .filter(|spec| !spec.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<ListenSpec>>>()?;
+    if specs.is_empty() {
+        return Err(anyhow!("no listen address given"));
+    }
+    Ok(specs)
+}
+
+/// A listener ready to be served.
+#[derive(Debug)]
+pub enum BoundListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl fmt::Display for BoundListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundListener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "tcp:?"),
+            },
+            #[cfg(unix)]
+            BoundListener::Unix(_, Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            BoundListener::Unix(_, None) => write!(f, "unix:(inherited)"),
+        }
+    }
+}
+
+impl ListenSpec {
+    pub async fn bind(&self) -> Result<Vec<BoundListener>> {
+        match self {
+            ListenSpec::Tcp(addr) => Ok(vec![BoundListener::Tcp(
+                TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("failed to bind {}", addr))?,
+            )]),
+            #[cfg(unix)]
+            ListenSpec::Unix { path, mode } => Ok(vec![bind_unix(path, *mode)?]),
+            #[cfg(unix)]
+            ListenSpec::Fd(fd) => Ok(vec![listener_from_fd(*fd)?]),
+            #[cfg(unix)]
+            ListenSpec::Systemd => {
+                let listeners = inherited_listeners()?;
+                if listeners.is_empty() {
+                    return Err(anyhow!("no sockets were passed through LISTEN_FDS"));
+                }
+                Ok(listeners)
+            }
+            #[cfg(not(unix))]
+            _ => Err(anyhow!("{:?} is only supported on unix", self)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &PathBuf, mode: Option<u32>) -> Result<BoundListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // A socket file left behind by a previous run would make bind fail.
+    if let Ok(meta) = std::fs::symlink_metadata(path)
+        && meta.file_type().is_socket()
+    {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(BoundListener::Unix(listener, Some(path.clone())))
+}
+
+/// Wraps an already opened listening socket, detecting whether it is a TCP or a Unix socket.
+#[cfg(unix)]
+pub fn listener_from_fd(fd: i32) -> Result<BoundListener> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    // SAFETY: the fd was handed to this process to listen on and is owned from here on.
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+    if unix.local_addr().is_ok() {
+        // The socket file belongs to the service manager, so it is not removed on shutdown.
+        unix.set_nonblocking(true)?;
+        return Ok(BoundListener::Unix(UnixListener::from_std(unix)?, None));
+    }
+
+    // SAFETY: same fd, ownership moved back out of the unix wrapper above.
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+    tcp.local_addr()
+        .with_context(|| format!("fd {} is not a listening socket", fd))?;
+    tcp.set_nonblocking(true)?;
+    Ok(BoundListener::Tcp(TcpListener::from_std(tcp)?))
+}
+
+/// Fds passed by a socket-activating service manager, following the `LISTEN_FDS` protocol.
+#[cfg(unix)]
+pub fn inherited_fds(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    current_pid: u32,
+) -> Vec<i32> {
+    let Some(count) = listen_fds.and_then(|fds| fds.parse::<i32>().ok()) else {
+        return Vec::new();
+    };
+    if let Some(pid) = listen_pid
+        && pid.parse::<u32>().ok() != Some(current_pid)
+    {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0)).collect()
+}
+
+#[cfg(unix)]
+fn inherited_listeners() -> Result<Vec<BoundListener>> {
+    inherited_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )
+    .into_iter()
+    .map(listener_from_fd)
+    .collect()
+}
+
+/// Binds every listener described by `MS_LISTEN`.
+/// Without it, sockets passed by the service manager are used, then `0.0.0.0:MS_PORT`.
+pub async fn bind_listeners_from_env() -> Result<Vec<BoundListener>> {
+    let specs = match std::env::var("MS_LISTEN") {
+        Ok(value) => parse_listen_specs(&value)?,
+        Err(_) if cfg!(unix) && std::env::var("LISTEN_FDS").is_ok() => vec![ListenSpec::Systemd],
+        Err(_) => {
+            let pre_port = std::env::var("MS_PORT").expect("MS_PORT must be set.");
+            let port = pre_port.parse().expect("MS_PORT must be a number.");
+            vec![ListenSpec::Tcp(SocketAddr::from(([0, 0, 0, 0], port)))]
+        }
+    };
+
+    let mut listeners = Vec::new();
+    for spec in &specs {
+        listeners.extend(spec.bind().await?);
+    }
+    Ok(listeners)
+}
+
+impl BoundListener {
+    /// Serves the router on this listener until `shutdown` completes.
+    /// TLS, when configured, is applied to TCP listeners only.
+    pub async fn serve<F>(
+        self,
+        app: Router,
+        tls: Option<ReloadableTlsConfig>,
+        shutdown: F,
+    ) -> std::io::Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let make_service = app.into_make_service_with_connect_info::<ConnectionInfo>();
+        match self {
+            BoundListener::Tcp(listener) => match tls {
+                Some(tls) => {
+                    axum::serve(TlsListener::new(listener, tls), make_service)
+                        .with_graceful_shutdown(shutdown)
+                        .await
+                }
+                None => {
+                    axum::serve(listener, make_service)
+                        .with_graceful_shutdown(shutdown)
+                        .await
+                }
+            },
+            #[cfg(unix)]
+            BoundListener::Unix(listener, path) => {
+                let result = axum::serve(listener, make_service)
+                    .with_graceful_shutdown(shutdown)
+                    .await;
+                if let Some(path) = path
+                    && let Err(err) = std::fs::remove_file(&path)
+                {
+                    warn!("Failed to remove socket {}: {}", path.display(), err);
+                }
+                result
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, UnixListener>> for ConnectionInfo {
+    fn connect_info(_stream: IncomingStream<'_, UnixListener>) -> Self {
+        ConnectionInfo::default()
+    }
+}
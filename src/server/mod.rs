@@ -0,0 +1,7 @@
+pub mod connection;
+pub mod listener;
+pub mod shutdown;
+pub mod tls;
+
+#[cfg(test)]
+mod tests;
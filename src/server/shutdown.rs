@@ -0,0 +1,184 @@
+use crate::utils::config_utils::env_duration;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+/// Readiness flag and in-flight request counter shared by the router and the shutdown coordinator.
+#[derive(Debug)]
+pub struct Lifecycle {
+    ready: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            ready: AtomicBool::new(true),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+}
+
+pub struct InFlightGuard(Arc<Lifecycle>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Middleware keeping `Lifecycle::in_flight` up to date.
+pub async fn track_in_flight(
+    State(lifecycle): State<Arc<Lifecycle>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _guard = lifecycle.track();
+    next.run(request).await
+}
+
+#[derive(Clone, Debug)]
+pub struct ShutdownSettings {
+    /// Time between failing readiness and closing the listeners, so load balancers stop routing here.
+    pub pre_stop_delay: Duration,
+    /// Maximum time given to in-flight requests once the listeners are closed.
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        ShutdownSettings {
+            pre_stop_delay: Duration::from_secs(0),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ShutdownSettings {
+    pub fn from_env() -> Self {
+        let default = ShutdownSettings::default();
+        ShutdownSettings {
+            pre_stop_delay: env_duration("SHUTDOWN_PRE_STOP_DELAY")
+                .unwrap_or(default.pre_stop_delay),
+            drain_timeout: env_duration("SHUTDOWN_DRAIN_TIMEOUT").unwrap_or(default.drain_timeout),
+        }
+    }
+}
+
+/// What happened while draining.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub drained: bool,
+    pub cut_off_requests: usize,
+    pub drain_elapsed: Duration,
+}
+
+pub struct ShutdownCoordinator {
+    settings: ShutdownSettings,
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(settings: ShutdownSettings, lifecycle: Arc<Lifecycle>) -> Self {
+        ShutdownCoordinator {
+            settings,
+            lifecycle,
+        }
+    }
+
+    /// Waits for `signal`, flips readiness, waits the pre-stop delay, then tells the servers
+    /// to stop accepting and gives them until the drain deadline to finish.
+    /// Servers still running at the deadline are aborted, their open connections end with the process.
+    pub async fn run<F>(
+        &self,
+        signal: F,
+        stop_accepting: watch::Sender<bool>,
+        servers: &mut JoinSet<std::io::Result<()>>,
+    ) -> ShutdownReport
+    where
+        F: Future<Output = ()>,
+    {
+        signal.await;
+
+        self.lifecycle.set_ready(false);
+        warn!(
+            "readiness set to failing, stopping listeners in {:?}",
+            self.settings.pre_stop_delay
+        );
+        tokio::time::sleep(self.settings.pre_stop_delay).await;
+
+        let _ = stop_accepting.send(true);
+        info!(
+            "listeners closed, draining {} in-flight requests",
+            self.lifecycle.in_flight()
+        );
+
+        let started = Instant::now();
+        let drained = tokio::time::timeout(self.settings.drain_timeout, async {
+            while let Some(result) = servers.join_next().await {
+                match result {
+                    Ok(Err(err)) => error!("server error: {}", err),
+                    Err(err) if err.is_panic() => error!("server task panicked: {}", err),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .is_ok();
+
+        let cut_off_requests = if drained {
+            0
+        } else {
+            let pending = self.lifecycle.in_flight();
+            servers.abort_all();
+            while servers.join_next().await.is_some() {}
+            pending
+        };
+
+        ShutdownReport {
+            drained,
+            cut_off_requests,
+            drain_elapsed: started.elapsed(),
+        }
+    }
+
+    /// Runs `cleanup` within what is left of the drain budget after `report`.
+    /// `false` when it was cut short, so closing pools cannot hang the shutdown.
+    pub async fn finish<F>(&self, report: &ShutdownReport, cleanup: F) -> bool
+    where
+        F: Future<Output = ()>,
+    {
+        let remaining = self
+            .settings
+            .drain_timeout
+            .saturating_sub(report.drain_elapsed);
+        tokio::time::timeout(remaining, cleanup).await.is_ok()
+    }
+}
@@ -0,0 +1,130 @@
+use crate::server::listener::*;
+use axum::{Router, routing::get};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn test_parse_tcp_specs() {
+    let specs = parse_listen_specs("0.0.0.0:3000, [::]:3001,127.0.0.1:8080").unwrap();
+
+    assert_eq!(
+        specs,
+        vec![
+            ListenSpec::Tcp("0.0.0.0:3000".parse::<SocketAddr>().unwrap()),
+            ListenSpec::Tcp("[::]:3001".parse::<SocketAddr>().unwrap()),
+            ListenSpec::Tcp("127.0.0.1:8080".parse::<SocketAddr>().unwrap()),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_unix_and_fd_specs() {
+    let specs =
+        parse_listen_specs("unix:/run/ms1.sock?mode=660,unix:/tmp/a.sock,fd:3,systemd").unwrap();
+
+    assert_eq!(
+        specs,
+        vec![
+            ListenSpec::Unix {
+                path: PathBuf::from("/run/ms1.sock"),
+                mode: Some(0o660),
+            },
+            ListenSpec::Unix {
+                path: PathBuf::from("/tmp/a.sock"),
+                mode: None,
+            },
+            ListenSpec::Fd(3),
+            ListenSpec::Systemd,
+        ]
+    );
+}
+
+#[test]
+fn test_parse_invalid_specs() {
+    assert!(parse_listen_specs("").is_err());
+    assert!(parse_listen_specs("localhost").is_err());
+    assert!(parse_listen_specs("unix:").is_err());
+    assert!(parse_listen_specs("unix:/tmp/a.sock?mode=999").is_err());
+    assert!(parse_listen_specs("fd:three").is_err());
+}
+
+#[test]
+fn test_inherited_fds() {
+    assert_eq!(inherited_fds(Some("42"), Some("2"), 42), vec![3, 4]);
+    assert_eq!(inherited_fds(None, Some("1"), 42), vec![3]);
+    assert!(inherited_fds(Some("7"), Some("2"), 42).is_empty());
+    assert!(inherited_fds(Some("42"), None, 42).is_empty());
+}
+
+#[tokio::test]
+async fn test_bind_tcp_listener() {
+    let listeners = ListenSpec::Tcp("127.0.0.1:0".parse().unwrap())
+        .bind()
+        .await
+        .unwrap();
+
+    assert_eq!(listeners.len(), 1);
+    assert!(matches!(listeners[0], BoundListener::Tcp(_)));
+    assert!(listeners[0].to_string().starts_with("127.0.0.1:"));
+}
+
+#[tokio::test]
+async fn test_listener_from_inherited_tcp_fd() {
+    use std::os::fd::IntoRawFd;
+
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+
+    let listener = listener_from_fd(std_listener.into_raw_fd()).unwrap();
+
+    assert!(matches!(listener, BoundListener::Tcp(_)));
+    assert_eq!(listener.to_string(), addr.to_string());
+}
+
+#[tokio::test]
+async fn test_listener_from_inherited_unix_fd() {
+    use std::os::fd::IntoRawFd;
+
+    let path = std::env::temp_dir().join(format!("excelsior-fd-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let std_listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+    let listener = listener_from_fd(std_listener.into_raw_fd()).unwrap();
+
+    assert!(matches!(listener, BoundListener::Unix(_, None)));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_serve_on_unix_socket_with_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("excelsior-{}.sock", std::process::id()));
+    let spec: ListenSpec = format!("unix:{}?mode=600", path.display()).parse().unwrap();
+    let listener = spec.bind().await.unwrap().remove(0);
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let app = Router::new().route("/ping", get(|| async { "PONG!" }));
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(listener.serve(app, None, async move {
+        let _ = stop_rx.await;
+    }));
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("PONG!"));
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert!(!path.exists());
+}
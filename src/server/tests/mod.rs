@@ -0,0 +1,3 @@
+mod listener_test;
+mod shutdown_test;
+mod tls_test;
@@ -0,0 +1,174 @@
+use crate::server::shutdown::*;
+use axum::middleware::from_fn_with_state;
+use axum::{Router, routing::get};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinSet;
+
+async fn spawn_server(
+    lifecycle: Arc<Lifecycle>,
+    handler_delay: Duration,
+) -> (
+    SocketAddr,
+    watch::Sender<bool>,
+    JoinSet<std::io::Result<()>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new()
+        .route(
+            "/slow",
+            get(move || async move {
+                tokio::time::sleep(handler_delay).await;
+                "DONE"
+            }),
+        )
+        .layer(from_fn_with_state(lifecycle, track_in_flight));
+
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let mut servers = JoinSet::new();
+    servers.spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                let _ = stop_rx.wait_for(|stop| *stop).await;
+            })
+            .await
+    });
+    (addr, stop_tx, servers)
+}
+
+async fn wait_for_in_flight(lifecycle: &Lifecycle, expected: usize) {
+    while lifecycle.in_flight() != expected {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+#[test]
+fn test_in_flight_guard() {
+    let lifecycle = Arc::new(Lifecycle::default());
+    assert!(lifecycle.is_ready());
+
+    let first = lifecycle.track();
+    let second = lifecycle.track();
+    assert_eq!(lifecycle.in_flight(), 2);
+
+    drop(first);
+    drop(second);
+    assert_eq!(lifecycle.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_coordinator_drains_requests_within_deadline() {
+    let lifecycle = Arc::new(Lifecycle::default());
+    let (addr, stop_tx, mut servers) =
+        spawn_server(lifecycle.clone(), Duration::from_millis(100)).await;
+
+    let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+    wait_for_in_flight(&lifecycle, 1).await;
+
+    let coordinator = ShutdownCoordinator::new(
+        ShutdownSettings {
+            pre_stop_delay: Duration::from_millis(10),
+            drain_timeout: Duration::from_secs(5),
+        },
+        lifecycle.clone(),
+    );
+    let report = coordinator
+        .run(std::future::ready(()), stop_tx, &mut servers)
+        .await;
+
+    assert!(!lifecycle.is_ready());
+    assert!(report.drained);
+    assert_eq!(report.cut_off_requests, 0);
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.text().await.unwrap(), "DONE");
+}
+
+#[tokio::test]
+async fn test_coordinator_cuts_off_requests_after_deadline() {
+    let lifecycle = Arc::new(Lifecycle::default());
+    let (addr, stop_tx, mut servers) =
+        spawn_server(lifecycle.clone(), Duration::from_secs(30)).await;
+
+    let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+    wait_for_in_flight(&lifecycle, 1).await;
+
+    let coordinator = ShutdownCoordinator::new(
+        ShutdownSettings {
+            pre_stop_delay: Duration::from_millis(0),
+            drain_timeout: Duration::from_millis(100),
+        },
+        lifecycle.clone(),
+    );
+    let report = coordinator
+        .run(std::future::ready(()), stop_tx, &mut servers)
+        .await;
+
+    assert!(!report.drained);
+    assert_eq!(report.cut_off_requests, 1);
+    assert!(servers.is_empty());
+    request.abort();
+}
+
+#[tokio::test]
+async fn test_readiness_flips_before_pre_stop_delay_ends() {
+    let lifecycle = Arc::new(Lifecycle::default());
+    let (_addr, stop_tx, mut servers) = spawn_server(lifecycle.clone(), Duration::ZERO).await;
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let coordinator = ShutdownCoordinator::new(
+        ShutdownSettings {
+            pre_stop_delay: Duration::from_millis(300),
+            drain_timeout: Duration::from_secs(1),
+        },
+        lifecycle.clone(),
+    );
+    let shutdown = tokio::spawn(async move {
+        coordinator
+            .run(
+                async move {
+                    let _ = signal_rx.await;
+                },
+                stop_tx,
+                &mut servers,
+            )
+            .await
+    });
+
+    assert!(lifecycle.is_ready());
+    signal_tx.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!lifecycle.is_ready());
+    assert!(!shutdown.is_finished());
+
+    assert!(shutdown.await.unwrap().drained);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_finish_gets_what_is_left_of_the_drain_budget() {
+    let coordinator = ShutdownCoordinator::new(
+        ShutdownSettings {
+            pre_stop_delay: Duration::ZERO,
+            drain_timeout: Duration::from_secs(10),
+        },
+        Arc::new(Lifecycle::default()),
+    );
+    let report = ShutdownReport {
+        drained: true,
+        cut_off_requests: 0,
+        drain_elapsed: Duration::from_secs(7),
+    };
+
+    assert!(
+        coordinator
+            .finish(&report, tokio::time::sleep(Duration::from_secs(2)))
+            .await
+    );
+    let started = tokio::time::Instant::now();
+    assert!(!coordinator.finish(&report, std::future::pending()).await);
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+}
@@ -0,0 +1,268 @@
+use crate::server::connection::{ClientIdentity, ConnectionInfo};
+use crate::server::tls::*;
+use axum::{Router, routing::get};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, SanType,
+};
+use rustls::pki_types::{PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+struct TestPki {
+    ca: Certificate,
+    ca_key: KeyPair,
+    dir: PathBuf,
+}
+
+impl TestPki {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("excelsior-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Excelsior Test CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+        TestPki { ca, ca_key, dir }
+    }
+
+    fn issue(&self, common_name: &str, purpose: ExtendedKeyUsagePurpose) -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.subject_alt_names.push(SanType::URI(
+            format!("spiffe://excelsior/{}", common_name)
+                .try_into()
+                .unwrap(),
+        ));
+        params.extended_key_usages = vec![purpose];
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+        (cert, key)
+    }
+
+    fn write_server_cert(&self, common_name: &str) {
+        let (cert, key) = self.issue(common_name, ExtendedKeyUsagePurpose::ServerAuth);
+        std::fs::write(self.dir.join("server.pem"), cert.pem()).unwrap();
+        std::fs::write(self.dir.join("server.key"), key.serialize_pem()).unwrap();
+    }
+
+    fn settings(&self, client_auth: ClientAuth) -> TlsSettings {
+        TlsSettings {
+            cert_path: self.dir.join("server.pem"),
+            key_path: self.dir.join("server.key"),
+            client_ca_path: Some(self.dir.join("ca.pem")),
+            client_auth,
+            reload_interval: Duration::from_secs(30),
+            handshake_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+async fn whoami(identity: Option<ClientIdentity>) -> String {
+    identity
+        .and_then(|identity| identity.common_name)
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+async fn spawn_tls_server(tls: ReloadableTlsConfig) -> SocketAddr {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let app = Router::new().route("/whoami", get(whoami));
+
+    tokio::spawn(async move {
+        axum::serve(
+            TlsListener::new(tcp, tls),
+            app.into_make_service_with_connect_info::<ConnectionInfo>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+async fn https_get(
+    addr: SocketAddr,
+    pki: &TestPki,
+    client: Option<(&Certificate, &KeyPair)>,
+) -> std::io::Result<String> {
+    let mut roots = RootCertStore::empty();
+    roots.add(pki.ca.der().clone()).unwrap();
+
+    let builder =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+    let config = match client {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::try_from(key.serialize_der()).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    let tcp = TcpStream::connect(addr).await?;
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await?;
+    stream
+        .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(String::from_utf8_lossy(&response).to_string())
+}
+
+#[tokio::test]
+async fn test_h2_is_served_when_negotiated() {
+    let pki = TestPki::new("alpn-h2");
+    pki.write_server_cert("server");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::None)).unwrap();
+    let addr = spawn_tls_server(tls).await;
+
+    let mut roots = RootCertStore::empty();
+    roots.add(pki.ca.der().clone()).unwrap();
+    let mut config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    // Connection preface and an empty SETTINGS frame, answered by the server's SETTINGS.
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+        .await
+        .unwrap();
+    let mut frame_header = [0u8; 9];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut frame_header))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame_header[3], 0x04, "expected a SETTINGS frame");
+}
+
+#[test]
+fn test_client_auth_from_str() {
+    assert_eq!(
+        "required".parse::<ClientAuth>().unwrap(),
+        ClientAuth::Required
+    );
+    assert_eq!(
+        "Optional".parse::<ClientAuth>().unwrap(),
+        ClientAuth::Optional
+    );
+    assert_eq!("none".parse::<ClientAuth>().unwrap(), ClientAuth::None);
+    assert!("sometimes".parse::<ClientAuth>().is_err());
+}
+
+#[test]
+fn test_client_identity_from_der() {
+    let pki = TestPki::new("identity");
+    let (cert, _) = pki.issue("orders-service", ExtendedKeyUsagePurpose::ClientAuth);
+
+    let identity = ClientIdentity::from_der(cert.der()).unwrap();
+
+    assert_eq!(identity.common_name.as_deref(), Some("orders-service"));
+    assert!(identity.subject.contains("CN=orders-service"));
+    assert_eq!(identity.dns_names, vec!["localhost".to_string()]);
+    assert_eq!(
+        identity.uris,
+        vec!["spiffe://excelsior/orders-service".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_mtls_exposes_client_identity() {
+    let pki = TestPki::new("mtls-ok");
+    pki.write_server_cert("server");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::Required)).unwrap();
+    let addr = spawn_tls_server(tls).await;
+
+    let (cert, key) = pki.issue("orders-service", ExtendedKeyUsagePurpose::ClientAuth);
+    let response = https_get(addr, &pki, Some((&cert, &key))).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("orders-service"));
+}
+
+#[tokio::test]
+async fn test_mtls_rejects_client_without_certificate() {
+    let pki = TestPki::new("mtls-reject");
+    pki.write_server_cert("server");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::Required)).unwrap();
+    let addr = spawn_tls_server(tls).await;
+
+    let response = https_get(addr, &pki, None).await;
+
+    assert!(!matches!(response, Ok(body) if body.starts_with("HTTP/1.1 200")));
+}
+
+#[tokio::test]
+async fn test_optional_client_auth_allows_anonymous() {
+    let pki = TestPki::new("mtls-optional");
+    pki.write_server_cert("server");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::Optional)).unwrap();
+    let addr = spawn_tls_server(tls).await;
+
+    let response = https_get(addr, &pki, None).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("anonymous"));
+}
+
+#[tokio::test]
+async fn test_reload_if_changed() {
+    let pki = TestPki::new("reload");
+    pki.write_server_cert("server-v1");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::None)).unwrap();
+    let before = tls.current();
+
+    assert!(!tls.reload_if_changed().unwrap());
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    pki.write_server_cert("server-v2");
+
+    assert!(tls.reload_if_changed().unwrap());
+    assert!(!Arc::ptr_eq(&before, &tls.current()));
+    assert!(!tls.reload_if_changed().unwrap());
+}
+
+#[tokio::test]
+async fn test_reload_keeps_previous_config_on_error() {
+    let pki = TestPki::new("reload-error");
+    pki.write_server_cert("server");
+    let tls = ReloadableTlsConfig::load(pki.settings(ClientAuth::None)).unwrap();
+    let before = tls.current();
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    std::fs::write(pki.dir.join("server.key"), "not a key").unwrap();
+
+    assert!(tls.reload_if_changed().is_err());
+    assert!(Arc::ptr_eq(&before, &tls.current()));
+}
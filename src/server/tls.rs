@@ -0,0 +1,353 @@
+use crate::server::connection::{ClientIdentity, ConnectionInfo};
+use crate::utils::config_utils::env_duration;
+use anyhow::{Context, Result, anyhow};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{debug, error, info, warn};
+use x509_parser::extensions::GeneralName;
+
+/// Whether clients must present a certificate signed by the configured CA bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    None,
+    Optional,
+    Required,
+}
+
+impl std::str::FromStr for ClientAuth {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(ClientAuth::None),
+            "optional" | "request" => Ok(ClientAuth::Optional),
+            "required" | "require" | "on" => Ok(ClientAuth::Required),
+            other => Err(anyhow!("unknown client auth mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+    pub reload_interval: Duration,
+    pub handshake_timeout: Duration,
+}
+
+impl TlsSettings {
+    /// Reads the TLS settings from the environment.
+    /// Returns `None` when `TLS_CERT_PATH` is not set, so the server keeps serving plain HTTP.
+    pub fn from_env() -> Option<Self> {
+        let cert_path = std::env::var("TLS_CERT_PATH").ok()?;
+        let key_path = std::env::var("TLS_KEY_PATH").expect("TLS_KEY_PATH must be set.");
+        let client_ca_path = std::env::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from);
+
+        let client_auth = match std::env::var("TLS_CLIENT_AUTH") {
+            Ok(mode) => mode
+                .parse()
+                .expect("TLS_CLIENT_AUTH must be none, optional or required."),
+            Err(_) if client_ca_path.is_some() => ClientAuth::Required,
+            Err(_) => ClientAuth::None,
+        };
+        if client_auth != ClientAuth::None && client_ca_path.is_none() {
+            panic!("TLS_CLIENT_CA_PATH must be set when TLS_CLIENT_AUTH is enabled.");
+        }
+
+        Some(TlsSettings {
+            cert_path: PathBuf::from(cert_path),
+            key_path: PathBuf::from(key_path),
+            client_ca_path,
+            client_auth,
+            reload_interval: env_duration("TLS_RELOAD_INTERVAL").unwrap_or(Duration::from_secs(30)),
+            handshake_timeout: env_duration("TLS_HANDSHAKE_TIMEOUT")
+                .unwrap_or(Duration::from_secs(10)),
+        })
+    }
+
+    fn watched_files(&self) -> Vec<&Path> {
+        let mut files = vec![self.cert_path.as_path(), self.key_path.as_path()];
+        if let Some(ca) = &self.client_ca_path {
+            files.push(ca.as_path());
+        }
+        files
+    }
+}
+
+/// Builds a rustls server config from the PEM files referenced by the settings.
+pub fn build_server_config(settings: &TlsSettings) -> Result<ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let certs = CertificateDer::pem_file_iter(&settings.cert_path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read certificate {:?}", settings.cert_path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {:?}", settings.cert_path));
+    }
+    let key = PrivateKeyDer::from_pem_file(&settings.key_path)
+        .with_context(|| format!("failed to read private key {:?}", settings.key_path))?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match (&settings.client_ca_path, settings.client_auth) {
+        (Some(ca_path), ClientAuth::Optional | ClientAuth::Required) => {
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(load_roots(ca_path)?.into(), provider);
+            let verifier = if settings.client_auth == ClientAuth::Optional {
+                verifier.allow_unauthenticated().build()?
+            } else {
+                verifier.build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn load_roots(ca_path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path)
+        .with_context(|| format!("failed to read client CA bundle {:?}", ca_path))?
+    {
+        roots.add(cert?)?;
+    }
+    if roots.is_empty() {
+        return Err(anyhow!("no CA certificate found in {:?}", ca_path));
+    }
+    Ok(roots)
+}
+
+type FilesStamp = Vec<Option<(SystemTime, u64)>>;
+
+/// TLS configuration that is swapped in place when the files on disk change.
+#[derive(Clone)]
+pub struct ReloadableTlsConfig {
+    settings: Arc<TlsSettings>,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+    stamp: Arc<Mutex<FilesStamp>>,
+}
+
+impl ReloadableTlsConfig {
+    pub fn load(settings: TlsSettings) -> Result<Self> {
+        // Make sure a process-wide provider exists for any rustls user that relies on it.
+        let _ = CryptoProvider::install_default(rustls::crypto::ring::default_provider());
+
+        let stamp = files_stamp(&settings);
+        let config = build_server_config(&settings)?;
+        Ok(ReloadableTlsConfig {
+            settings: Arc::new(settings),
+            current: Arc::new(RwLock::new(Arc::new(config))),
+            stamp: Arc::new(Mutex::new(stamp)),
+        })
+    }
+
+    pub fn settings(&self) -> &TlsSettings {
+        &self.settings
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current
+            .read()
+            .expect("TLS config lock poisoned")
+            .clone()
+    }
+
+    /// Rebuilds the config when any watched file changed since the last load.
+    /// On failure the previous config stays active.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let stamp = files_stamp(&self.settings);
+        let mut last = self.stamp.lock().expect("TLS stamp lock poisoned");
+        if *last == stamp {
+            return Ok(false);
+        }
+
+        let config = build_server_config(&self.settings)?;
+        *self.current.write().expect("TLS config lock poisoned") = Arc::new(config);
+        *last = stamp;
+        Ok(true)
+    }
+
+    /// Polls the certificate files and reloads them whenever they change.
+    pub fn spawn_reloader(&self) -> JoinHandle<()> {
+        let tls = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tls.settings.reload_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match tls.reload_if_changed() {
+                    Ok(true) => info!("TLS certificates reloaded"),
+                    Ok(false) => {}
+                    Err(err) => error!(
+                        "Failed to reload TLS certificates, keeping the previous ones: {:#}",
+                        err
+                    ),
+                }
+            }
+        })
+    }
+}
+
+fn files_stamp(settings: &TlsSettings) -> FilesStamp {
+    settings
+        .watched_files()
+        .into_iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| Ok((meta.modified()?, meta.len())))
+                .ok()
+        })
+        .collect()
+}
+
+impl ClientIdentity {
+    /// Extracts the subject and alternative names from a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|err| anyhow!("invalid client certificate: {}", err))?;
+
+        let mut dns_names = Vec::new();
+        let mut uris = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                    GeneralName::URI(uri) => uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(ClientIdentity {
+            subject: cert.subject().to_string(),
+            common_name: cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string),
+            dns_names,
+            uris,
+            serial: cert.raw_serial_as_string(),
+        })
+    }
+}
+
+type Handshake = Option<(TlsStream<TcpStream>, ConnectionInfo)>;
+
+/// TCP listener that terminates TLS before handing connections to axum.
+/// Handshakes run concurrently so a slow client cannot stall the accept loop.
+pub struct TlsListener {
+    tcp: TcpListener,
+    tls: ReloadableTlsConfig,
+    handshakes: JoinSet<Handshake>,
+}
+
+impl TlsListener {
+    pub fn new(tcp: TcpListener, tls: ReloadableTlsConfig) -> Self {
+        TlsListener {
+            tcp,
+            tls,
+            handshakes: JoinSet::new(),
+        }
+    }
+
+    fn start_handshake(&mut self, stream: TcpStream, remote_addr: SocketAddr) {
+        let acceptor = TlsAcceptor::from(self.tls.current());
+        let timeout = self.tls.settings().handshake_timeout;
+
+        self.handshakes.spawn(async move {
+            let stream = match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    warn!("TLS handshake with {} failed: {}", remote_addr, err);
+                    return None;
+                }
+                Err(_) => {
+                    warn!("TLS handshake with {} timed out", remote_addr);
+                    return None;
+                }
+            };
+
+            let client_identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|leaf| match ClientIdentity::from_der(leaf) {
+                    Ok(identity) => Some(identity),
+                    Err(err) => {
+                        warn!(
+                            "Could not read client certificate from {}: {}",
+                            remote_addr, err
+                        );
+                        None
+                    }
+                });
+
+            Some((
+                stream,
+                ConnectionInfo {
+                    remote_addr: Some(remote_addr),
+                    client_identity,
+                },
+            ))
+        });
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = ConnectionInfo;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                accepted = self.tcp.accept() => match accepted {
+                    Ok((stream, remote_addr)) => self.start_handshake(stream, remote_addr),
+                    Err(err) => {
+                        error!("accept error: {}", err);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                },
+                Some(done) = self.handshakes.join_next(), if !self.handshakes.is_empty() => {
+                    if let Ok(Some(connection)) = done {
+                        debug!("TLS connection established with {:?}", connection.1.remote_addr);
+                        return connection;
+                    }
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(ConnectionInfo::tcp(self.tcp.local_addr()?))
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
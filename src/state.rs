@@ -1,7 +1,44 @@
 use crate::engine::db_engine::DbPool;
+use crate::middleware::load_shed::LoadShedder;
+use crate::server::shutdown::Lifecycle;
+use axum::http::Extensions;
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: Arc<DbPool>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub load_shedder: Arc<LoadShedder>,
+    /// Bearer token of the admin routes and admin-only user operations, none to refuse them all.
+    pub admin_token: Option<Arc<str>>,
+    /// Values contributed by the enabled modules, looked up by type.
+    pub extensions: Arc<Extensions>,
+}
+
+impl AppState {
+    pub fn new(db_pool: DbPool) -> Self {
+        AppState {
+            db_pool: Arc::new(db_pool),
+            lifecycle: Arc::new(Lifecycle::default()),
+            load_shedder: Arc::new(LoadShedder::default()),
+            admin_token: None,
+            extensions: Arc::new(Extensions::new()),
+        }
+    }
+
+    pub fn with_load_shedder(mut self, load_shedder: LoadShedder) -> Self {
+        self.load_shedder = Arc::new(load_shedder);
+        self
+    }
+
+    /// An empty token is no token, it would match an empty `Bearer`.
+    pub fn with_admin_token(mut self, token: Option<&str>) -> Self {
+        self.admin_token = token.filter(|token| !token.is_empty()).map(Arc::from);
+        self
+    }
+
+    pub fn with_extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = Arc::new(extensions);
+        self
+    }
 }
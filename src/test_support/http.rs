@@ -0,0 +1,22 @@
+//! Request helpers shared by the router and middleware tests.
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{HeaderName, Request};
+use axum::response::Response;
+use tower::ServiceExt;
+
+/// Answer of `app` to `GET uri`.
+pub async fn get_response(app: Router, uri: &str) -> Response {
+    app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+/// Value of the `name` header, `None` when the response has none.
+pub fn header_of(response: &Response, name: HeaderName) -> Option<&str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
@@ -0,0 +1,309 @@
+//! Minimal in-process server speaking the Redis protocol (RESP2), used by tests instead of a real Redis.
+//! It understands the handful of commands the service sends: strings with expiry, counters,
+//! MULTI/EXEC transactions and the connection handshake. Lua is not interpreted: the scripts the
+//! service loads are answered by Rust renderings of them, so tests through the stand-in cover how
+//! the service uses a script, not the script itself. The integration tests run the scripts on a
+//! real Redis.
+
+use crate::middleware::rate_limit_store::TOKEN_BUCKET_SCRIPT;
+use redis::Script;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(text) => out.extend_from_slice(format!("+{}\r\n", text).as_bytes()),
+            Reply::Error(text) => out.extend_from_slice(format!("-{}\r\n", text).as_bytes()),
+            Reply::Integer(value) => out.extend_from_slice(format!(":{}\r\n", value).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(data)) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, (Vec<u8>, Option<Instant>)>,
+    /// Loaded scripts by SHA1.
+    scripts: HashMap<String, String>,
+}
+
+impl Store {
+    fn live(&mut self, key: &str) -> Option<&mut (Vec<u8>, Option<Instant>)> {
+        let now = Instant::now();
+        if let Some((_, Some(expires))) = self.entries.get(key)
+            && *expires <= now
+        {
+            self.entries.remove(key);
+        }
+        self.entries.get_mut(key)
+    }
+
+    fn execute(&mut self, args: &[Vec<u8>]) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let arg = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+        match (name.as_str(), args.len()) {
+            ("PING", _) => Reply::Simple("PONG"),
+            ("AUTH" | "SELECT" | "CLIENT", _) => Reply::Simple("OK"),
+            ("GET", 2) => Reply::Bulk(self.live(&arg(1)).map(|(value, _)| value.clone())),
+            ("SET", n) if n >= 3 => {
+                let key = arg(1);
+                let mut expires = None;
+                let mut only_new = false;
+                let mut i = 3;
+                while i < n {
+                    match arg(i).to_ascii_uppercase().as_str() {
+                        "NX" => only_new = true,
+                        "PX" | "EX" if i + 1 < n => {
+                            let amount: u64 = arg(i + 1).parse().unwrap_or(0);
+                            let ttl = if arg(i).eq_ignore_ascii_case("PX") {
+                                Duration::from_millis(amount)
+                            } else {
+                                Duration::from_secs(amount)
+                            };
+                            expires = Some(Instant::now() + ttl);
+                            i += 1;
+                        }
+                        _ => return Reply::Error("ERR syntax error".into()),
+                    }
+                    i += 1;
+                }
+                if only_new && self.live(&key).is_some() {
+                    return Reply::Bulk(None);
+                }
+                self.entries.insert(key, (args[2].clone(), expires));
+                Reply::Simple("OK")
+            }
+            ("DEL", n) if n >= 2 => {
+                let mut removed = 0;
+                for i in 1..n {
+                    if self.live(&arg(i)).is_some() {
+                        self.entries.remove(&arg(i));
+                        removed += 1;
+                    }
+                }
+                Reply::Integer(removed)
+            }
+            ("EXISTS", 2) => Reply::Integer(self.live(&arg(1)).is_some() as i64),
+            ("INCR", 2) => {
+                let key = arg(1);
+                let entry = match self.live(&key) {
+                    Some(entry) => entry,
+                    None => self.entries.entry(key).or_insert((b"0".to_vec(), None)),
+                };
+                match String::from_utf8_lossy(&entry.0).parse::<i64>() {
+                    Ok(value) => {
+                        entry.0 = (value + 1).to_string().into_bytes();
+                        Reply::Integer(value + 1)
+                    }
+                    Err(_) => Reply::Error("ERR value is not an integer".into()),
+                }
+            }
+            ("PTTL", 2) => match self.live(&arg(1)) {
+                None => Reply::Integer(-2),
+                Some((_, None)) => Reply::Integer(-1),
+                Some((_, Some(expires))) => Reply::Integer(
+                    expires
+                        .saturating_duration_since(Instant::now())
+                        .as_millis() as i64,
+                ),
+            },
+            ("PEXPIRE", 3) => {
+                let ttl = Duration::from_millis(arg(2).parse().unwrap_or(0));
+                match self.live(&arg(1)) {
+                    Some(entry) => {
+                        entry.1 = Some(Instant::now() + ttl);
+                        Reply::Integer(1)
+                    }
+                    None => Reply::Integer(0),
+                }
+            }
+            ("SCRIPT", 3) if arg(1).eq_ignore_ascii_case("LOAD") => {
+                let source = arg(2);
+                let sha = Script::new(&source).get_hash().to_string();
+                self.scripts.insert(sha.clone(), source);
+                Reply::Bulk(Some(sha.into_bytes()))
+            }
+            ("EVALSHA", n) if n >= 3 => {
+                let Some(source) = self.scripts.get(&arg(1)).cloned() else {
+                    return Reply::Error("NOSCRIPT No matching script.".into());
+                };
+                let key_count: usize = arg(2).parse().unwrap_or(0);
+                let rest: Vec<String> = (3..n).map(arg).collect();
+                if rest.len() < key_count {
+                    return Reply::Error("ERR wrong number of keys".into());
+                }
+                let (keys, argv) = rest.split_at(key_count);
+                self.run_script(&source, keys, argv)
+            }
+            _ => Reply::Error(format!("ERR unknown command '{}'", name)),
+        }
+    }
+
+    fn run_script(&mut self, source: &str, keys: &[String], argv: &[String]) -> Reply {
+        if source == TOKEN_BUCKET_SCRIPT {
+            return self.token_bucket(&keys[0], argv);
+        }
+        Reply::Error("ERR the stand-in does not emulate this script".into())
+    }
+
+    /// What `TOKEN_BUCKET_SCRIPT` is meant to do, on the local clock rather than Redis `TIME`.
+    fn token_bucket(&mut self, key: &str, argv: &[String]) -> Reply {
+        let capacity: f64 = argv[0].parse().unwrap_or(0.0);
+        let rate: f64 = argv[1].parse().unwrap_or(0.0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1000.0;
+
+        let (mut tokens, updated) = self
+            .live(key)
+            .and_then(|(value, _)| {
+                let value = String::from_utf8_lossy(value).to_string();
+                let (tokens, updated) = value.split_once(':')?;
+                Some((tokens.parse().ok()?, updated.parse().ok()?))
+            })
+            .unwrap_or((capacity, now));
+        tokens = f64::min(capacity, tokens + f64::max(0.0, now - updated) * rate);
+
+        let allowed = tokens >= 1.0;
+        if allowed {
+            tokens -= 1.0;
+        }
+        let ttl = Duration::from_millis(((capacity - tokens) / rate).ceil() as u64 + 1);
+        self.entries.insert(
+            key.to_string(),
+            (
+                format!("{}:{}", tokens, now).into_bytes(),
+                Some(Instant::now() + ttl),
+            ),
+        );
+        Reply::Array(vec![
+            Reply::Integer(allowed as i64),
+            Reply::Bulk(Some(tokens.to_string().into_bytes())),
+        ])
+    }
+}
+
+/// Handle on a running stand-in. The server stops when the test's runtime shuts down.
+#[derive(Clone)]
+pub struct RedisStandIn {
+    addr: SocketAddr,
+    store: Arc<Mutex<Store>>,
+}
+
+impl RedisStandIn {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stand_in = RedisStandIn {
+            addr: listener.local_addr().unwrap(),
+            store: Arc::new(Mutex::new(Store::default())),
+        };
+
+        let store = stand_in.store.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, store.clone()));
+            }
+        });
+        stand_in
+    }
+
+    pub fn url(&self) -> String {
+        format!("redis://{}/", self.addr)
+    }
+
+    /// Number of keys currently stored, expired ones excluded.
+    pub fn key_count(&self) -> usize {
+        let mut store = self.store.lock().unwrap();
+        let keys: Vec<String> = store.entries.keys().cloned().collect();
+        keys.iter().filter(|key| store.live(key).is_some()).count()
+    }
+}
+
+async fn serve_connection(stream: TcpStream, store: Arc<Mutex<Store>>) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut queued: Option<Vec<Vec<Vec<u8>>>> = None;
+
+    while let Some(args) = read_command(&mut reader).await {
+        if args.is_empty() {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let reply = match (name.as_str(), queued.as_mut()) {
+            ("MULTI", None) => {
+                queued = Some(Vec::new());
+                Reply::Simple("OK")
+            }
+            ("EXEC", Some(_)) => {
+                let commands = queued.take().unwrap_or_default();
+                let mut store = store.lock().unwrap();
+                Reply::Array(commands.iter().map(|cmd| store.execute(cmd)).collect())
+            }
+            ("DISCARD", Some(_)) => {
+                queued = None;
+                Reply::Simple("OK")
+            }
+            (_, Some(commands)) => {
+                commands.push(args);
+                Reply::Simple("QUEUED")
+            }
+            _ => store.lock().unwrap().execute(&args),
+        };
+
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+        if write.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_command<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<Vec<u8>>> {
+    let header = read_line(reader).await?;
+    let count: usize = header.strip_prefix('*')?.parse().ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+        let mut data = vec![0; len + 2];
+        reader.read_exact(&mut data).await.ok()?;
+        data.truncate(len);
+        args.push(data);
+    }
+    Some(args)
+}
+
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    Some(line.trim_end().to_string())
+}
@@ -0,0 +1,65 @@
+use anyhow::{Result, anyhow};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Parses durations such as `500ms`, `30s`, `5m` or `1h`. A bare number is read as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| anyhow!("invalid duration: {}", value))?;
+
+    let seconds = match unit.trim() {
+        "" | "s" => amount,
+        "ms" => amount / 1000.0,
+        "m" => amount * 60.0,
+        "h" => amount * 3600.0,
+        other => return Err(anyhow!("unknown duration unit {} in {}", other, value)),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses sizes such as `512`, `1kb`, `10mb` or `1gb`, in multiples of 1024 bytes.
+pub fn parse_size(value: &str) -> Result<usize> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: usize = amount
+        .parse()
+        .map_err(|_| anyhow!("invalid size: {}", value))?;
+
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1024,
+        "mb" => 1024 * 1024,
+        "gb" => 1024 * 1024 * 1024,
+        other => return Err(anyhow!("unknown size unit {} in {}", other, value)),
+    };
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("size too large: {}", value))
+}
+
+/// Reads a duration from the environment, panicking on a malformed value like the other settings.
+pub fn env_duration(name: &str) -> Option<Duration> {
+    std::env::var(name).ok().map(|value| {
+        parse_duration(&value).unwrap_or_else(|_| panic!("{} must be a duration.", name))
+    })
+}
+
+/// Reads and parses a value from the environment, falling back to `default` when unset.
+pub fn env_parse<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value.", name)),
+        Err(_) => default,
+    }
+}
@@ -1,41 +1,99 @@
 use crate::database::connection::init_db;
+use crate::database::pool::{Database, stream_send_timeout_from_env};
+use crate::database::repository::repository_from_env;
+use crate::engine::cache::cached_from_env;
 use crate::engine::db_engine::DbPool;
-use crate::routes::create_routes;
+use crate::engine::replicas::replicated_from_env;
+use crate::handlers::admin_handler::admin_token_from_env;
+use crate::middleware::load_shed::LoadShedder;
+use crate::modules::Modules;
+use crate::routes::create_module_routes;
+use crate::server::listener::{BoundListener, bind_listeners_from_env};
+use crate::server::shutdown::{ShutdownCoordinator, ShutdownSettings};
+use crate::server::tls::{ReloadableTlsConfig, TlsSettings};
 use crate::state;
 use crate::utils::otel_config::{setup_tracing_with_otel, shutdown_telemetry};
 use crate::utils::un_utils::start_message;
 use dotenv::dotenv;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::TcpListener;
 use tokio::signal;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
 use tracing::{info, warn};
 
 pub async fn service_starter() {
     dotenv().ok();
     setup_tracing_with_otel();
 
-    let db_pool = init_db().await.expect("Failed to connect to DB");
-    let app_state = state::AppState {
-        db_pool: Arc::new(DbPool::Real(db_pool)),
-    };
+    let db_pool = init_db()
+        .await
+        .expect("Failed to connect to DB, retries exhausted");
+    let primary = Database::from(db_pool)
+        .with_repository(repository_from_env())
+        .with_stream_send_timeout(stream_send_timeout_from_env());
+    let modules = Modules::from_env();
+    let app_state = modules.state(
+        state::AppState::new(cached_from_env(replicated_from_env(DbPool::Real(primary))))
+            .with_load_shedder(LoadShedder::from_env())
+            .with_admin_token(admin_token_from_env().as_deref()),
+    );
+    modules
+        .start(&app_state)
+        .await
+        .expect("Failed to start modules");
+    let db_pool = app_state.db_pool.clone();
+    let coordinator =
+        ShutdownCoordinator::new(ShutdownSettings::from_env(), app_state.lifecycle.clone());
 
-    let app = create_routes(app_state);
+    let app = create_module_routes(app_state, &modules);
 
-    let pre_port = std::env::var("MS_PORT").expect("MS_PORT must be set.");
-    let port = pre_port.parse().expect("MS_PORT must be a number.");
+    let listeners = bind_listeners_from_env()
+        .await
+        .expect("Failed to bind listeners");
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let tls = TlsSettings::from_env().map(|settings| {
+        ReloadableTlsConfig::load(settings).expect("Failed to load TLS certificates")
+    });
+    let _reloader = tls.as_ref().map(ReloadableTlsConfig::spawn_reloader);
 
-    let server = TcpListener::bind(&addr).await.unwrap();
+    let locations = listeners
+        .iter()
+        .map(|listener| match (&tls, listener) {
+            (Some(_), BoundListener::Tcp(_)) => format!("https://{}", listener),
+            _ => listener.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
 
-    //info!("Excelsior listening on {}", addr); //tracing mode startup
-    start_message(addr.to_string()).await; //default mode startup
+    //info!("Excelsior listening on {}", locations); //tracing mode startup
+    start_message(locations).await; //default mode startup
 
-    axum::serve(server, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .expect("server error");
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let mut shutdown_rx = shutdown_rx.clone();
+        servers.spawn(listener.serve(app.clone(), tls.clone(), async move {
+            let _ = shutdown_rx.wait_for(|stop| *stop).await;
+        }));
+    }
+
+    let report = coordinator
+        .run(shutdown_signal(), shutdown_tx, &mut servers)
+        .await;
+    if report.drained {
+        info!("All requests drained in {:?}", report.drain_elapsed);
+    } else {
+        warn!(
+            "Drain deadline exceeded after {:?}, {} in-flight requests were cut off",
+            report.drain_elapsed, report.cut_off_requests
+        );
+    }
+
+    info!("Closing database pool...");
+    if !coordinator.finish(&report, db_pool.close()).await {
+        warn!(
+            "Shutdown deadline reached with database connections still in use, not waiting for them"
+        );
+    }
 
     info!("Shutting down OpenTelemetry...");
     shutdown_telemetry();
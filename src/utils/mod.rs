@@ -1,3 +1,4 @@
+pub mod config_utils;
 pub mod main_utils;
 pub mod otel_config;
 pub mod un_utils;
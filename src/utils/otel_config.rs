@@ -46,6 +46,8 @@ pub fn init_telemetry() -> Result<SdkTracerProvider> {
         .build();
 
     global::set_tracer_provider(tracer_provider.clone());
+    // Kept so shutdown_telemetry can flush pending spans on exit.
+    let _ = TRACER_PROVIDER.set(tracer_provider.clone());
 
     Ok(tracer_provider)
 }
@@ -0,0 +1,37 @@
+use crate::utils::config_utils::*;
+use std::time::Duration;
+
+#[test]
+fn test_parse_duration_units() {
+    assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+    assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+    assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+    assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+    assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+    assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+}
+
+#[test]
+fn test_parse_duration_invalid() {
+    assert!(parse_duration("").is_err());
+    assert!(parse_duration("ten").is_err());
+    assert!(parse_duration("10d").is_err());
+}
+
+#[test]
+fn test_parse_size_units() {
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert_eq!(parse_size("512b").unwrap(), 512);
+    assert_eq!(parse_size("1kb").unwrap(), 1024);
+    assert_eq!(parse_size("10MB").unwrap(), 10 * 1024 * 1024);
+    assert_eq!(parse_size("1gb").unwrap(), 1024 * 1024 * 1024);
+    assert!(parse_size("").is_err());
+    assert!(parse_size("1.5mb").is_err());
+    assert!(parse_size("1tb").is_err());
+}
+
+#[test]
+fn test_env_parse_default() {
+    assert_eq!(env_parse("EXCELSIOR_TEST_UNSET_VALUE", 42u32), 42);
+    assert_eq!(env_duration("EXCELSIOR_TEST_UNSET_VALUE"), None);
+}
@@ -1,3 +1,4 @@
+mod config_utils_test;
 mod main_utils_test;
 mod otel_config_test;
 mod un_utils_test;
@@ -8,7 +8,14 @@ USE DEV_ENVIRONMENT;
 CREATE TABLE `DEV_ENVIRONMENT`.`t_Users` (
     `UID` int(11) NOT NULL AUTO_INCREMENT,
     `NAME` varchar(15) NOT NULL,
-    PRIMARY KEY (`UID`)
+    `UPDATED_AT` timestamp(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3) ON UPDATE CURRENT_TIMESTAMP(3),
+    `CREATED_AT` timestamp(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3),
+    `DELETED_AT` timestamp(3) NULL DEFAULT NULL,
+    `CREATED_BY` varchar(255) NULL DEFAULT NULL,
+    `VERSION` int(11) NOT NULL DEFAULT 1,
+    `IMPORT_BATCH` char(36) NULL DEFAULT NULL,
+    PRIMARY KEY (`UID`),
+    KEY `IX_Users_IMPORT_BATCH` (`IMPORT_BATCH`)
 );
 
 INSERT INTO `DEV_ENVIRONMENT`.`t_Users` (`NAME`) VALUES
@@ -31,34 +38,167 @@ INSERT INTO `DEV_ENVIRONMENT`.`t_Users` (`NAME`) VALUES
 ('Quentin');
 
 DELIMITER $$
-CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Return_USERS()
+
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Return_USERS(
+    IN prmIncludeDeleted boolean
+)
 BEGIN
-SELECT
-    U.UID,
-    U.NAME
-FROM t_Users U;
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE prmIncludeDeleted OR U.DELETED_AT IS NULL;
+END$$
 
-#     SIGNAL SQLSTATE '45000'
-# 		SET MESSAGE_TEXT = 'TEST';
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Return_Users_Stamp(
+    IN prmIncludeDeleted boolean
+)
+BEGIN
+    SELECT
+        COUNT(*) AS USERS,
+        MAX(U.UID) AS MAX_UID,
+        CAST(COALESCE(SUM(U.VERSION), 0) AS SIGNED) AS VERSIONS,
+        MAX(U.UPDATED_AT) AS UPDATED_AT
+    FROM t_Users U
+    WHERE prmIncludeDeleted OR U.DELETED_AT IS NULL;
+END$$
+
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Return_User(
+    IN prmUid int(11)
+)
+BEGIN
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.UID = prmUid
+      AND U.DELETED_AT IS NULL;
 END$$
-DELIMITER ;
 
-DELIMITER $$
 CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Insert_User(
-    IN prmName varchar(15)
+    IN prmName varchar(15),
+    IN prmCreatedBy varchar(255)
 )
 BEGIN
-    START TRANSACTION;
-    INSERT INTO t_Users (NAME)
-    VALUES (prmName);
-    COMMIT;
-
-#    SIGNAL SQLSTATE '45000'
-#		SET MESSAGE_TEXT = 'TEST';
-end$$
-DELIMITER ;
+    INSERT INTO t_Users (NAME, CREATED_BY)
+    VALUES (prmName, prmCreatedBy);
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.UID = LAST_INSERT_ID();
+END$$
+
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Insert_Users(
+    IN prmNames json,
+    IN prmCreatedBy varchar(255)
+)
+BEGIN
+    DECLARE vBatch char(36) DEFAULT UUID();
+
+    INSERT INTO t_Users (NAME, CREATED_BY, IMPORT_BATCH)
+    SELECT J.NAME, prmCreatedBy, vBatch
+    FROM JSON_TABLE(prmNames, '$[*]' COLUMNS (
+        POS FOR ORDINALITY,
+        NAME varchar(15) PATH '$'
+    )) J
+    ORDER BY J.POS;
+
+    SELECT
+        U.UID,
+        U.NAME,
+        U.UPDATED_AT,
+        U.CREATED_AT,
+        U.DELETED_AT,
+        U.CREATED_BY,
+        U.VERSION
+    FROM t_Users U
+    WHERE U.IMPORT_BATCH = vBatch
+    ORDER BY U.UID;
+END$$
+
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Update_User(
+    IN prmUid int(11),
+    IN prmName varchar(15),
+    IN prmExpectedVersion int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET NAME = prmName,
+        UPDATED_AT = CURRENT_TIMESTAMP(3),
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NULL
+      AND (prmExpectedVersion IS NULL OR VERSION = prmExpectedVersion);
+
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT,
+            U.CREATED_AT,
+            U.DELETED_AT,
+            U.CREATED_BY,
+            U.VERSION
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+END$$
 
-#the "signal" statements above are commented out to avoid errors during execution.
+# Returns the number of deleted rows, 0 when the user is missing, already deleted or changed since.
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Delete_User(
+    IN prmUid int(11),
+    IN prmExpectedVersion int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET DELETED_AT = CURRENT_TIMESTAMP(3),
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NULL
+      AND (prmExpectedVersion IS NULL OR VERSION = prmExpectedVersion);
+    SELECT ROW_COUNT() AS DELETED;
+END$$
 
+# Returns the restored row, or nothing when the user is missing or not deleted.
+CREATE OR REPLACE PROCEDURE DEV_ENVIRONMENT.sp_Restore_User(
+    IN prmUid int(11)
+)
+BEGIN
+    UPDATE t_Users
+    SET DELETED_AT = NULL,
+        VERSION = VERSION + 1
+    WHERE UID = prmUid
+      AND DELETED_AT IS NOT NULL;
 
+    IF ROW_COUNT() > 0 THEN
+        SELECT
+            U.UID,
+            U.NAME,
+            U.UPDATED_AT,
+            U.CREATED_AT,
+            U.DELETED_AT,
+            U.CREATED_BY,
+            U.VERSION
+        FROM t_Users U
+        WHERE U.UID = prmUid;
+    END IF;
+END$$
 
+DELIMITER ;
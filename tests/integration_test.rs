@@ -2,8 +2,13 @@ use axum::http::StatusCode;
 use dotenv::dotenv;
 use serial_test::serial;
 use sqlx::MySqlPool;
-use std::sync::Arc;
 // Import the ms1 crate and its modules
+use futures::{StreamExt, TryStreamExt};
+use ms1::database::redis_connection::RedisConnection;
+use ms1::engine::db_engine::DatabaseExecutor;
+use ms1::engine::transaction::in_transaction;
+use ms1::middleware::rate_limit::RateLimitPolicy;
+use ms1::middleware::rate_limit_store::{RateLimitStore, RedisRateLimitStore};
 use ms1::utils::main_utils::service_starter;
 use ms1::utils::otel_config::{setup_tracing_with_otel, shutdown_telemetry};
 use ms1::{database, engine::db_engine::DbPool, routes, state::AppState};
@@ -30,9 +35,18 @@ fn setup_test_env() {
 
 // Helper function to create a test database connection
 async fn create_test_db_pool() -> MySqlPool {
-    database::connection::init_db()
-        .await
-        .expect("Failed to connect to test database")
+    // A single attempt, a missing test database should fail the tests at once.
+    let retry = database::connection::RetrySettings {
+        attempts: 1,
+        ..Default::default()
+    };
+    database::connection::connect(
+        &database::connection::database_url(),
+        &database::connection::PoolSettings::from_env("DB_POOL"),
+        &retry,
+    )
+    .await
+    .expect("Failed to connect to test database")
 }
 
 // Helper function to create a test app instance
@@ -46,9 +60,7 @@ async fn spawn_app() -> String {
     // Set up a test database connection using the same method as the main app
     let pool = create_test_db_pool().await;
 
-    let app_state = AppState {
-        db_pool: Arc::new(DbPool::Real(pool)),
-    };
+    let app_state = AppState::new(DbPool::Real(pool.into()));
 
     // Build the application with routes
     let app = routes::create_routes(app_state);
@@ -175,10 +187,128 @@ async fn test_create_user() {
         StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::UNAUTHORIZED),
         StatusCode::CREATED
     );
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    let created: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(created["name"], "Test User");
+    assert_eq!(location, format!("/users/{}", created["uid"]));
 
     cleanup_test_data(&pool).await;
 }
 
+#[tokio::test]
+async fn test_transaction_rolls_back_on_error() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    let db_pool = DbPool::Real(pool.clone().into());
+    cleanup_test_data(&pool).await;
+
+    let result: anyhow::Result<()> = in_transaction(&db_pool, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("Test User".to_string(), None)
+                .await?;
+            Err(anyhow::anyhow!("abort"))
+        })
+    })
+    .await;
+    assert!(result.is_err());
+    let users = db_pool.execute_get_users().await.unwrap();
+    assert!(users.iter().all(|user| user.name != "Test User"));
+
+    in_transaction(&db_pool, |tx| {
+        Box::pin(async move {
+            tx.execute_create_user("Test User".to_string(), None)
+                .await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let users = db_pool.execute_get_users().await.unwrap();
+    assert!(users.iter().any(|user| user.name == "Test User"));
+
+    cleanup_test_data(&pool).await;
+}
+
+#[tokio::test]
+async fn test_plain_queries_repository() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    let db_pool = DbPool::Real(
+        database::pool::Database::from(pool.clone())
+            .with_repository(database::repository::repository("queries").unwrap()),
+    );
+    cleanup_test_data(&pool).await;
+
+    db_pool
+        .execute_create_user("Test User".to_string(), None)
+        .await
+        .unwrap();
+    let users = db_pool.execute_get_users().await.unwrap();
+    let created = users
+        .iter()
+        .find(|user| user.name == "Test User")
+        .expect("created user listed");
+    assert!(created.updated_at.is_some());
+
+    let fetched = db_pool
+        .execute_get_user(created.uid)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fetched.name, "Test User");
+    // A stale version changes nothing.
+    let stale = fetched.version + 1;
+    assert!(
+        !db_pool
+            .execute_delete_user(created.uid, Some(stale))
+            .await
+            .unwrap()
+    );
+    assert!(
+        db_pool
+            .execute_delete_user(created.uid, Some(fetched.version))
+            .await
+            .unwrap()
+    );
+    assert!(
+        db_pool
+            .execute_get_user(created.uid)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_create_users_reads_back_its_batch_in_order() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    for kind in ["procedures", "queries"] {
+        let db_pool = DbPool::Real(
+            database::pool::Database::from(pool.clone())
+                .with_repository(database::repository::repository(kind).unwrap()),
+        );
+        let names: Vec<String> = (1..=3).map(|index| format!("Import {}", index)).collect();
+
+        let created = db_pool
+            .execute_create_users(names.clone(), Some("importer".to_string()))
+            .await
+            .unwrap();
+        let created_names: Vec<String> = created.iter().map(|user| user.name.clone()).collect();
+        assert_eq!(created_names, names, "{}", kind);
+        assert!(
+            created
+                .iter()
+                .all(|user| user.created_by.as_deref() == Some("importer"))
+        );
+
+        sqlx::query("DELETE FROM t_Users WHERE NAME LIKE 'Import %'")
+            .execute(&pool)
+            .await
+            .expect("Failed to clean up imported users");
+    }
+}
+
 #[tokio::test]
 async fn test_get_params() {
     let address = spawn_app().await;
@@ -365,3 +495,197 @@ async fn test_service_starter_graceful_shutdown() {
     );
     println!("Service starter graceful shutdown test: Server stopped successfully");
 }
+
+#[tokio::test]
+async fn test_stream_users_matches_get_users() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    for kind in ["procedures", "queries"] {
+        let db_pool = DbPool::Real(
+            database::pool::Database::from(pool.clone())
+                .with_repository(database::repository::repository(kind).unwrap()),
+        );
+        cleanup_test_data(&pool).await;
+        let names = (0..200)
+            .map(|index| format!("Streamed {}", index))
+            .collect();
+        db_pool.execute_create_users(names, None).await.unwrap();
+
+        let streamed: Vec<_> = db_pool
+            .stream_users(false)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let collected = db_pool.execute_get_users().await.unwrap();
+        let uids = |users: &[ms1::domain::database::User]| {
+            users.iter().map(|user| user.uid).collect::<Vec<_>>()
+        };
+        assert_eq!(uids(&streamed), uids(&collected), "{} repository", kind);
+    }
+}
+
+#[tokio::test]
+async fn test_users_stamp_matches_the_listed_users() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    for kind in ["procedures", "queries"] {
+        let db_pool = DbPool::Real(
+            database::pool::Database::from(pool.clone())
+                .with_repository(database::repository::repository(kind).unwrap()),
+        );
+        for include_deleted in [false, true] {
+            let users = if include_deleted {
+                db_pool.execute_get_all_users().await.unwrap()
+            } else {
+                db_pool.execute_get_users().await.unwrap()
+            };
+            let stamp = db_pool
+                .execute_get_users_stamp(include_deleted)
+                .await
+                .unwrap();
+            assert_eq!(
+                stamp,
+                ms1::domain::database::UsersStamp::of_users(&users),
+                "{} repository",
+                kind
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_stalled_stream_releases_its_connection() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    let database = database::pool::Database::from(pool.clone())
+        .with_stream_send_timeout(Duration::from_millis(100));
+    let names = (0..ms1::engine::db_engine::STREAM_BUFFER + 10)
+        .map(|index| format!("Stalled {}", index))
+        .collect();
+    database.execute_create_users(names, None).await.unwrap();
+
+    let stream = database.stream_users(false).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(database.stats().in_use, 0);
+    let read: Vec<anyhow::Result<_>> = stream.collect().await;
+    assert!(read.last().unwrap().is_err());
+
+    sqlx::query("DELETE FROM t_Users WHERE NAME LIKE 'Stalled %'")
+        .execute(&pool)
+        .await
+        .expect("Failed to clean up streamed users");
+}
+
+#[tokio::test]
+async fn test_soft_delete_and_restore() {
+    setup_test_env();
+    let pool = create_test_db_pool().await;
+    for kind in ["procedures", "queries"] {
+        let db_pool = DbPool::Real(
+            database::pool::Database::from(pool.clone())
+                .with_repository(database::repository::repository(kind).unwrap()),
+        );
+        cleanup_test_data(&pool).await;
+        let created = db_pool
+            .execute_create_user("Test User".to_string(), Some("tester".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(created.created_by.as_deref(), Some("tester"), "{}", kind);
+        assert!(created.created_at.is_some() && created.deleted_at.is_none());
+
+        assert!(
+            db_pool
+                .execute_delete_user(created.uid, None)
+                .await
+                .unwrap()
+        );
+        // Already deleted.
+        assert!(
+            !db_pool
+                .execute_delete_user(created.uid, None)
+                .await
+                .unwrap()
+        );
+        assert!(
+            db_pool
+                .execute_get_user(created.uid)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let listed = |users: Vec<ms1::domain::database::User>| {
+            users.into_iter().find(|user| user.uid == created.uid)
+        };
+        assert!(listed(db_pool.execute_get_users().await.unwrap()).is_none());
+        let deleted = listed(db_pool.execute_get_all_users().await.unwrap()).unwrap();
+        assert!(deleted.deleted_at.is_some(), "{}", kind);
+
+        let restored = db_pool
+            .execute_restore_user(created.uid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert_eq!(restored.created_by.as_deref(), Some("tester"));
+        assert!(
+            db_pool
+                .execute_restore_user(created.uid)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            db_pool
+                .execute_get_user(created.uid)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+    cleanup_test_data(&pool).await;
+}
+
+// The unit tests answer TOKEN_BUCKET_SCRIPT with a Rust stand-in, this runs the Lua itself.
+#[tokio::test]
+async fn test_redis_rate_limit_script_is_a_token_bucket() {
+    setup_test_env();
+    let redis = RedisConnection::from_env()
+        .expect("REDIS_URL or REDIS_HOST must be set for the integration tests");
+    let store = RedisRateLimitStore::new(redis.clone());
+    let policy: RateLimitPolicy = "4/400ms".parse().unwrap();
+    let key = format!(
+        "integration-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+
+    let first = store.acquire(&key, &policy).await.unwrap();
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 3);
+    for _ in 0..3 {
+        assert!(store.acquire(&key, &policy).await.unwrap().allowed);
+    }
+    let denied = store.acquire(&key, &policy).await.unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    assert!(denied.retry_after <= Duration::from_millis(100));
+
+    // Refilled by about one token, not reset like a fixed window.
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(store.acquire(&key, &policy).await.unwrap().allowed);
+    assert!(!store.acquire(&key, &policy).await.unwrap().allowed);
+
+    // The bucket expires once full again.
+    tokio::time::sleep(Duration::from_millis(450)).await;
+    let mut connection = redis.connection().await.unwrap();
+    let exists: i64 = redis::cmd("EXISTS")
+        .arg(format!("ratelimit:{}", key))
+        .query_async(&mut connection)
+        .await
+        .unwrap();
+    assert_eq!(exists, 0);
+}